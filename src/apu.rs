@@ -0,0 +1,121 @@
+use io::IoDevice;
+use vgm::VgmLog;
+use model::Model;
+
+pub const APU_REGISTERS_START: u16  = 0xFF10;
+pub const APU_REGISTERS_END: u16    = 0xFF3F;
+
+const NR11_REG: u16 = 0xFF11;
+const NR21_REG: u16 = 0xFF16;
+const NR31_REG: u16 = 0xFF1B;
+const NR41_REG: u16 = 0xFF20;
+const NR51_REG: u16 = 0xFF25;
+const NR52_REG: u16 = 0xFF26;
+
+// The APU's registers. Sound isn't emulated yet, but writes can be logged.
+pub struct Apu {
+    registers: [u8; 0x30],
+    cycles: u64,    // At normal speed, for the audio log
+    audio_log: Option<VgmLog>,
+    model: Model
+}
+
+impl Apu {
+    // The APU is off at power on, until the boot ROM turns it on.
+    pub fn new(model: Model) -> Apu {
+        Apu {
+            registers: [0u8; 0x30],
+            cycles: 0,
+            audio_log: None,
+            model: model
+        }
+    }
+
+    fn powered(&self) -> bool {
+        self.registers[(NR52_REG - APU_REGISTERS_START) as usize] & 0x80 != 0
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+    }
+
+    pub fn start_audio_log(&mut self) {
+        self.audio_log = Some(VgmLog::new());
+    }
+
+    pub fn stop_audio_log(&mut self) -> Option<VgmLog> {
+        self.audio_log.take()
+    }
+}
+
+impl IoDevice for Apu {
+    fn read_io(&self, addr: u16) -> u8 {
+        self.registers[(addr - APU_REGISTERS_START) as usize]
+    }
+
+    fn write_io(&mut self, addr: u16, value: u8) -> u8 {
+        if let Some(ref mut log) = self.audio_log {
+            log.record(self.cycles, addr, value);
+        }
+        let index = (addr - APU_REGISTERS_START) as usize;
+        match addr {
+            // Turning the APU off clears its registers, but not wave RAM.
+            NR52_REG => {
+                if value & 0x80 == 0 {
+                    for register in &mut self.registers[..=index] {
+                        *register = 0;
+                    }
+                } else {
+                    // The low bits show which channels are playing, so are read only.
+                    self.registers[index] |= 0x80;
+                }
+            },
+            // The registers ignore writes while the APU is off, except that the DMG can still
+            // load the length timers.
+            APU_REGISTERS_START...NR51_REG if !self.powered() => {
+                if !self.model.is_cgb() {
+                    match addr {
+                        NR11_REG | NR21_REG | NR41_REG => self.registers[index] = value & 0x3F,
+                        NR31_REG => self.registers[index] = value,
+                        _ => {}
+                    }
+                }
+            },
+            _ => self.registers[index] = value
+        }
+        0
+    }
+
+    fn poke_io(&mut self, addr: u16, value: u8) {
+        self.registers[(addr - APU_REGISTERS_START) as usize] = value;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn power_off() {
+        for &model in &[Model::Dmg, Model::Cgb] {
+            let mut apu = Apu::new(model);
+            apu.write_io(NR52_REG, 0x80);
+            apu.write_io(0xFF12, 0xF3);
+            apu.write_io(0xFF30, 0x12);
+            apu.write_io(NR52_REG, 0x00);
+            assert_eq!(apu.read_io(0xFF12), 0x00);
+            assert_eq!(apu.read_io(NR52_REG), 0x00);
+            assert_eq!(apu.read_io(0xFF30), 0x12);
+
+            apu.write_io(0xFF12, 0xF3);
+            apu.write_io(NR11_REG, 0xFF);
+            assert_eq!(apu.read_io(0xFF12), 0x00);
+            let length = if model == Model::Dmg { 0x3F } else { 0x00 };
+            assert_eq!(apu.read_io(NR11_REG), length);
+
+            apu.write_io(NR52_REG, 0x80);
+            apu.write_io(0xFF12, 0xF3);
+            assert_eq!(apu.read_io(0xFF12), 0xF3);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+/*
+    The boot ROM, which is mapped over the start of the cartridge until it writes to 0xFF50. The
+    DMG, MGB and SGB boot ROMs are 256 bytes at 0x0000-0x00FF. The CGB boot ROM is 2304 bytes,
+    mapped at 0x0000-0x00FF and 0x0200-0x08FF so that the cartridge header shows through at
+    0x0100-0x01FF. Its contents at 0x0100-0x01FF are never used.
+*/
+
+use io::IoDevice;
+use model::Model;
+
+pub const BOOT_ROM_REG: u16 = 0xFF50;
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+pub struct BootRom {
+    rom: Vec<u8>,
+    mapped: bool
+}
+
+impl BootRom {
+    pub fn new() -> BootRom {
+        BootRom {
+            rom: Vec::new(),
+            mapped: false
+        }
+    }
+
+    // Map a boot ROM, which must be the right size for the model.
+    pub fn load(&mut self, rom: Vec<u8>, model: Model) -> Result<(), String> {
+        let (kind, size) = if model.is_cgb() { ("CGB", CGB_BOOT_ROM_SIZE) } else { ("DMG", DMG_BOOT_ROM_SIZE) };
+        if rom.len() != size {
+            return Err(format!("{} boot ROMs are {} bytes, but this one is {}", kind, size, rom.len()));
+        }
+        self.rom = rom;
+        self.mapped = true;
+        Ok(())
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.mapped
+    }
+
+    // Whether an address reads from the boot ROM rather than the cartridge.
+    pub fn maps(&self, addr: u16) -> bool {
+        self.mapped && match addr {
+            0x0000...0x00FF => true,
+            0x0200...0x08FF => self.rom.len() == CGB_BOOT_ROM_SIZE,
+            _ => false
+        }
+    }
+
+    pub fn read_u8(&self, addr: u16) -> u8 {
+        self.rom[addr as usize]
+    }
+
+    pub fn write_u8(&mut self, addr: u16, value: u8) {
+        self.rom[addr as usize] = value;
+    }
+}
+
+// Writing 1 to bit 0 of 0xFF50 unmaps the boot ROM until the next power cycle.
+impl IoDevice for BootRom {
+    fn read_io(&self, _addr: u16) -> u8 {
+        0xFF
+    }
+
+    fn write_io(&mut self, _addr: u16, value: u8) -> u8 {
+        if value & 0x01 != 0 && self.mapped {
+            println!("status: Finished booting");
+            self.mapped = false;
+        }
+        0
+    }
+
+    fn poke_io(&mut self, _addr: u16, value: u8) {
+        if value & 0x01 != 0 {
+            self.mapped = false;
+        }
+    }
+}
+
+pub static DMG_BOOT_ROM: [u8; DMG_BOOT_ROM_SIZE] = [
+    0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
+    0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
+    0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1A, 0xCD, 0x95, 0x00, 0xCD, 0x96, 0x00, 0x13, 0x7B,
+    0xFE, 0x34, 0x20, 0xF3, 0x11, 0xD8, 0x00, 0x06, 0x08, 0x1A, 0x13, 0x22, 0x23, 0x05, 0x20, 0xF9,
+    0x3E, 0x19, 0xEA, 0x10, 0x99, 0x21, 0x2F, 0x99, 0x0E, 0x0C, 0x3D, 0x28, 0x08, 0x32, 0x0D, 0x20,
+    0xF9, 0x2E, 0x0F, 0x18, 0xF3, 0x67, 0x3E, 0x64, 0x57, 0xE0, 0x42, 0x3E, 0x91, 0xE0, 0x40, 0x04,
+    0x1E, 0x02, 0x0E, 0x0C, 0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, 0x0D, 0x20, 0xF7, 0x1D, 0x20, 0xF2,
+    0x0E, 0x13, 0x24, 0x7C, 0x1E, 0x83, 0xFE, 0x62, 0x28, 0x06, 0x1E, 0xC1, 0xFE, 0x64, 0x20, 0x06,
+    0x7B, 0xE2, 0x0C, 0x3E, 0x87, 0xF2, 0xF0, 0x42, 0x90, 0xE0, 0x42, 0x15, 0x20, 0xD2, 0x05, 0x20,
+    0x4F, 0x16, 0x20, 0x18, 0xCB, 0x4F, 0x06, 0x04, 0xC5, 0xCB, 0x11, 0x17, 0xC1, 0xCB, 0x11, 0x17,
+    0x05, 0x20, 0xF5, 0x22, 0x23, 0x22, 0x23, 0xC9, 0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B,
+    0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
+    0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E, 0x3c, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x4C,
+    0x21, 0x04, 0x01, 0x11, 0xA8, 0x00, 0x1A, 0x13, 0xBE, 0x20, 0xFE, 0x23, 0x7D, 0xFE, 0x34, 0x20,
+    0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x20, 0xFE, 0x3E, 0x01, 0xE0, 0x50];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mapping() {
+        let mut boot_rom = BootRom::new();
+        assert!(!boot_rom.maps(0x0000));
+        assert!(boot_rom.load(vec![0; DMG_BOOT_ROM_SIZE], Model::Cgb).is_err());
+        boot_rom.load(vec![0; CGB_BOOT_ROM_SIZE], Model::Cgb).unwrap();
+        assert!(boot_rom.maps(0x00FF));
+        assert!(!boot_rom.maps(0x0100));
+        assert!(boot_rom.maps(0x08FF));
+
+        boot_rom.load(DMG_BOOT_ROM.to_vec(), Model::Dmg).unwrap();
+        assert!(!boot_rom.maps(0x0200));
+        // Only bit 0 unmaps it.
+        boot_rom.write_io(BOOT_ROM_REG, 0xFE);
+        assert!(boot_rom.maps(0x0000));
+        boot_rom.write_io(BOOT_ROM_REG, 0x01);
+        assert!(!boot_rom.maps(0x0000));
+    }
+}
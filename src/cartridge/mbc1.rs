@@ -1,4 +1,4 @@
-use cartridge::MemoryBankController;
+use cartridge::{MemoryBankController, rom_offset, ram_offset};
 
 pub struct MBC1 {
     rom: Vec<u8>,
@@ -13,8 +13,13 @@ impl MemoryBankController for MBC1 {
     fn read_u8(&self, addr: u16) -> u8 {
         match addr {
             0x0000...0x3FFF => self.rom[addr as usize],
-            0x4000...0x7FFF => self.rom[self.rom_bank_addr(addr - 0x4000, self.rom_bank)],
-            0xA000...0xBFFF => self.ram[self.ram_bank_addr(addr - 0xA000, self.ram_bank)],
+            // Banks past the end of the ROM wrap around, as the chip ignores the high bits. The
+            // count is a power of two, except for a truncated image, which reads as open bus.
+            0x4000...0x7FFF => {
+                let bank = self.rom_bank as usize & (self.rom_bank_count() - 1);
+                *self.rom.get(rom_offset(bank, addr)).unwrap_or(&0xFF)
+            },
+            0xA000...0xBFFF => self.ram[ram_offset(self.ram_bank as usize, addr)],
             _ => panic!("ERROR: Attempting to read from invalid address: 0x{:X}", addr)
         }
     }
@@ -63,11 +68,47 @@ impl MemoryBankController for MBC1 {
             }
             // RAM.
             0xA000...0xBFFF => {
-                self.ram[self.ram_bank_addr(addr - 0xA000, self.ram_bank)] = value;
+                self.ram[ram_offset(self.ram_bank as usize, addr)] = value;
             },
             _ => panic!("ERROR: Attempting to write to invalid address: 0x{:X}", addr)
         }
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank as usize
+    }
+
+    fn peek(&self, bank: usize, addr: u16) -> u8 {
+        let byte = match addr {
+            0x0000...0x7FFF => self.rom.get(rom_offset(bank, addr)),
+            0xA000...0xBFFF => self.ram.get(ram_offset(bank, addr)),
+            _ => None
+        };
+        *byte.unwrap_or(&0xFF)
+    }
+
+    fn poke(&mut self, bank: usize, addr: u16, value: u8) {
+        let byte = match addr {
+            0x0000...0x7FFF => self.rom.get_mut(rom_offset(bank, addr)),
+            0xA000...0xBFFF => self.ram.get_mut(ram_offset(bank, addr)),
+            _ => None
+        };
+        if let Some(byte) = byte {
+            *byte = value;
+        }
+    }
 }
 
 impl MBC1 {
@@ -81,16 +122,35 @@ impl MBC1 {
             ram_bank: 0
         }
     }
+}
 
-    #[inline]
-    fn rom_bank_addr(&self, rel_addr: u16, bank: u8) -> usize {
-        // bank * 0x4000 (rom bank size).
-        (bank as usize) * 0x2000 + (rel_addr as usize)
-    }
+#[cfg(test)]
+mod test {
+    use cartridge::MemoryBankController;
+    use super::MBC1;
 
-    #[inline]
-    fn ram_bank_addr(&self, rel_addr: u16, bank: u8) -> usize {
-        // bank * 0x2000 (ram bank size).
-        (bank as usize) * 0x2000 + (rel_addr as usize)
+    #[test]
+    fn switchable_rom_banks() {
+        // Four 16KB banks, each filled with its own number.
+        let mut rom = vec![0; 0x10000];
+        for bank in 0..4 {
+            for byte in rom[bank * 0x4000..(bank + 1) * 0x4000].iter_mut() {
+                *byte = bank as u8;
+            }
+        }
+        let mut mbc = MBC1::new(&rom);
+        assert_eq!(mbc.read_u8(0x4010), 1);
+        mbc.write_u8(0x2000, 2);
+        assert_eq!(mbc.read_u8(0x4010), 2);
+        assert_eq!(mbc.read_u8(0x7FFF), 2);
+        mbc.write_u8(0x2000, 3);
+        assert_eq!(mbc.read_u8(0x4010), 3);
+        assert_eq!(mbc.read_u8(0x7FFF), 3);
+
+        // The bank number wraps around to the size of the ROM.
+        mbc.write_u8(0x2000, 0x1F);
+        assert_eq!(mbc.read_u8(0x4010), 3);
+        mbc.write_u8(0x2000, 0x06);
+        assert_eq!(mbc.read_u8(0x4010), 2);
     }
-}
\ No newline at end of file
+}
@@ -7,11 +7,70 @@ use std::io::Read;
 use cartridge::rom::ROM;
 use cartridge::mbc1::MBC1;
 
-pub trait MemoryBankController {
+// Size of a switchable ROM bank. Bank 0 is fixed at 0x0000-0x3FFF and the selected bank is mapped
+// at 0x4000-0x7FFF.
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+// Size of a cartridge RAM bank, mapped at 0xA000-0xBFFF.
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+// Send so that the emulator can be moved to another thread.
+pub trait MemoryBankController: Send {
     fn read_u8(&self, addr: u16) -> u8;
     fn write_u8(&mut self, addr: u16, data: u8);
+
+    // The complete ROM image.
+    fn rom(&self) -> &[u8];
+
+    // The ROM bank currently mapped at 0x4000-0x7FFF.
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        (self.rom().len() + ROM_BANK_SIZE - 1) / ROM_BANK_SIZE
+    }
+
+    // Whether there is no MBC, so writes to ROM do nothing.
+    fn is_rom_only(&self) -> bool {
+        false
+    }
+
+    // All banks of cartridge RAM, which is empty if there is none.
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // The RAM bank currently mapped at 0xA000-0xBFFF.
+    fn ram_bank(&self) -> usize {
+        0
+    }
+
+    // Access any bank for a debugger, without side effects or logging. ROM banks are addressed
+    // through 0x0000-0x7FFF, so bank 2 at 0x4000 and 0x0000 are the same byte, and RAM banks
+    // through 0xA000-0xBFFF. Addresses outside of the cartridge read 0xFF and ignore pokes.
+    fn peek(&self, bank: usize, addr: u16) -> u8;
+    fn poke(&mut self, bank: usize, addr: u16, value: u8);
+}
+
+// The offset of a bank:address pair into a ROM image.
+fn rom_offset(bank: usize, addr: u16) -> usize {
+    bank * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1))
+}
+
+// The offset of a bank:address pair into cartridge RAM.
+fn ram_offset(bank: usize, addr: u16) -> usize {
+    bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))
 }
 
+// The logo the boot ROM scrolls down the screen, which must match the cartridge header at
+// 0x0104-0x0133 or the boot ROM locks up.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E
+];
+
 pub struct Cartridge {
     pub title: String,
     pub mbc: Box<MemoryBankController>,
@@ -40,14 +99,24 @@ impl Cartridge {
             return Err("Unable to read cartridge file".to_string());
         }
 
-        // Verify the Nintendo Logo bytes
-        // TODO
+        Cartridge::from_rom(contents)
+    }
+
+    // Create a cartridge from a ROM image in memory, e.g. one produced by the assembler. Images
+    // smaller than 32 KB are padded with zeros.
+    pub fn from_rom(mut contents: Vec<u8>) -> Result<Cartridge, String> {
+        if contents.len() < 0x8000 {
+            contents.resize(0x8000, 0);
+        }
+
+        // The Nintendo logo is checked by the boot ROM, see logo_valid.
 
         // Grab the game title from bytes 0134-0143
-        let title = String::from_utf8(contents[0x0134..0x0143].to_vec()).unwrap();
+        let title = String::from_utf8_lossy(&contents[0x0134..0x0143]).into_owned();
 
         // Calculate ROM size by shifting 32k by the value at 0x148
-        let rom_size = (32 * 1024) << contents[0x148];
+        let rom_size = (32usize * 1024).checked_shl(contents[0x148] as u32)
+            .ok_or(format!("Invalid ROM size 0x{:02X}", contents[0x148]))?;
         println!("status: ROM size is {} KB", rom_size / 1024);
         println!("status: Actual ROM size is {} KB", contents.len() / 1024);
 
@@ -64,7 +133,7 @@ impl Cartridge {
         let mbc: Box<MemoryBankController> = match cartridge_type {
             0x0 | 0x8 | 0x9  => Box::new(ROM::new(&contents[0..0x8000])),
             0x1 | 0x2 | 0x3 => Box::new(MBC1::new(contents.as_slice())),
-            _ => panic!("ERROR: unknown cartridge type")
+            _ => return Err(format!("Unsupported cartridge type 0x{:02X}", cartridge_type))
         };
 
         // Return a new cartridge object
@@ -83,7 +152,77 @@ impl Cartridge {
         self.mbc.write_u8(addr, value);
     }
 
+    pub fn rom(&self) -> &[u8] {
+        self.mbc.rom()
+    }
+
+    pub fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    pub fn is_rom_only(&self) -> bool {
+        self.mbc.is_rom_only()
+    }
+
+    pub fn ram_bank(&self) -> usize {
+        self.mbc.ram_bank()
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        self.mbc.ram_mut()
+    }
+
+    pub fn peek(&self, bank: usize, addr: u16) -> u8 {
+        self.mbc.peek(bank, addr)
+    }
+
+    pub fn poke(&mut self, bank: usize, addr: u16, value: u8) {
+        self.mbc.poke(bank, addr, value);
+    }
+
     pub fn is_cgb_enabled(&self) -> bool {
         self.cgb_enabled
     }
+
+    // Whether the header has the logo the boot ROM checks for. The CGB boot ROM only checks the
+    // first half of it.
+    pub fn logo_valid(&self, cgb: bool) -> bool {
+        let len = if cgb { 0x18 } else { 0x30 };
+        self.rom()[0x0104..0x0104 + len] == NINTENDO_LOGO[..len]
+    }
+
+    // The 16 bytes from 0x0134-0x0143, which on CGB cartridges include the manufacturer code and
+    // CGB flag.
+    pub fn title_bytes(&self) -> &[u8] {
+        &self.rom()[0x0134..0x0144]
+    }
+
+    // Whether the header's licensee code is Nintendo, either the old code at 0x014B or the new
+    // code at 0x0144-0x0145 if the old code is 0x33.
+    pub fn is_nintendo_licensee(&self) -> bool {
+        let rom = self.rom();
+        match rom[0x014B] {
+            0x01 => true,
+            0x33 => &rom[0x0144..0x0146] == b"01",
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bad_headers() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x138].copy_from_slice(&[b'G', b'A', 0xFF, b'E']);
+        assert_eq!(Cartridge::from_rom(rom.clone()).unwrap().title, "GA\u{FFFD}E\0\0\0\0\0\0\0\0\0\0\0");
+
+        rom[0x147] = 0x1B;
+        assert_eq!(Cartridge::from_rom(rom.clone()).err(), Some("Unsupported cartridge type 0x1B".to_string()));
+        rom[0x147] = 0x00;
+        rom[0x148] = 0xFF;
+        assert!(Cartridge::from_rom(rom).is_err());
+    }
 }
@@ -1,4 +1,4 @@
-use cartridge::MemoryBankController;
+use cartridge::{MemoryBankController, rom_offset};
 
 pub struct ROM {
     rom: [u8; 0x8000]
@@ -6,12 +6,39 @@ pub struct ROM {
 
 impl MemoryBankController for ROM {
     fn read_u8(&self, addr: u16) -> u8 {
-        self.rom[addr as usize]
+        match addr {
+            0x0000...0x7FFF => self.rom[addr as usize],
+            // There is no cartridge RAM, so nothing drives the bus.
+            _ => 0xFF
+        }
     }
 
     fn write_u8(&mut self, addr: u16, value: u8) {
         println!("WARNING: Writing to a read-only memory region. Addr: {:04X} - Value: {:02X}", addr, value);
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn is_rom_only(&self) -> bool {
+        true
+    }
+
+    fn peek(&self, bank: usize, addr: u16) -> u8 {
+        match addr {
+            0x0000...0x7FFF => *self.rom.get(rom_offset(bank, addr)).unwrap_or(&0xFF),
+            _ => 0xFF
+        }
+    }
+
+    fn poke(&mut self, bank: usize, addr: u16, value: u8) {
+        if let 0x0000...0x7FFF = addr {
+            if let Some(byte) = self.rom.get_mut(rom_offset(bank, addr)) {
+                *byte = value;
+            }
+        }
+    }
 }
 
 impl ROM {
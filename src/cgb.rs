@@ -0,0 +1,104 @@
+use io::IoDevice;
+
+// Registers
+pub const CGB_KEY0_REG: u16                 = 0xFF4C;
+pub const CGB_DOUBLE_SPEED_PREP_REG: u16    = 0xFF4D;
+pub const CGB_HDMA_SOURCE_HIGH_REG: u16     = 0xFF51;
+pub const CGB_HDMA_SOURCE_LOW_REG: u16      = 0xFF52;
+pub const CGB_HDMA_DEST_HIGH_REG: u16       = 0xFF53;
+pub const CGB_HDMA_DEST_LOW_REG: u16        = 0xFF54;
+pub const CGB_HDMA_REG: u16                 = 0xFF55;
+pub const CGB_INFRARED_PORT_REG: u16        = 0xFF56;
+pub const CGB_WRAM_BANK_SELECT: u16         = 0xFF70;
+
+// Gameboy Colour specific registers, which are only mapped in CGB mode.
+pub struct CgbRegisters {
+    key0: u8,           // Written by the boot ROM, to pick CGB or DMG compatibility mode
+    double_speed: bool,
+    double_speed_prep: u8,
+    wram_bank_select: u8,
+    hdma_src_high: u8,
+    hdma_src_low: u8,
+    hdma_dest_high: u8,
+    hdma_dest_low: u8,
+    hdma_control: u8    // The last value written to HDMA5
+}
+
+impl CgbRegisters {
+    pub fn new() -> CgbRegisters {
+        CgbRegisters {
+            key0: 0,
+            double_speed: false,
+            double_speed_prep: 0,
+            wram_bank_select: 0,
+            hdma_src_high: 0,
+            hdma_src_low: 0,
+            hdma_dest_high: 0,
+            hdma_dest_low: 0,
+            hdma_control: 0xFF
+        }
+    }
+
+    // Whether the boot ROM asked for DMG compatibility mode through KEY0.
+    pub fn dmg_compatible(&self) -> bool {
+        self.key0 & 0x04 != 0
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // If a speed switch has been armed through KEY1, toggle between normal and double speed and
+    // return true.
+    pub fn speed_switch(&mut self) -> bool {
+        if self.double_speed_prep & 0x01 == 0 {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.double_speed_prep = 0;
+        true
+    }
+}
+
+impl IoDevice for CgbRegisters {
+    fn read_io(&self, addr: u16) -> u8 {
+        match addr {
+            CGB_KEY0_REG => self.key0,
+            CGB_DOUBLE_SPEED_PREP_REG => (if self.double_speed { 0x80 } else { 0 }) | self.double_speed_prep,
+            CGB_WRAM_BANK_SELECT => self.wram_bank_select,
+            CGB_HDMA_SOURCE_HIGH_REG => self.hdma_src_high,
+            CGB_HDMA_SOURCE_LOW_REG => self.hdma_src_low,
+            CGB_HDMA_DEST_HIGH_REG => self.hdma_dest_high,
+            CGB_HDMA_DEST_LOW_REG => self.hdma_dest_low,
+            // Neither HDMA nor infrared are supported, so report HDMA as inactive and no light.
+            CGB_HDMA_REG => 0xFF,
+            _ => 0x02
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            CGB_KEY0_REG => self.key0 = value,
+            CGB_DOUBLE_SPEED_PREP_REG => self.double_speed_prep = value & 0x01,
+            CGB_WRAM_BANK_SELECT => self.wram_bank_select = value & 0x07,
+            CGB_HDMA_SOURCE_HIGH_REG => self.hdma_src_high = value,
+            CGB_HDMA_SOURCE_LOW_REG => self.hdma_src_low = value,
+            CGB_HDMA_DEST_HIGH_REG => self.hdma_dest_high = value,
+            CGB_HDMA_DEST_LOW_REG => self.hdma_dest_low = value,
+            // The transfer isn't performed, so reads still report HDMA as inactive.
+            CGB_HDMA_REG => self.hdma_control = value,
+            _ => {}
+        }
+        0
+    }
+
+    fn poke_io(&mut self, addr: u16, value: u8) {
+        match addr {
+            CGB_DOUBLE_SPEED_PREP_REG => {
+                self.double_speed = value & 0x80 != 0;
+                self.double_speed_prep = value & 0x01;
+            },
+            _ => { self.write_io(addr, value); }
+        }
+    }
+}
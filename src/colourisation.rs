@@ -0,0 +1,346 @@
+/*
+    When a DMG cartridge boots on a CGB, the boot ROM picks palettes for it, as the cartridge
+    has none of its own. Nintendo's own games are recognised by a checksum of the title and get
+    a palette made for them, and everything else gets the default. Holding a direction, with or
+    without A or B, while the logo is shown picks one of twelve palettes instead:
+
+              Direction    + A          + B
+    Up        Brown        Red          Dark brown
+    Down      Pale yellow  Orange       Yellow
+    Left      Blue         Dark blue    Grey
+    Right     Green        Dark green   Reverse
+
+    Dark green is also the default. Each choice is a set of three palettes, for the background
+    and the two object palettes. When the boot ROM is skipped, nothing polls the buttons, so the
+    held buttons are given up front instead, e.g. with --hold up+a.
+
+    The tables below are laid out as in the boot ROM. A title checksum picks a combination, and
+    a combination picks the three palettes by where they start in the list of raw colours. A few
+    combinations start partway through a palette, so get colours shuffled in from its neighbour.
+*/
+
+use cartridge::Cartridge;
+use input::InputButton;
+
+// 4 colours as 15 bit CGB RGB, which the DMG shades 0-3 map to.
+pub type Palette = [u16; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatPalettes {
+    pub bg: Palette,
+    pub obj0: Palette,
+    pub obj1: Palette
+}
+
+// The raw palettes, which combinations index by colour.
+const COLOURS: [u16; 30 * 4] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,     // 0
+    0x639F, 0x4279, 0x15B0, 0x04CB,     // 1
+    0x7FFF, 0x6E31, 0x454A, 0x0000,     // 2
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,     // 3
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,     // 4
+    0x7FFF, 0x5294, 0x294A, 0x0000,     // 5
+    0x7FFF, 0x03FF, 0x012F, 0x0000,     // 6
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,     // 7
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,     // 8
+    0x7E74, 0x03FF, 0x0180, 0x0000,     // 9
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,     // 10
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,     // 11
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,     // 12
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,     // 13
+    0x03ED, 0x7FFF, 0x255F, 0x0000,     // 14
+    0x036A, 0x021F, 0x03FF, 0x7FFF,     // 15
+    0x7FFF, 0x01DF, 0x0112, 0x0000,     // 16
+    0x231F, 0x035F, 0x00F2, 0x0009,     // 17
+    0x7FFF, 0x03EA, 0x011F, 0x0000,     // 18
+    0x299F, 0x001A, 0x000C, 0x0000,     // 19
+    0x7FFF, 0x027F, 0x001F, 0x0000,     // 20
+    0x7FFF, 0x03E0, 0x0206, 0x0120,     // 21
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,     // 22
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,     // 23
+    0x7FFF, 0x03FF, 0x001F, 0x0000,     // 24
+    0x03FF, 0x001F, 0x000C, 0x0000,     // 25
+    0x7FFF, 0x033F, 0x0193, 0x0000,     // 26
+    0x0000, 0x4200, 0x037F, 0x7FFF,     // 27
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,     // 28
+    0x7FFF, 0x1BEF, 0x6180, 0x0000      // 29
+];
+
+// The first colour of the OBJ0, OBJ1 and BG palettes of each combination.
+const fn combination(obj0: usize, obj1: usize, bg: usize) -> (usize, usize, usize) {
+    (obj0 * 4, obj1 * 4, bg * 4)
+}
+
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    combination(4, 4, 29),      // 0: Dark green
+    combination(18, 18, 18),    // 1: Green
+    combination(20, 20, 20),
+    combination(24, 24, 24),    // 3: Orange
+    combination(9, 9, 9),
+    combination(0, 0, 0),       // 5: Brown
+    combination(27, 27, 27),    // 6: Reverse
+    combination(5, 5, 5),       // 7: Grey
+    combination(12, 12, 12),    // 8: Pale yellow
+    combination(26, 26, 26),
+    combination(16, 8, 8),
+    combination(4, 28, 28),
+    combination(4, 2, 2),
+    combination(3, 4, 4),
+    combination(4, 29, 29),
+    combination(28, 4, 28),
+    combination(2, 17, 2),
+    combination(16, 16, 8),
+    combination(4, 4, 7),
+    combination(4, 4, 18),
+    combination(4, 4, 20),
+    combination(19, 19, 9),
+    (4 * 4 - 1, 4 * 4 - 1, 11 * 4),    // 22: Objects start on the last colour of palette 3
+    combination(17, 17, 2),
+    combination(4, 4, 2),
+    combination(4, 4, 3),
+    combination(28, 28, 0),
+    combination(3, 3, 0),
+    combination(0, 0, 1),       // 28: Dark brown
+    combination(18, 22, 18),
+    combination(20, 22, 20),
+    combination(24, 22, 24),
+    combination(16, 22, 8),
+    combination(17, 4, 13),
+    (28 * 4 - 1, 0, 14 * 4),
+    (28 * 4 - 1, 4 * 4, 15 * 4),
+    combination(19, 22, 9),
+    combination(16, 28, 10),
+    combination(4, 23, 28),
+    combination(17, 22, 2),
+    combination(4, 0, 2),       // 40: Dark blue
+    combination(4, 28, 3),
+    combination(28, 3, 0),
+    combination(3, 28, 4),      // 43: Red
+    combination(21, 28, 4),
+    combination(3, 28, 0),
+    combination(25, 3, 28),
+    combination(0, 28, 8),
+    combination(4, 3, 28),      // 48: Blue
+    combination(28, 3, 6),      // 49: Yellow
+    combination(4, 28, 29)
+];
+
+pub const DEFAULT: usize = 0;
+
+// The combination for each title checksum. After the first 65 entries, checksums are shared by
+// several titles, so the 4th letter of the title has to match too. Unnamed entries are for titles
+// that are not known.
+const TITLES: [(u8, Option<u8>, usize); 94] = [
+    (0x00, None, 0),            // Default
+    (0x88, None, 4),            // ALLEY WAY
+    (0x16, None, 5),            // YAKUMAN
+    (0x36, None, 35),           // BASEBALL, (Game and Watch 2)
+    (0xD1, None, 34),           // TENNIS
+    (0xDB, None, 3),            // TETRIS
+    (0xF2, None, 31),           // QIX
+    (0x3C, None, 15),           // DR.MARIO
+    (0x8C, None, 10),           // RADARMISSION
+    (0x92, None, 5),            // F1RACE
+    (0x3D, None, 19),           // YOSSY NO TAMAGO
+    (0x5C, None, 36),
+    (0x58, None, 7),            // X
+    (0xC9, None, 37),           // MARIOLAND2
+    (0x3E, None, 30),           // YOSSY NO COOKIE
+    (0x70, None, 44),           // ZELDA
+    (0x1D, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31),           // TETRIS FLASH
+    (0x19, None, 20),           // DONKEY KONG
+    (0x35, None, 5),            // MARIO'S PICROSS
+    (0xA8, None, 33),
+    (0x14, None, 13),           // POKEMON RED, (GAMEBOYCAMERA G)
+    (0xAA, None, 14),           // POKEMON GREEN
+    (0x75, None, 5),            // PICROSS 2
+    (0x95, None, 29),           // YOSSY NO PANEPON
+    (0x99, None, 5),            // KIRAKIRA KIDS
+    (0x34, None, 18),           // GAMEBOY GALLERY
+    (0x6F, None, 9),            // POCKETCAMERA
+    (0x15, None, 3),
+    (0xFF, None, 2),            // BALLOON KID
+    (0x97, None, 26),           // KINGOFTHEZOO
+    (0x4B, None, 25),           // DMG FOOTBALL
+    (0x90, None, 25),           // WORLD CUP
+    (0x17, None, 41),           // OTHELLO
+    (0x10, None, 42),           // SUPER RC PRO-AM
+    (0x39, None, 26),           // DYNABLASTER
+    (0xF7, None, 45),           // BOY AND BLOB GB2
+    (0xF6, None, 42),           // MEGAMAN
+    (0xA2, None, 45),           // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4E, None, 38),           // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42),           // LOLO2
+    (0xE0, None, 30),           // YOSHI'S COOKIE
+    (0x8B, None, 41),           // MYSTIC QUEST
+    (0xF0, None, 34),
+    (0xCE, None, 34),           // TOPRANKINGTENNIS
+    (0x0C, None, 5),            // MANSELL
+    (0x29, None, 42),           // MEGAMAN3
+    (0xE8, None, 6),            // SPACE INVADERS
+    (0xB7, None, 5),            // GAME&WATCH
+    (0x86, None, 33),           // DONKEYKONGLAND95
+    (0x9A, None, 25),           // ASTEROIDS/MISCMD
+    (0x52, None, 42),           // STREET FIGHTER 2
+    (0x01, None, 42),           // DEFENDER/JOUST
+    (0x9D, None, 40),           // KILLERINSTINCT95
+    (0x71, None, 2),            // TETRIS BLAST
+    (0x9C, None, 16),           // PINOCCHIO
+    (0xBD, None, 25),
+    (0x5D, None, 42),           // BA.TOSHINDEN
+    (0x6D, None, 42),           // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3F, None, 0),            // TETRIS PLUS
+    (0x6B, None, 39),           // DONKEYKONGLAND 3
+    (0xB3, Some(b'B'), 36),
+    (0x46, Some(b'E'), 22),     // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25),     // GOLF
+    (0xA5, Some(b'A'), 6),      // SOLARSTRIKER
+    (0xC6, Some(b'A'), 32),     // GBWARS
+    (0xD3, Some(b'R'), 12),     // KAERUNOTAMENI
+    (0x27, Some(b'B'), 36),
+    (0x61, Some(b'E'), 11),     // POKEMON BLUE
+    (0x18, Some(b'K'), 39),     // DONKEYKONGLAND
+    (0x66, Some(b'E'), 18),     // GAMEBOY GALLERY2
+    (0x6A, Some(b'K'), 39),     // DONKEYKONGLAND 2
+    (0xBF, Some(b' '), 24),     // KID ICARUS
+    (0x0D, Some(b'R'), 31),     // TETRIS2
+    (0xF4, Some(b'-'), 50),
+    (0xB3, Some(b'U'), 17),     // MOGURANYA
+    (0x46, Some(b'R'), 46),
+    (0x28, Some(b'A'), 6),      // GALAGA&GALAXIAN
+    (0xA5, Some(b'R'), 27),     // BT2RAGNAROKWORLD
+    (0xC6, Some(b' '), 0),      // KEN GRIFFEY JR
+    (0xD3, Some(b'I'), 47),
+    (0x27, Some(b'N'), 41),     // MAGNETIC SOCCER
+    (0x61, Some(b'A'), 41),     // VEGAS STAKES
+    (0x18, Some(b'I'), 0),
+    (0x66, Some(b'L'), 0),      // MILLI/CENTI/PEDE
+    (0x6A, Some(b'I'), 19),     // MARIO & YOSHI
+    (0xBF, Some(b'C'), 34),     // SOCCER
+    (0x0D, Some(b'E'), 23),     // POKEBOM
+    (0xF4, Some(b' '), 18),     // G&W GALLERY
+    (0xB3, Some(b'R'), 29),     // TETRIS ATTACK
+];
+
+// The combinations picked by holding a direction alone, with A and with B.
+const BUTTON_COMBINATIONS: [(InputButton, [usize; 3]); 4] = [
+    (InputButton::Up, [5, 43, 28]),
+    (InputButton::Down, [8, 3, 49]),
+    (InputButton::Left, [48, 40, 7]),
+    (InputButton::Right, [1, 0, 6])
+];
+
+// The three palettes of a combination.
+pub fn palettes(combination: usize) -> CompatPalettes {
+    let palette = |start: usize| [COLOURS[start], COLOURS[start + 1], COLOURS[start + 2], COLOURS[start + 3]];
+    let (obj0, obj1, bg) = COMBINATIONS[combination];
+    CompatPalettes { bg: palette(bg), obj0: palette(obj0), obj1: palette(obj1) }
+}
+
+// The sum of the 16 bytes of the title, as the boot ROM calculates it.
+pub fn title_checksum(cartridge: &Cartridge) -> u8 {
+    cartridge.title_bytes().iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+// The combination chosen by the boot ROM, given the buttons held while it runs.
+pub fn select(cartridge: &Cartridge, held: &[InputButton]) -> usize {
+    if let Some(combination) = button_combination(held) {
+        return combination;
+    }
+    if !cartridge.is_nintendo_licensee() {
+        return DEFAULT;
+    }
+    let checksum = title_checksum(cartridge);
+    let fourth_letter = cartridge.title_bytes()[3];
+    TITLES.iter()
+        .find(|&&(c, letter, _)| c == checksum && letter.map_or(true, |l| l == fourth_letter))
+        .map_or(DEFAULT, |&(_, _, combination)| combination)
+}
+
+// The first direction held in the order Up, Down, Left, Right wins, and A beats B.
+fn button_combination(held: &[InputButton]) -> Option<usize> {
+    let choices = BUTTON_COMBINATIONS.iter()
+        .find(|&&(direction, _)| held.contains(&direction))
+        .map(|&(_, choices)| choices)?;
+    Some(if held.contains(&InputButton::A) {
+        choices[1]
+    } else if held.contains(&InputButton::B) {
+        choices[2]
+    } else {
+        choices[0]
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cartridge(title: &str, licensee: u8) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+        rom[0x14B] = licensee;
+        Cartridge::from_rom(rom).unwrap()
+    }
+
+    fn nintendo(title: &str) -> usize {
+        select(&cartridge(title, 0x01), &[])
+    }
+
+    #[test]
+    fn titles() {
+        assert_eq!(title_checksum(&cartridge("TETRIS", 0x01)), 0xDB);
+        assert_eq!(nintendo("TETRIS"), 3);
+        assert_eq!(nintendo("ZELDA"), 44);
+        assert_eq!(nintendo("DR.MARIO"), 15);
+        assert_eq!(nintendo("POKEMON RED"), 13);
+        assert_eq!(nintendo("POKEMON GREEN"), 14);
+        assert_eq!(nintendo("SUPER MARIOLAND"), 22);
+        assert_eq!(nintendo("MARIO & YOSHI"), 19);
+
+        // POKEMON BLUE and VEGAS STAKES share a checksum, so the 4th letter decides.
+        assert_eq!(title_checksum(&cartridge("VEGAS STAKES", 0x01)), 0x61);
+        assert_eq!(nintendo("POKEMON BLUE"), 11);
+        assert_eq!(nintendo("VEGAS STAKES"), 41);
+        // As do DONKEYKONGLAND 2 and MARIO & YOSHI. A title with the checksum but neither letter
+        // gets the default.
+        assert_eq!(nintendo("DONKEYKONGLAND 2"), 39);
+        assert_eq!(nintendo("DONXEYKONGLAND %"), DEFAULT);
+
+        // Only Nintendo's games are recognised.
+        assert_eq!(select(&cartridge("POKEMON RED", 0x33), &[]), DEFAULT);
+        assert_eq!(nintendo("HOMEBREW GAME"), DEFAULT);
+    }
+
+    #[test]
+    fn buttons() {
+        let blue = cartridge("POKEMON BLUE", 0x01);
+        assert_eq!(select(&blue, &[InputButton::Up]), 5);
+        assert_eq!(select(&blue, &[InputButton::Up, InputButton::A]), 43);
+        assert_eq!(select(&blue, &[InputButton::B, InputButton::Left]), 7);
+        assert_eq!(select(&blue, &[InputButton::Right, InputButton::A, InputButton::B]), 0);
+        // A or B without a direction does nothing.
+        assert_eq!(select(&blue, &[InputButton::A]), 11);
+        assert_eq!(select(&cartridge("HOMEBREW GAME", 0x01), &[InputButton::Down, InputButton::B]), 49);
+    }
+
+    #[test]
+    fn palette_data() {
+        // Up + A: red background, green and blue objects.
+        let red = palettes(43);
+        assert_eq!(red.bg, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(red.obj0, [0x7FFF, 0x1BEF, 0x0200, 0x0000]);
+        assert_eq!(red.obj1, [0x7FFF, 0x7E8C, 0x7C00, 0x0000]);
+        assert_eq!(palettes(DEFAULT).bg, [0x7FFF, 0x1BEF, 0x6180, 0x0000]);
+
+        // SUPER MARIOLAND's object palettes start on the last colour of palette 3.
+        let mario = palettes(22);
+        assert_eq!(mario.obj0, [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+        assert_eq!(mario.obj1, mario.obj0);
+        assert_eq!(mario.bg, [0x7ED6, 0x4BFF, 0x2175, 0x0000]);
+    }
+}
@@ -0,0 +1,456 @@
+/*
+    Recursive traversal ROM disassembler.
+
+    Code is discovered by following JP/JR/CALL/RST targets from the entry point and the interrupt
+    vectors, one ROM bank at a time. Everything which is not reached is treated as data. The result
+    can be written out as RGBDS source which reassembles into the original ROM.
+
+    Jumps from bank 0 into the switchable bank area depend on the MBC state at runtime. The bank is
+    known when the code before the jump selects it with a constant, i.e. ld a, n followed by a
+    write of a to $2000-$3FFF, and is otherwise only assumed for 32KB ROMs without banking. Code
+    which is only reached some other way, e.g. through a jump table, can be traced by adding extra
+    entry points.
+
+    Each instruction is traced once, so a bank 0 routine which is called with different banks
+    selected only follows jumps into the bank which was selected the first time it was reached.
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use cartridge::{MemoryBankController, ROM_BANK_SIZE};
+use cpu::Cond;
+use cpu::IndirectAddr;
+use cpu::decoder;
+use cpu::disassembler;
+use cpu::ops::{Arg8, Arg16, Instruction};
+use cpu::registers::Reg8;
+
+// Entry point, RST vectors and interrupt vectors
+const ENTRY_POINT: u16 = 0x0100;
+const VECTORS: [u16; 13] = [
+    0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38,
+    0x40, 0x48, 0x50, 0x58, 0x60
+];
+
+// The cartridge header, which is never code
+const HEADER_START: usize = 0x0104;
+const HEADER_END: usize = 0x0150;
+
+// Maximum number of bytes on a single db line
+const DATA_LINE_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Byte {
+    Data,
+    Instr,      // First byte of an instruction
+    Operand     // Remaining bytes of an instruction
+}
+
+pub struct Analysis {
+    rom: Vec<u8>,
+    banks: usize,
+    bytes: Vec<Byte>,
+    targets: BTreeSet<usize>,           // ROM offsets of jump and call targets
+    resolved: BTreeMap<usize, usize>    // ROM offsets of jumps and calls, and of their targets
+}
+
+impl Analysis {
+    // Analyse the ROM of a cartridge.
+    pub fn new(mbc: &MemoryBankController) -> Analysis {
+        Analysis::from_rom(mbc.rom(), mbc.rom_bank_count())
+    }
+
+    pub fn from_rom(rom: &[u8], banks: usize) -> Analysis {
+        let mut analysis = Analysis {
+            rom: rom.to_vec(),
+            banks: banks,
+            bytes: vec![Byte::Data; rom.len()],
+            targets: BTreeSet::new(),
+            resolved: BTreeMap::new()
+        };
+        analysis.trace(0, ENTRY_POINT);
+        for &vector in VECTORS.iter() {
+            analysis.trace(0, vector);
+        }
+        analysis
+    }
+
+    // Trace code from an address which isn't reached from the entry point, and give it a label.
+    pub fn add_entry_point(&mut self, bank: usize, addr: u16) -> Result<(), String> {
+        match self.offset(bank, addr) {
+            Some(offset) => {
+                self.targets.insert(offset);
+                self.trace(bank, addr);
+                Ok(())
+            },
+            None => Err(format!("{:02X}:{:04X} is not in the ROM", bank, addr))
+        }
+    }
+
+    // Convert a banked address into an offset into the ROM image.
+    fn offset(&self, bank: usize, addr: u16) -> Option<usize> {
+        let offset = match (bank, addr) {
+            (0, 0x0000...0x3FFF) => addr as usize,
+            (_, 0x4000...0x7FFF) if bank > 0 => bank * ROM_BANK_SIZE + (addr as usize - 0x4000),
+            _ => return None
+        };
+        if offset < self.rom.len() { Some(offset) } else { None }
+    }
+
+    // Work out which bank a jump target refers to, if it can be known statically. selected is the
+    // bank which the code has switched to, if known.
+    fn target_bank(&self, bank: usize, target: u16, selected: Option<usize>) -> Option<usize> {
+        match target {
+            0x0000...0x3FFF => Some(0),
+            0x4000...0x7FFF if bank > 0 => Some(bank),
+            0x4000...0x7FFF if selected.is_some() => selected,
+            0x4000...0x7FFF if self.banks <= 2 => Some(1),
+            _ => None
+        }
+    }
+
+    // The bank selected by writing a value to the ROM bank register. Bank 0 can't be selected,
+    // and values above the number of banks wrap around.
+    fn selected_bank(&self, value: u8) -> Option<usize> {
+        if self.banks <= 2 {
+            return None;
+        }
+        match value as usize % self.banks {
+            0 => Some(1),
+            bank => Some(bank)
+        }
+    }
+
+    fn trace(&mut self, bank: usize, addr: u16) {
+        // Code in a switchable bank can only run while that bank is selected.
+        let selected = if bank > 0 { Some(bank) } else { None };
+        let mut queue = vec![(bank, addr, selected)];
+        while let Some((bank, mut addr, mut selected)) = queue.pop() {
+            // The value in a, if it was loaded with a constant.
+            let mut a = None;
+            loop {
+                let offset = match self.offset(bank, addr) {
+                    Some(offset) => offset,
+                    None => break
+                };
+                if self.bytes[offset] != Byte::Data || (offset >= HEADER_START && offset < HEADER_END) {
+                    break;
+                }
+
+                // Decode within the current bank only.
+                let bank_end = (offset / ROM_BANK_SIZE + 1) * ROM_BANK_SIZE;
+                let bank_end = if bank_end > self.rom.len() { self.rom.len() } else { bank_end };
+                let (instr, length) = match decoder::decode_slice(&self.rom[offset..bank_end]) {
+                    Some(decoded) => decoded,
+                    None => break
+                };
+
+                // Unmapped opcodes lock up the CPU, so are assumed to be data.
+                if let Instruction::ILLEGAL(_) = instr {
+                    break;
+                }
+                if decoder::encode(instr).as_ref().map(|b| &b[..]) != Some(&self.rom[offset..offset + length]) {
+                    break;
+                }
+                if self.bytes[offset + 1..offset + length].iter().any(|&b| b != Byte::Data) {
+                    break;
+                }
+                self.bytes[offset] = Byte::Instr;
+                for b in &mut self.bytes[offset + 1..offset + length] {
+                    *b = Byte::Operand;
+                }
+
+                // Follow writes of a known bank number to the ROM bank register.
+                a = match instr {
+                    Instruction::LD(Arg8::Reg(Reg8::A), Arg8::Imm(value)) => Some(value),
+                    Instruction::LD(Arg8::Ind(IndirectAddr::Imm16(0x2000...0x3FFF)), Arg8::Reg(Reg8::A)) => {
+                        if let Some(value) = a {
+                            selected = self.selected_bank(value).or(selected);
+                        }
+                        a
+                    },
+                    // Storing a elsewhere, e.g. to a shadow copy of the bank number, leaves it intact.
+                    Instruction::LD(Arg8::Ind(_), Arg8::Reg(Reg8::A)) |
+                    Instruction::LDH(Arg8::Ind(_), Arg8::Reg(Reg8::A)) => a,
+                    _ => None
+                };
+
+                if let Some(target) = jump_target(&instr, addr) {
+                    if let Some(target_bank) = self.target_bank(bank, target, selected) {
+                        if let Some(target_offset) = self.offset(target_bank, target) {
+                            self.targets.insert(target_offset);
+                            self.resolved.insert(offset, target_offset);
+                            queue.push((target_bank, target, selected));
+                        }
+                    }
+                }
+
+                if ends_flow(&instr) {
+                    break;
+                }
+                addr = addr.wrapping_add(length as u16);
+            }
+        }
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks
+    }
+
+    pub fn is_code(&self, bank: usize, addr: u16) -> bool {
+        self.offset(bank, addr).map_or(false, |offset| self.bytes[offset] != Byte::Data)
+    }
+
+    // Jump targets which start an instruction, and so are given a label.
+    fn has_label(&self, offset: usize) -> bool {
+        self.targets.contains(&offset) && self.bytes[offset] == Byte::Instr
+    }
+
+    pub fn labels(&self) -> Vec<(usize, u16)> {
+        self.targets.iter()
+            .filter(|&&offset| self.has_label(offset))
+            .map(|&offset| address_of(offset))
+            .collect()
+    }
+
+    // The label of the target of the jump or call at a ROM offset.
+    fn label_for(&self, offset: usize) -> Option<String> {
+        self.resolved.get(&offset)
+            .and_then(|&target| if self.has_label(target) { Some(label_name(target)) } else { None })
+    }
+
+    // Generate the RGBDS source for a single bank.
+    pub fn bank_source(&self, bank: usize) -> String {
+        let mut out = String::new();
+        let start = bank * ROM_BANK_SIZE;
+        let end = if start + ROM_BANK_SIZE > self.rom.len() { self.rom.len() } else { start + ROM_BANK_SIZE };
+        if bank == 0 {
+            out.push_str("SECTION \"ROM Bank $000\", ROM0[$0000]\n");
+        } else {
+            out.push_str(&format!("SECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[${:X}]\n", bank, bank));
+        }
+
+        let mut offset = start;
+        while offset < end {
+            let (_, addr) = address_of(offset);
+            if self.has_label(offset) {
+                out.push_str(&format!("\n{}:\n", label_name(offset)));
+            }
+
+            if self.bytes[offset] == Byte::Instr {
+                let (instr, length) = decoder::decode_slice(&self.rom[offset..end]).unwrap();
+                let bytes = &self.rom[offset..offset + length];
+                let text = match self.label_for(offset) {
+                    Some(label) => disassembler::format_with_label(&instr, &label),
+                    None => disassembler::format_at(&instr, addr, &disassembler::Options { absolute_jumps: true })
+                };
+                if reassembles_exactly(&instr) {
+                    out.push_str(&format!("    {:<24}; ${:04X}\n", text, addr));
+                } else {
+                    out.push_str(&format!("    {:<24}; ${:04X} {}\n", data_line(bytes), addr, text));
+                }
+                offset += length;
+            } else {
+                // Collect data up to the next instruction or label.
+                let mut data_end = offset + 1;
+                while data_end < end && data_end - offset < DATA_LINE_LENGTH
+                    && self.bytes[data_end] == Byte::Data && !self.has_label(data_end) {
+                    data_end += 1;
+                }
+                out.push_str(&format!("    {} ; ${:04X}\n", data_line(&self.rom[offset..data_end]), addr));
+                offset = data_end;
+            }
+        }
+        out
+    }
+
+    // Generate the source files for the whole ROM, as (filename, contents) pairs. The first file
+    // includes all of the others.
+    pub fn source_files(&self) -> Vec<(String, String)> {
+        let mut main = String::new();
+        let mut files = Vec::new();
+        for bank in 0..self.banks {
+            let filename = format!("bank_{:03x}.asm", bank);
+            main.push_str(&format!("INCLUDE \"{}\"\n", filename));
+            files.push((filename, self.bank_source(bank)));
+        }
+        files.insert(0, ("game.asm".to_string(), main));
+        files
+    }
+
+    pub fn save(&self, directory: &str) -> Result<(), String> {
+        for (filename, contents) in self.source_files() {
+            let path = Path::new(directory).join(&filename);
+            let mut f = match File::create(&path) {
+                Ok(f) => f,
+                Err(_) => return Err(format!("Unable to create {}", path.display())),
+            };
+            if let Err(_) = f.write_all(contents.as_bytes()) {
+                return Err(format!("Unable to write {}", path.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Convert a ROM offset into a (bank, address) pair.
+fn address_of(offset: usize) -> (usize, u16) {
+    let bank = offset / ROM_BANK_SIZE;
+    let addr = (offset % ROM_BANK_SIZE) as u16;
+    if bank == 0 { (0, addr) } else { (bank, addr + 0x4000) }
+}
+
+fn label_name(offset: usize) -> String {
+    let (bank, addr) = address_of(offset);
+    format!("L{:03X}_{:04X}", bank, addr)
+}
+
+fn data_line(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+    format!("db {}", values.join(", "))
+}
+
+fn jump_target(instr: &Instruction, addr: u16) -> Option<u16> {
+    match *instr {
+        Instruction::JP(_, Arg16::Imm(target)) => Some(target),
+        Instruction::CALL(_, Arg16::Imm(target)) => Some(target),
+        Instruction::JR(_, offset) => Some(addr.wrapping_add(2).wrapping_add(offset as u16)),
+        Instruction::RST(target) => Some(target as u16),
+        _ => None
+    }
+}
+
+// Whether execution can't continue with the next instruction.
+fn ends_flow(instr: &Instruction) -> bool {
+    match *instr {
+        Instruction::JP(Cond::None, _) | Instruction::JR(Cond::None, _) => true,
+        Instruction::RET(Cond::None) | Instruction::RETI => true,
+        _ => false
+    }
+}
+
+// Some instructions have more than one encoding, or are encoded differently depending on the
+// assembler version and options. These are emitted as data.
+fn reassembles_exactly(instr: &Instruction) -> bool {
+    match *instr {
+        // Older versions of rgbasm optimise these into LDH.
+        Instruction::LD(Arg8::Ind(IndirectAddr::Imm16(addr)), _) |
+        Instruction::LD(_, Arg8::Ind(IndirectAddr::Imm16(addr))) => addr < 0xFF00,
+        _ => true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A 32KB ROM with a small program at the entry point.
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        let program: &[(usize, &[u8])] = &[
+            (0x0000, &[0xC9]),                          // ret
+            (0x0100, &[0x00, 0xC3, 0x50, 0x01]),        // nop; jp $0150
+            (0x0150, &[0x3E, 0x05,                      // ld a, $05
+                       0xCD, 0x00, 0x40,                // call $4000
+                       0x3D,                            // dec a
+                       0x20, 0xFA,                      // jr nz, $0152
+                       0xFA, 0x00, 0xFF,                // ld a, [$FF00]
+                       0x18, 0xFE]),                    // jr @
+            (0x4000, &[0xD3, 0x12, 0x34]),              // Illegal opcode and data.
+        ];
+        for &(addr, bytes) in program {
+            rom[addr..addr + bytes.len()].copy_from_slice(bytes);
+        }
+        rom
+    }
+
+    #[test]
+    fn code_and_data_separation() {
+        let analysis = Analysis::from_rom(&test_rom(), 2);
+        assert!(analysis.is_code(0, 0x0000));
+        assert!(!analysis.is_code(0, 0x0001));
+        assert!(analysis.is_code(0, 0x0008)); // RST vector, runs into the entry point.
+        assert!(analysis.is_code(0, 0x0100));
+        assert!(!analysis.is_code(0, 0x0104)); // Header.
+        assert!(analysis.is_code(0, 0x0150));
+        assert!(analysis.is_code(0, 0x015C));
+        assert!(!analysis.is_code(0, 0x015D));
+        assert!(!analysis.is_code(1, 0x4000)); // Illegal opcode.
+        assert_eq!(analysis.labels(), vec![(0, 0x0150), (0, 0x0152), (0, 0x015B)]);
+    }
+
+    #[test]
+    fn source_output() {
+        let analysis = Analysis::from_rom(&test_rom(), 2);
+        let bank0 = analysis.bank_source(0);
+        assert!(bank0.starts_with("SECTION \"ROM Bank $000\", ROM0[$0000]\n"));
+        assert!(bank0.contains("    jp L000_0150"));
+        assert!(bank0.contains("\nL000_0152:\n    call $4000"));
+        assert!(bank0.contains("    jr nz, L000_0152"));
+        assert!(bank0.contains("    db $FA, $00, $FF        ; $0158 ld a, [$FF00]"));
+        assert!(bank0.contains("    jr L000_015B"));
+
+        let bank1 = analysis.bank_source(1);
+        assert!(bank1.starts_with("SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]\n"));
+        assert!(bank1.contains("    db $D3, $12, $34"));
+
+        let files = analysis.source_files();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].1, "INCLUDE \"bank_000.asm\"\nINCLUDE \"bank_001.asm\"\n");
+    }
+    // A 64KB ROM with four banks, where bank 0 switches banks before jumping into them.
+    fn banked_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x10000];
+        let program: &[(usize, &[u8])] = &[
+            (0x0100, &[0x00, 0xC3, 0x50, 0x01]),        // nop; jp $0150
+            (0x0150, &[0x3E, 0x03,                      // ld a, $03
+                       0xE0, 0x80,                      // ldh [$FF80], a
+                       0xEA, 0x00, 0x20,                // ld [$2000], a
+                       0xCD, 0x00, 0x40,                // call $4000
+                       0x3E, 0x02,                      // ld a, $02
+                       0xEA, 0x00, 0x21,                // ld [$2100], a
+                       0xC3, 0x10, 0x40]),              // jp $4010
+            (0x4000, &[0xD3]),                          // Bank 1, illegal opcode.
+            (0x4010, &[0xD3]),
+            (0x4020, &[0x18, 0x02,                      // Bank 1, jr $4024
+                       0xD3, 0xD3,
+                       0xC9]),                          // ret
+            (0x8010, &[0x18, 0xFE]),                    // Bank 2, jr @
+            (0xC000, &[0xCD, 0x05, 0x40,                // Bank 3, call $4005
+                       0xC9,                            // ret
+                       0xD3,
+                       0xC9]),                          // ret
+        ];
+        for &(addr, bytes) in program {
+            rom[addr..addr + bytes.len()].copy_from_slice(bytes);
+        }
+        rom
+    }
+
+    #[test]
+    fn bank_switching() {
+        let mut analysis = Analysis::from_rom(&banked_rom(), 4);
+        assert!(analysis.is_code(3, 0x4000));
+        assert!(analysis.is_code(3, 0x4005));
+        assert!(!analysis.is_code(3, 0x4004));
+        assert!(analysis.is_code(2, 0x4010));
+        assert!(!analysis.is_code(1, 0x4000));
+        assert!(!analysis.is_code(1, 0x4010));
+        assert!(!analysis.is_code(1, 0x4020));
+        assert!(analysis.bank_source(0).contains("    call L003_4000"));
+        assert!(analysis.bank_source(0).contains("    jp L002_4010"));
+        assert!(analysis.bank_source(3).contains("    call L003_4005"));
+
+        // Code which is never jumped to can be added by hand.
+        analysis.add_entry_point(1, 0x4020).unwrap();
+        assert!(analysis.is_code(1, 0x4020));
+        assert!(!analysis.is_code(1, 0x4022));
+        assert!(analysis.is_code(1, 0x4024));
+        assert!(analysis.labels().contains(&(1, 0x4020)));
+        assert!(analysis.labels().contains(&(1, 0x4024)));
+        assert!(analysis.add_entry_point(4, 0x4000).is_err());
+        assert!(analysis.add_entry_point(1, 0x8000).is_err());
+    }
+}
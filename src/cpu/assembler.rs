@@ -0,0 +1,607 @@
+/*
+    Assembler for the RGBDS syntax produced by the disassembler.
+
+    Supports labels (including .local labels), the db/dw directives, and SECTION directives with a
+    fixed address and bank so that the analyser's output can be reassembled. Expressions may use
+    numbers ($hex, 0xhex, %binary or decimal), labels, @ for the current address, and + or -.
+*/
+
+use std::collections::HashMap;
+use cpu::{Cond, IndirectAddr};
+use cpu::decoder;
+use cpu::ops::*;
+use cpu::ops::Instruction::*;
+use cpu::registers::*;
+use cartridge::ROM_BANK_SIZE;
+
+pub struct Program {
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>
+}
+
+impl Program {
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.get(name).cloned()
+    }
+}
+
+// Assembler state for a single pass over the source
+struct Pass<'a> {
+    labels: &'a HashMap<String, u16>,
+    final_pass: bool,
+    addr: u16,
+    end: u32,           // The address after the end of the current section
+    offset: usize,
+    scope: String,      // The last global label, which .local labels belong to
+    bytes: Vec<u8>
+}
+
+// Assemble a program. Without a SECTION directive, the first byte is placed at origin.
+pub fn assemble(source: &str, origin: u16) -> Result<Program, String> {
+    // The first pass finds the address of every label, the second encodes with them.
+    let (_, labels) = run_pass(source, origin, &HashMap::new(), false)?;
+    let (bytes, _) = run_pass(source, origin, &labels, true)?;
+    Ok(Program { bytes: bytes, labels: labels })
+}
+
+// Assemble a single instruction located at addr, e.g. for patching memory.
+pub fn assemble_instr(text: &str, addr: u16) -> Result<Vec<u8>, String> {
+    let labels = HashMap::new();
+    let pass = Pass { labels: &labels, final_pass: true, addr: addr, end: 0x10000, offset: 0, scope: String::new(), bytes: Vec::new() };
+    let instr = pass.parse_instr(text.trim())?;
+    encode(instr)
+}
+
+fn run_pass(source: &str, origin: u16, labels: &HashMap<String, u16>, final_pass: bool)
+            -> Result<(Vec<u8>, HashMap<String, u16>), String> {
+    let mut found = HashMap::new();
+    let mut pass = Pass { labels: labels, final_pass: final_pass, addr: origin, end: 0x10000, offset: 0, scope: String::new(), bytes: Vec::new() };
+    for (number, line) in source.lines().enumerate() {
+        let line = match line.find(';') {
+            Some(comment) => &line[..comment],
+            None => line
+        };
+        pass.line(line, &mut found).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+    }
+    Ok((pass.bytes, found))
+}
+
+fn encode(instr: Instruction) -> Result<Vec<u8>, String> {
+    decoder::encode(instr).ok_or(format!("Instruction {:?} has no encoding", instr))
+}
+
+// Split operands on commas which are not inside quotes.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    for ch in text.chars() {
+        match ch {
+            '"' => { in_string = !in_string; current.push(ch); },
+            ',' if !in_string => { operands.push(current.trim().to_string()); current = String::new(); },
+            _ => current.push(ch)
+        }
+    }
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+fn reg8(name: &str) -> Option<Arg8> {
+    match name {
+        "a" => Some(Arg8::Reg(Reg8::A)),
+        "b" => Some(Arg8::Reg(Reg8::B)),
+        "c" => Some(Arg8::Reg(Reg8::C)),
+        "d" => Some(Arg8::Reg(Reg8::D)),
+        "e" => Some(Arg8::Reg(Reg8::E)),
+        "h" => Some(Arg8::Reg(Reg8::H)),
+        "l" => Some(Arg8::Reg(Reg8::L)),
+        "[hl]" => Some(Arg8::Ind(IndirectAddr::HL)),
+        _ => None
+    }
+}
+
+fn reg16(name: &str) -> Option<Arg16> {
+    match name {
+        "af" => Some(Arg16::Reg(Reg16::AF)),
+        "bc" => Some(Arg16::Reg(Reg16::BC)),
+        "de" => Some(Arg16::Reg(Reg16::DE)),
+        "hl" => Some(Arg16::Reg(Reg16::HL)),
+        "sp" => Some(Arg16::Reg(Reg16::SP)),
+        _ => None
+    }
+}
+
+fn cond(name: &str) -> Option<Cond> {
+    match name {
+        "nz" => Some(Cond::NZ),
+        "z" => Some(Cond::Z),
+        "nc" => Some(Cond::NC),
+        "c" => Some(Cond::C),
+        _ => None
+    }
+}
+
+fn is_memory(operand: &str) -> bool {
+    operand.starts_with('[') && operand.ends_with(']')
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let lower = text.to_lowercase();
+    if lower.starts_with('$') {
+        i32::from_str_radix(&lower[1..], 16).ok()
+    } else if lower.starts_with("0x") {
+        i32::from_str_radix(&lower[2..], 16).ok()
+    } else if lower.starts_with('%') {
+        i32::from_str_radix(&lower[1..], 2).ok()
+    } else if lower.chars().next().map_or(false, |c| c.is_digit(10)) {
+        lower.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl<'a> Pass<'a> {
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.addr as u32 + bytes.len() as u32 > self.end {
+            return Err(format!("Section overflows past ${:04X}", self.end - 1));
+        }
+        let end = self.offset + bytes.len();
+        if self.bytes.len() < end {
+            self.bytes.resize(end, 0);
+        }
+        self.bytes[self.offset..end].copy_from_slice(bytes);
+        self.offset = end;
+        self.addr = self.addr.wrapping_add(bytes.len() as u16);
+        Ok(())
+    }
+
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('.') { format!("{}{}", self.scope, name) } else { name.to_string() }
+    }
+
+    fn line(&mut self, line: &str, found: &mut HashMap<String, u16>) -> Result<(), String> {
+        let mut text = line.trim();
+
+        // Labels.
+        if let Some(colon) = text.find(':') {
+            let name = &text[..colon];
+            if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+                if !name.starts_with('.') {
+                    self.scope = name.to_string();
+                }
+                let name = self.qualify(name);
+                if found.insert(name.clone(), self.addr).is_some() {
+                    return Err(format!("Label {} is defined more than once", name));
+                }
+                text = text[colon..].trim_start_matches(':').trim();
+            }
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], text[space..].trim()),
+            None => (text, "")
+        };
+        match mnemonic.to_lowercase().as_str() {
+            "section" => self.section(rest),
+            "db" => {
+                for operand in split_operands(rest) {
+                    if operand.starts_with('"') && operand.ends_with('"') && operand.len() >= 2 {
+                        let string = operand[1..operand.len() - 1].to_string();
+                        self.emit(string.as_bytes())?;
+                    } else {
+                        let value = self.imm8(&operand)?;
+                        self.emit(&[value])?;
+                    }
+                }
+                Ok(())
+            },
+            "dw" => {
+                for operand in split_operands(rest) {
+                    let value = self.imm16(&operand)?;
+                    self.emit(&[(value & 0xFF) as u8, (value >> 8) as u8])?;
+                }
+                Ok(())
+            },
+            _ => {
+                let instr = self.parse_instr(text)?;
+                let bytes = encode(instr)?;
+                self.emit(&bytes)
+            }
+        }
+    }
+
+    // SECTION "name", TYPE[$addr], BANK[$n]
+    fn section(&mut self, text: &str) -> Result<(), String> {
+        let operands = split_operands(text);
+        let mut kind = String::new();
+        let mut addr = None;
+        let mut bank = 0;
+        for operand in operands.iter().skip(1) {
+            let (name, value) = match (operand.find('['), operand.rfind(']')) {
+                (Some(open), Some(close)) if close > open =>
+                    (operand[..open].trim().to_lowercase(), self.expr(&operand[open + 1..close])?),
+                _ => continue
+            };
+            if name == "bank" {
+                bank = value as usize;
+            } else {
+                kind = name;
+                addr = Some(value);
+            }
+        }
+        let addr = addr.ok_or("SECTION must have a fixed address".to_string())?;
+        // The window of addresses the section type can be placed in.
+        let (start, end) = match kind.as_str() {
+            "rom0" => (0x0000, 0x4000),
+            "romx" => (0x4000, 0x8000),
+            _ => (0x0000, 0x10000)
+        };
+        if addr < start || addr >= end {
+            return Err(format!("{} section can't be placed at ${:04X}", kind.to_uppercase(), addr));
+        }
+        let addr = addr as u16;
+        self.addr = addr;
+        self.end = end as u32;
+        self.offset = match addr {
+            0x4000...0x7FFF if bank > 0 => bank * ROM_BANK_SIZE + (addr as usize - 0x4000),
+            _ => addr as usize
+        };
+        Ok(())
+    }
+
+    fn expr(&self, text: &str) -> Result<i32, String> {
+        let text = text.trim();
+        let mut terms = Vec::new();
+        let mut sign = 1i32;
+        let mut term = String::new();
+        for ch in text.chars() {
+            match ch {
+                '+' | '-' => {
+                    if !term.trim().is_empty() {
+                        terms.push((sign, term.trim().to_string()));
+                        term = String::new();
+                    }
+                    sign = if ch == '-' { -1 } else { 1 };
+                },
+                _ => term.push(ch)
+            }
+        }
+        if term.trim().is_empty() {
+            return Err(format!("Invalid expression: {}", text));
+        }
+        terms.push((sign, term.trim().to_string()));
+
+        let mut result = 0i32;
+        for (sign, term) in terms {
+            let value = if term == "@" {
+                self.addr as i32
+            } else if let Some(n) = parse_number(&term) {
+                n
+            } else if let Some(&addr) = self.labels.get(&self.qualify(&term)) {
+                addr as i32
+            } else if !self.final_pass {
+                0
+            } else {
+                return Err(format!("Unknown label or invalid number: {}", term));
+            };
+            result += sign * value;
+        }
+        Ok(result)
+    }
+
+    fn imm8(&self, text: &str) -> Result<u8, String> {
+        let value = self.expr(text)?;
+        if value < -128 || value > 255 {
+            return Err(format!("Value out of range: {}", text));
+        }
+        Ok(value as u8)
+    }
+
+    fn imm16(&self, text: &str) -> Result<u16, String> {
+        let value = self.expr(text)?;
+        if value < -32768 || value > 65535 {
+            return Err(format!("Value out of range: {}", text));
+        }
+        Ok(value as u16)
+    }
+
+    fn signed8(&self, text: &str) -> Result<i8, String> {
+        let value = self.expr(text)?;
+        if value < -128 || value > 127 {
+            return Err(format!("Signed value out of range: {}", text));
+        }
+        Ok(value as i8)
+    }
+
+    // An 8-bit source operand: a register, [hl] or an immediate.
+    fn src8(&self, text: &str) -> Result<Arg8, String> {
+        match reg8(text) {
+            Some(arg) => Ok(arg),
+            None if is_memory(text) => Err(format!("Invalid operand: {}", text)),
+            None => Ok(Arg8::Imm(self.imm8(text)?))
+        }
+    }
+
+    // The address of a memory operand, e.g. [$C000].
+    fn address(&self, text: &str) -> Result<u16, String> {
+        self.imm16(&text[1..text.len() - 1])
+    }
+
+    fn jr_offset(&self, text: &str) -> Result<i8, String> {
+        let target = self.expr(text)?;
+        let offset = target - (self.addr as i32 + 2);
+        if self.final_pass && (offset < -128 || offset > 127) {
+            return Err(format!("Jump target out of range: {}", text));
+        }
+        Ok(offset as i8)
+    }
+
+    fn parse_instr(&self, text: &str) -> Result<Instruction, String> {
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (text[..space].to_lowercase(), text[space..].trim()),
+            None => (text.to_lowercase(), "")
+        };
+        let operands = split_operands(rest);
+        let normalised: Vec<String> = operands.iter().map(|o| normalise(o)).collect();
+        let ops: Vec<&str> = normalised.iter().map(|o| o.as_str()).collect();
+
+        let instr = match (mnemonic.as_str(), &ops[..]) {
+            ("nop", &[]) => NOP,
+            ("halt", &[]) => HALT,
+            ("stop", &[]) => STOP,
+            ("di", &[]) => DI,
+            ("ei", &[]) => EI,
+            ("daa", &[]) => DAA,
+            ("cpl", &[]) => CPL,
+            ("ccf", &[]) => CCF,
+            ("scf", &[]) => SCF,
+            ("rlca", &[]) => RLCA,
+            ("rla", &[]) => RLA,
+            ("rrca", &[]) => RRCA,
+            ("rra", &[]) => RRA,
+            ("reti", &[]) => RETI,
+            ("ret", &[]) => RET(Cond::None),
+            ("ret", &[c]) => RET(cond(c).ok_or(format!("Invalid condition: {}", c))?),
+
+            // 8-bit loads.
+            ("ld", &["a", "[hl+]"]) | ("ld", &["a", "[hli]"]) => LDI(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::HL)),
+            ("ld", &["[hl+]", "a"]) | ("ld", &["[hli]", "a"]) => LDI(Arg8::Ind(IndirectAddr::HL), Arg8::Reg(Reg8::A)),
+            ("ld", &["a", "[hl-]"]) | ("ld", &["a", "[hld]"]) => LDD(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::HL)),
+            ("ld", &["[hl-]", "a"]) | ("ld", &["[hld]", "a"]) => LDD(Arg8::Ind(IndirectAddr::HL), Arg8::Reg(Reg8::A)),
+            ("ld", &["a", "[bc]"]) => LD(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::BC)),
+            ("ld", &["a", "[de]"]) => LD(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::DE)),
+            ("ld", &["[bc]", "a"]) => LD(Arg8::Ind(IndirectAddr::BC), Arg8::Reg(Reg8::A)),
+            ("ld", &["[de]", "a"]) => LD(Arg8::Ind(IndirectAddr::DE), Arg8::Reg(Reg8::A)),
+            ("ld", &["a", "[c]"]) | ("ld", &["a", "[$ff00+c]"]) | ("ldh", &["a", "[c]"]) =>
+                LD(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::C)),
+            ("ld", &["[c]", "a"]) | ("ld", &["[$ff00+c]", "a"]) | ("ldh", &["[c]", "a"]) =>
+                LD(Arg8::Ind(IndirectAddr::C), Arg8::Reg(Reg8::A)),
+            ("ldh", &["a", m]) if is_memory(m) =>
+                LDH(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::Imm8((self.address(m)? & 0xFF) as u8))),
+            ("ldh", &[m, "a"]) if is_memory(m) =>
+                LDH(Arg8::Ind(IndirectAddr::Imm8((self.address(m)? & 0xFF) as u8)), Arg8::Reg(Reg8::A)),
+
+            // 16-bit loads.
+            ("ld", &["sp", "hl"]) => LD16(Arg16::Reg(Reg16::SP), Arg16::Reg(Reg16::HL)),
+            ("ld", &["hl", s]) if s.starts_with("sp+") || s.starts_with("sp-") =>
+                LDHL16(self.signed8(&s[2..])?),
+            ("ld", &[m, "sp"]) if is_memory(m) => LD16(Arg16::Ind(IndirectAddr::Imm16(self.address(m)?)), Arg16::Reg(Reg16::SP)),
+            ("ld", &[r, v]) if reg16(r).is_some() && r != "af" => LD16(reg16(r).unwrap(), Arg16::Imm(self.imm16(v)?)),
+
+            ("ld", &["a", m]) if is_memory(m) && reg8(m).is_none() =>
+                LD(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::Imm16(self.address(m)?))),
+            ("ld", &[m, "a"]) if is_memory(m) && reg8(m).is_none() =>
+                LD(Arg8::Ind(IndirectAddr::Imm16(self.address(m)?)), Arg8::Reg(Reg8::A)),
+            ("ld", &[o, i]) if reg8(o).is_some() => LD(reg8(o).unwrap(), self.src8(i)?),
+
+            ("push", &[r]) if reg16(r).is_some() && r != "sp" => PUSH(reg16(r).unwrap()),
+            ("pop", &[r]) if reg16(r).is_some() && r != "sp" => POP(reg16(r).unwrap()),
+
+            // Arithmetic. The A operand is optional.
+            ("add", &["hl", r]) => ADD16(reg16(r).ok_or(format!("Invalid operand: {}", r))?),
+            ("add", &["sp", v]) => ADD16SP(self.signed8(v)?),
+            (alu, &["a", i]) | (alu, &[i]) if is_alu(alu) => alu_instr(alu, self.src8(i)?),
+            ("inc", &[r]) if reg16(r).is_some() => INC16(reg16(r).unwrap()),
+            ("dec", &[r]) if reg16(r).is_some() => DEC16(reg16(r).unwrap()),
+            ("inc", &[r]) => INC(reg8(r).ok_or(format!("Invalid operand: {}", r))?),
+            ("dec", &[r]) => DEC(reg8(r).ok_or(format!("Invalid operand: {}", r))?),
+
+            // CB prefixed.
+            (op, &[r]) if is_shift(op) => {
+                let arg = reg8(r).ok_or(format!("Invalid operand: {}", r))?;
+                shift_instr(op, arg)
+            },
+            (op, &[b, r]) if op == "bit" || op == "set" || op == "res" => {
+                let bit = self.expr(b)?;
+                if bit < 0 || bit > 7 {
+                    return Err(format!("Invalid bit: {}", b));
+                }
+                let arg = reg8(r).ok_or(format!("Invalid operand: {}", r))?;
+                match op {
+                    "bit" => BIT(bit as u8, arg),
+                    "set" => SET(bit as u8, arg),
+                    _ => RES(bit as u8, arg)
+                }
+            },
+
+            // Control flow.
+            ("jp", &["hl"]) | ("jp", &["[hl]"]) => JP(Cond::None, Arg16::Ind(IndirectAddr::HL)),
+            ("jp", &[c, t]) => JP(cond(c).ok_or(format!("Invalid condition: {}", c))?, Arg16::Imm(self.imm16(t)?)),
+            ("jp", &[t]) => JP(Cond::None, Arg16::Imm(self.imm16(t)?)),
+            ("jr", &[c, t]) => JR(cond(c).ok_or(format!("Invalid condition: {}", c))?, self.jr_offset(t)?),
+            ("jr", &[t]) => JR(Cond::None, self.jr_offset(t)?),
+            ("call", &[c, t]) => CALL(cond(c).ok_or(format!("Invalid condition: {}", c))?, Arg16::Imm(self.imm16(t)?)),
+            ("call", &[t]) => CALL(Cond::None, Arg16::Imm(self.imm16(t)?)),
+            ("rst", &[v]) => {
+                let vector = self.expr(v)?;
+                if vector < 0 || vector > 0x38 || vector % 8 != 0 {
+                    return Err(format!("Invalid RST vector: {}", v));
+                }
+                RST(vector as u8)
+            },
+
+            _ => return Err(format!("Invalid instruction: {}", text))
+        };
+
+        Ok(instr)
+    }
+}
+
+// Lowercase register, condition and [register] operands so they can be matched, but keep labels
+// in their original case.
+fn normalise(operand: &str) -> String {
+    let compact = operand.replace(" ", "");
+    let lower = compact.to_lowercase();
+    let keyword = |name: &str| reg8(name).is_some() || reg16(name).is_some() || cond(name).is_some();
+    match lower.as_str() {
+        "[hl+]" | "[hli]" | "[hl-]" | "[hld]" | "[bc]" | "[de]" | "[c]" | "[$ff00+c]" => lower,
+        _ if keyword(&lower) => lower,
+        _ if lower.starts_with("sp+") || lower.starts_with("sp-") => format!("sp{}", &compact[2..]),
+        _ => compact
+    }
+}
+
+fn is_alu(mnemonic: &str) -> bool {
+    match mnemonic {
+        "add" | "adc" | "sub" | "sbc" | "and" | "or" | "xor" | "cp" => true,
+        _ => false
+    }
+}
+
+fn alu_instr(mnemonic: &str, arg: Arg8) -> Instruction {
+    match mnemonic {
+        "add" => ADD(arg),
+        "adc" => ADC(arg),
+        "sub" => SUB(arg),
+        "sbc" => SBC(arg),
+        "and" => AND(arg),
+        "or" => OR(arg),
+        "xor" => XOR(arg),
+        _ => CP(arg)
+    }
+}
+
+fn is_shift(mnemonic: &str) -> bool {
+    match mnemonic {
+        "rlc" | "rl" | "rrc" | "rr" | "sla" | "sra" | "swap" | "srl" => true,
+        _ => false
+    }
+}
+
+fn shift_instr(mnemonic: &str, arg: Arg8) -> Instruction {
+    match mnemonic {
+        "rlc" => RLC(arg),
+        "rl" => RL(arg),
+        "rrc" => RRC(arg),
+        "rr" => RR(arg),
+        "sla" => SLA(arg),
+        "sra" => SRA(arg),
+        "swap" => SWAP(arg),
+        _ => SRL(arg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::analyser::Analysis;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source, 0x150).unwrap().bytes
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(bytes("ld a, $05"), vec![0x3E, 0x05]);
+        assert_eq!(bytes("LD A, [HL+]"), vec![0x2A]);
+        assert_eq!(bytes("ld [$C000], a"), vec![0xEA, 0x00, 0xC0]);
+        assert_eq!(bytes("ldh [$FF44], a"), vec![0xE0, 0x44]);
+        assert_eq!(bytes("ldh a, [c]"), vec![0xF2]);
+        assert_eq!(bytes("ld hl, sp-$03"), vec![0xF8, 0xFD]);
+        assert_eq!(bytes("ld [$D000], sp"), vec![0x08, 0x00, 0xD0]);
+        assert_eq!(bytes("add a, [hl]"), vec![0x86]);
+        assert_eq!(bytes("cp $10"), vec![0xFE, 0x10]);
+        assert_eq!(bytes("add sp, -2"), vec![0xE8, 0xFE]);
+        assert_eq!(bytes("bit 7, h"), vec![0xCB, 0x7C]);
+        assert_eq!(bytes("jp nz, $1234"), vec![0xC2, 0x34, 0x12]);
+        assert_eq!(bytes("ret c"), vec![0xD8]);
+        assert_eq!(bytes("rst $38"), vec![0xFF]);
+        assert_eq!(bytes("push af"), vec![0xF5]);
+        assert!(assemble("ld [hl], [hl]", 0).is_err());
+        assert!(assemble("rst $09", 0).is_err());
+        assert!(assemble("ld a, $100", 0).is_err());
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let program = assemble("
+Start:
+    ld b, 3
+.loop:
+    dec b
+    jr nz, .loop       ; Backwards
+    jr Data            ; Forwards
+Data:
+    db $01, 2, \"hi\"
+    dw Start, @", 0x150).unwrap();
+        assert_eq!(program.label("Start"), Some(0x150));
+        assert_eq!(program.label("Start.loop"), Some(0x152));
+        assert_eq!(program.label("Data"), Some(0x157));
+        assert_eq!(program.bytes, vec![0x06, 0x03, 0x05, 0x20, 0xFD, 0x18, 0x00,
+                                       0x01, 0x02, b'h', b'i', 0x50, 0x01, 0x5D, 0x01]);
+        assert!(assemble("jr Missing", 0).is_err());
+        assert!(assemble("A:\nA:", 0).is_err());
+        // Data is range checked like instruction operands.
+        assert_eq!(assemble("db -1\ndw -1", 0).unwrap().bytes, vec![0xFF, 0xFF, 0xFF]);
+        assert!(assemble("db 300", 0).is_err());
+        assert!(assemble("dw $12345", 0).is_err());
+    }
+
+    #[test]
+    fn sections() {
+        let program = assemble("SECTION \"End\", ROM0[$3FFF]\nnop\nSECTION \"Bank 2\", ROMX[$7FFF], BANK[2]\nnop", 0).unwrap();
+        assert_eq!(program.bytes.len(), 0xC000);
+        assert_eq!(program.bytes[0x3FFF], 0x00);
+        assert!(assemble("SECTION \"x\", ROM0[$8000]", 0).is_err());
+        assert!(assemble("SECTION \"x\", ROMX[$3FFF], BANK[1]", 0).is_err());
+        // Code can't run past the end of the section.
+        assert!(assemble("SECTION \"x\", ROM0[$3FFF]\nnop\nnop", 0).is_err());
+        assert!(assemble("SECTION \"x\", ROMX[$7FFE], BANK[1]\njp $0150", 0).is_err());
+        assert!(assemble("ld a, 1", 0xFFFF).is_err());
+    }
+
+    #[test]
+    fn reassemble_analysis() {
+        // Assembling the analyser's output must reproduce the original ROM.
+        let mut rom = vec![0u8; 0x8000];
+        let program = assemble("
+    SECTION \"Entry\", ROM0[$0100]
+    nop
+    jp Main
+    SECTION \"Main\", ROM0[$0150]
+Main:
+    ld a, [$FF44]
+    ld hl, $C000
+    ld [hl+], a
+    call Sub
+    stop
+    jr Main
+Sub:
+    ld de, $4000
+    ld a, [de]
+    ret
+    SECTION \"Bank 1\", ROMX[$4000], BANK[1]
+    db $12, $34", 0).unwrap();
+        rom[..program.bytes.len()].copy_from_slice(&program.bytes);
+
+        let analysis = Analysis::from_rom(&rom, 2);
+        let source: String = (0..analysis.bank_count()).map(|bank| analysis.bank_source(bank)).collect();
+        let reassembled = assemble(&source, 0).unwrap();
+        assert_eq!(reassembled.bytes, rom);
+    }
+}
@@ -0,0 +1,172 @@
+/*
+    The CPU's view of the rest of the system. Memory is the real implementation, but the CPU can
+    be run against anything which implements Bus:
+
+    - FlatRam is 64KB of plain RAM, for testing the CPU in isolation.
+    - RecordingBus wraps another bus and records the bus activity of every cycle.
+*/
+
+pub trait Bus {
+    fn read_u8(&mut self, addr: u16) -> u8;
+    fn write_u8(&mut self, addr: u16, value: u8);
+
+    // Read without it counting as a bus access, e.g. for tracing.
+    fn peek_u8(&self, addr: u16) -> u8;
+
+    // Advance the rest of the hardware by a number of cycles.
+    fn tick(&mut self, cycles: u32);
+
+    // Hardware which the CPU talks to directly. The defaults suit a bus with nothing attached.
+    // This is called before each instruction is fetched, e.g. so that problems can be blamed on it.
+    fn begin_instruction(&mut self, _pc: u16, _sp: u16) {}
+
+    // The ROM bank mapped at an address, or None if the address isn't in cartridge ROM.
+    fn rom_bank(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    // Whether a selected joypad line is low, which wakes the CPU from STOP.
+    fn joypad_line_low(&self) -> bool {
+        false
+    }
+
+    // Called by STOP. Returns true if a CGB speed switch happened instead of stopping.
+    fn speed_switch(&mut self) -> bool {
+        false
+    }
+
+    // Called by STOP when not switching speed.
+    fn stop(&mut self) {}
+}
+
+// 64KB of plain RAM with nothing attached.
+pub struct FlatRam {
+    pub ram: Vec<u8>
+}
+
+impl FlatRam {
+    pub fn new() -> FlatRam {
+        FlatRam {
+            ram: vec![0; 0x10000]
+        }
+    }
+}
+
+impl Bus for FlatRam {
+    fn read_u8(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write_u8(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+
+    fn peek_u8(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn tick(&mut self, _cycles: u32) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusAccess {
+    Read { addr: u16, value: u8 },
+    Write { addr: u16, value: u8 }
+}
+
+// Records what happened on the bus during each M-cycle, which is None for internal cycles.
+// Accesses without a cycle of their own, i.e. before the first tick or a second access in the
+// same cycle, are a CPU timing bug and are recorded separately.
+pub struct RecordingBus<B: Bus> {
+    pub inner: B,
+    pub cycles: Vec<Option<BusAccess>>,
+    pub unclocked: Vec<BusAccess>
+}
+
+impl<B: Bus> RecordingBus<B> {
+    pub fn new(inner: B) -> RecordingBus<B> {
+        RecordingBus {
+            inner: inner,
+            cycles: Vec::new(),
+            unclocked: Vec::new()
+        }
+    }
+
+    fn record(&mut self, access: BusAccess) {
+        match self.cycles.last_mut() {
+            Some(cycle @ &mut None) => *cycle = Some(access),
+            _ => self.unclocked.push(access)
+        }
+    }
+}
+
+impl<B: Bus> Bus for RecordingBus<B> {
+    fn read_u8(&mut self, addr: u16) -> u8 {
+        let value = self.inner.read_u8(addr);
+        self.record(BusAccess::Read { addr: addr, value: value });
+        value
+    }
+
+    fn write_u8(&mut self, addr: u16, value: u8) {
+        self.inner.write_u8(addr, value);
+        self.record(BusAccess::Write { addr: addr, value: value });
+    }
+
+    fn peek_u8(&self, addr: u16) -> u8 {
+        self.inner.peek_u8(addr)
+    }
+
+    // The CPU ticks the bus once per M-cycle.
+    fn tick(&mut self, cycles: u32) {
+        self.cycles.push(None);
+        self.inner.tick(cycles);
+    }
+
+    fn begin_instruction(&mut self, pc: u16, sp: u16) {
+        self.inner.begin_instruction(pc, sp)
+    }
+
+    fn rom_bank(&self, addr: u16) -> Option<usize> {
+        self.inner.rom_bank(addr)
+    }
+
+    fn joypad_line_low(&self) -> bool {
+        self.inner.joypad_line_low()
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        self.inner.speed_switch()
+    }
+
+    fn stop(&mut self) {
+        self.inner.stop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recording() {
+        let mut bus = RecordingBus::new(FlatRam::new());
+        bus.inner.ram[0x100] = 0x42;
+        bus.tick(4);
+        assert_eq!(bus.read_u8(0x100), 0x42);
+        bus.tick(4);
+        bus.tick(4);
+        bus.write_u8(0xC000, 0x12);
+        assert_eq!(bus.peek_u8(0xC000), 0x12);
+        assert_eq!(bus.cycles, vec![
+            Some(BusAccess::Read { addr: 0x100, value: 0x42 }),
+            None,
+            Some(BusAccess::Write { addr: 0xC000, value: 0x12 })
+        ]);
+        assert_eq!(bus.unclocked, vec![]);
+
+        // A second access in the same cycle.
+        bus.read_u8(0x100);
+        assert_eq!(bus.cycles.len(), 3);
+        assert_eq!(bus.unclocked, vec![BusAccess::Read { addr: 0x100, value: 0x42 }]);
+    }
+}
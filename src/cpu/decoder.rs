@@ -3,14 +3,37 @@
 use cpu::Cond;
 use cpu::ops::Arg8;
 use cpu::ops::Arg16;
-use cpu::ops::Cont;
-use cpu::ops::Cont::*;
 use cpu::ops::Instruction;
 use cpu::ops::Instruction::*;
 use cpu::registers::Reg8::*;
 use cpu::registers::Reg16::*;
 use cpu::IndirectAddr;
 
+// Immediate data which follows an opcode in the instruction stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    None,           // No immediate data
+    Imm8,           // A single byte
+    Imm16,          // A little-endian word
+    Prefix          // The opcode is the CB prefix, the next byte selects from CB_TABLE
+}
+
+// An entry in one of the opcode tables. The instruction is a template in which any immediate data
+// is zero, use with_operand to fill it in once the operand bytes have been fetched.
+#[derive(Debug, Clone, Copy)]
+pub struct Opcode {
+    pub instr: Instruction,
+    pub operand: Operand,
+    pub prefixed: bool,     // Whether the entry is from CB_TABLE
+    pub length: u8,         // Total length in bytes, including any prefix
+    pub cycles: u8,         // M-cycles taken, or M-cycles when a conditional branch is not taken
+    pub cycles_taken: u8    // M-cycles when a conditional branch is taken
+}
+
+// Opcode tables, indexed by the opcode byte
+pub static BASE_TABLE: [Opcode; 256] = build_table(false);
+pub static CB_TABLE: [Opcode; 256] = build_table(true);
+
 /*
  * Examples for instruction decoding:
  *
@@ -23,50 +46,54 @@ use cpu::IndirectAddr;
  * ([0b10, 2] [#arg, 3] [0b110, 3]) => [Instr arg ]
  *  If opcode matches 0b10xxx110, then extract xxx as the argument and decode as Instr
  *
- * Instructions themselves can sometimes require more than 8 bits. Therefore, the [..] syntax can
- * instead build a function that constructions an instruction given more data. For example:
- *  - [Instr] becomes Done(Instr)
- *  - [Instr arg] becomes Done(Instr(arg))
- *  - [Instr a, b] becomes Done(Instr(a, b))
- *  - [Instr A#, arg] becomes Partial8(|data| Instr(Ind(IndirectAddr::Imm8(data)), arg))
- *  - [Instr arg, I16#] becomes Partial16(|data| Instr(arg, data))
+ * Instructions themselves can sometimes require more than 8 bits. Therefore, the [..] syntax
+ * also records which immediate data follows the opcode, using a zero placeholder in the
+ * instruction template. For example:
+ *  - [Instr] becomes (Instr, Operand::None)
+ *  - [Instr arg] becomes (Instr(arg), Operand::None)
+ *  - [Instr a, b] becomes (Instr(a, b), Operand::None)
+ *  - [Instr A#, arg] becomes (Instr(Ind(IndirectAddr::Imm8(0)), arg), Operand::Imm8)
+ *  - [Instr arg, I16#] becomes (Instr(arg, Arg16::Imm(0)), Operand::Imm16)
  */
 
 macro_rules! imm_type {
-    (A   -> $i: ident) => { Arg8::Ind(IndirectAddr::Imm8($i)) };
-    (I   -> $i: ident) => { Arg8::Imm($i) };
-    (A16 -> $i: ident) => { Arg16::Ind(IndirectAddr::Imm16($i)) };
-    (I16 -> $i: ident) => { Arg16::Imm($i) };
-    (SI  -> $i: ident) => { $i as i8 }
+    (A    -> $i: expr) => { Arg8::Ind(IndirectAddr::Imm8($i)) };
+    (I    -> $i: expr) => { Arg8::Imm($i) };
+    (A16B -> $i: expr) => { Arg8::Ind(IndirectAddr::Imm16($i)) }; // Byte at a 16-bit address
+    (A16  -> $i: expr) => { Arg16::Ind(IndirectAddr::Imm16($i)) };
+    (I16  -> $i: expr) => { Arg16::Imm($i) };
+    (SI   -> $i: expr) => { $i as i8 }
 }
 
 macro_rules! partial_type {
-    (A)   => { Partial8  };
-    (I)   => { Partial8  };
-    (SI)  => { Partial8  };
-    (I16) => { Partial16 };
-    (A16) => { Partial16 }
+    (A)    => { Operand::Imm8  };
+    (I)    => { Operand::Imm8  };
+    (SI)   => { Operand::Imm8  };
+    (A16B) => { Operand::Imm16 };
+    (I16)  => { Operand::Imm16 };
+    (A16)  => { Operand::Imm16 }
 }
 
-// A convenience macro that builds a Cont<Instruction> from an instruction mmemonic and arguments
+// A convenience macro that builds an (Instruction, Operand) pair from an instruction mmemonic and
+// arguments
 macro_rules! instr {
     ($mnem: ident) => {
-        Done($mnem)
+        ($mnem, Operand::None)
     };
     ($mnem: ident $t: ident#) => {
-        partial_type!($t)(Box::new(move |addr| $mnem(imm_type!($t -> addr))))
+        ($mnem(imm_type!($t -> 0)), partial_type!($t))
     };
     ($mnem: ident $op1: expr) => {
-        Done($mnem($op1))
+        ($mnem($op1), Operand::None)
     };
     ($mnem: ident $t: ident#, $op2: expr) => {
-        partial_type!($t)(Box::new(move |addr| $mnem(imm_type!($t -> addr), $op2)))
+        ($mnem(imm_type!($t -> 0), $op2), partial_type!($t))
     };
     ($mnem: ident $op1: expr, $t: ident#) => {
-        partial_type!($t)(Box::new(move |addr| $mnem($op1, imm_type!($t -> addr))))
+        ($mnem($op1, imm_type!($t -> 0)), partial_type!($t))
     };
     ($mnem: ident $op1: expr, $op2: expr) => {
-        Done($mnem($op1, $op2))
+        ($mnem($op1, $op2), Operand::None)
     };
 }
 
@@ -85,7 +112,7 @@ macro_rules! transform {
     // If the RHS is an expression in parentheses, then return it literally
     ($op: expr, () => ($leaf: expr)) => {$leaf};
 
-    // Otherwise, build the Cond<Instruction> using the instr! macro
+    // Otherwise, build the (Instruction, Operand) pair using the instr! macro
     ($op: expr, () => [$($leaf: tt)+]) => {instr!($($leaf)+)};
 
     // If we reach an argument [#ident, <size>] then extract it as ident
@@ -130,21 +157,21 @@ macro_rules! matches {
     };
 }
 
-// Transform rules into a series of if-else statements
+// Transform rules into a series of if-else statements. Op-codes which match no rule are illegal.
 macro_rules! match_rule {
     ($op: expr, $(($($t: tt)+) => $e: tt,)+) => {
         $( if matches!($op, $($t)+) {
                 transform!($op, ($($t)+) => $e)
             } else
-        )+ {panic!("No match: {}", $op)}
+        )+ {(ILLEGAL($op), Operand::None)}
     }
 }
 
-fn shift_left(operand: u8, by: u8) -> u8 {
+const fn shift_left(operand: u8, by: u8) -> u8 {
     operand << by
 }
 
-fn reg8(operand: u8) -> Arg8 {
+const fn reg8(operand: u8) -> Arg8 {
     match operand {
         0x0 => Arg8::Reg(B),
         0x1 => Arg8::Reg(C),
@@ -160,7 +187,7 @@ fn reg8(operand: u8) -> Arg8 {
 
 // PUSH and POP might use the AF register instead of SP
 // AF and SP are mutually exclusive for a given type of instruction
-fn reg16(operand: u8, has_af: bool) -> Arg16 {
+const fn reg16(operand: u8, has_af: bool) -> Arg16 {
     match operand {
          0x0 => Arg16::Reg(BC),
          0x1 => Arg16::Reg(DE),
@@ -171,7 +198,7 @@ fn reg16(operand: u8, has_af: bool) -> Arg16 {
     }
 }
 
-fn cond(operand: u8) -> Cond {
+const fn cond(operand: u8) -> Cond {
     match operand {
         0x0 => Cond::NZ,
         0x1 => Cond::Z,
@@ -181,8 +208,8 @@ fn cond(operand: u8) -> Cond {
     }
 }
 
-pub fn decode_cb(code: u8) -> Instruction {
-    let cont = match_rule!(code,
+pub const fn decode_cb(code: u8) -> Instruction {
+    let (instr, _) = match_rule!(code,
         // | opcode(5) | op(3)
         ([0b00000, 5] [#op, 3]) => [RLC  reg8(op)],
         ([0b00001, 5] [#op, 3]) => [RRC  reg8(op)],
@@ -197,13 +224,10 @@ pub fn decode_cb(code: u8) -> Instruction {
         ([0b10, 2] [#imm_op, 3] [#op, 3]) => [RES imm_op, reg8(op)],
         ([0b11, 2] [#imm_op, 3] [#op, 3]) => [SET imm_op, reg8(op)],
     );
-    match cont {
-        Done(i) => i,
-        _       => panic!("CB instructions should not take immediate data")
-    }
+    instr
 }
 
-pub fn decode(opcode: u8) -> Cont<Instruction> {
+pub const fn decode(opcode: u8) -> (Instruction, Operand) {
     match_rule!(opcode,
         (#0x76) => [HALT],
 
@@ -249,17 +273,17 @@ pub fn decode(opcode: u8) -> Cont<Instruction> {
         (#0x3F) => [CCF],
         (#0x37) => [SCF],
         (#0x00) => [NOP],
-        (#0x10) => [STOP],
+        (#0x10) => ((STOP, Operand::Imm8)),    // STOP is followed by a padding byte
         (#0xF3) => [DI],
         (#0xFB) => [EI],
 
         (#0x02) => [LD Arg8::Ind(IndirectAddr::BC), Arg8::Reg(A)],
         (#0x12) => [LD Arg8::Ind(IndirectAddr::DE), Arg8::Reg(A)],
-        (#0xEA) => [LD A#, Arg8::Reg(A)],
+        (#0xEA) => [LD A16B#, Arg8::Reg(A)],
         (#0xF2) => [LD Arg8::Reg(A), Arg8::Ind(IndirectAddr::C)],
 
         (#0x1A) => [LD Arg8::Reg(A), Arg8::Ind(IndirectAddr::DE)],
-        (#0xFA) => [LD Arg8::Reg(A), A#],
+        (#0xFA) => [LD Arg8::Reg(A), A16B#],
         (#0xE2) => [LD Arg8::Ind(IndirectAddr::C), Arg8::Reg(A)],
         (#0x0A) => [LD Arg8::Reg(A), Arg8::Ind(IndirectAddr::BC)],
 
@@ -285,35 +309,289 @@ pub fn decode(opcode: u8) -> Cont<Instruction> {
         (#0xFE) => [CP  I#],
 
         // rotates and shifts
-        (#0x0F) => [RRC Arg8::Reg(A)],
-        (#0x1f) => [RR Arg8::Reg(A)],
-        (#0xCB) => (Partial8(Box::new(|cb| decode_cb(cb)))),
+        (#0x0F) => [RRCA],
+        (#0x1F) => [RRA],
+        (#0xCB) => ((NOP, Operand::Prefix)),
 
         (#0xD9) => [RETI],
-        (#0x07) => [RLC Arg8::Reg(A)],
-        (#0x17) => [RL Arg8::Reg(A)],
-
-        // UNMAPPED:
-        (#0xD3) => [NOP],
-        (#0xDB) => [NOP],
-        (#0xDD) => [NOP],
-        (#0xE3) => [NOP],
-        (#0xE4) => [NOP],
-        (#0xEB) => [NOP],
-        (#0xEC) => [NOP],
-        (#0xED) => [NOP],
-        (#0xF4) => [NOP],
-        (#0xFC) => [NOP],
-        (#0xFD) => [NOP],
+        (#0x07) => [RLCA],
+        (#0x17) => [RLA],
+
+        // Unmapped opcodes lock up the CPU
+        (#0xD3) => [ILLEGAL 0xD3],
+        (#0xDB) => [ILLEGAL 0xDB],
+        (#0xDD) => [ILLEGAL 0xDD],
+        (#0xE3) => [ILLEGAL 0xE3],
+        (#0xE4) => [ILLEGAL 0xE4],
+        (#0xEB) => [ILLEGAL 0xEB],
+        (#0xEC) => [ILLEGAL 0xEC],
+        (#0xED) => [ILLEGAL 0xED],
+        (#0xF4) => [ILLEGAL 0xF4],
+        (#0xFC) => [ILLEGAL 0xFC],
+        (#0xFD) => [ILLEGAL 0xFD],
     )
 }
 
+// Fill in the immediate data of a template instruction from one of the opcode tables
+pub fn with_operand(instr: Instruction, data: u16) -> Instruction {
+    fn arg8(arg: Arg8, data: u16) -> Arg8 {
+        match arg {
+            Arg8::Imm(_) => Arg8::Imm(data as u8),
+            Arg8::Ind(IndirectAddr::Imm8(_)) => Arg8::Ind(IndirectAddr::Imm8(data as u8)),
+            Arg8::Ind(IndirectAddr::Imm16(_)) => Arg8::Ind(IndirectAddr::Imm16(data)),
+            _ => arg
+        }
+    }
+
+    fn arg16(arg: Arg16, data: u16) -> Arg16 {
+        match arg {
+            Arg16::Imm(_) => Arg16::Imm(data),
+            Arg16::Ind(IndirectAddr::Imm16(_)) => Arg16::Ind(IndirectAddr::Imm16(data)),
+            _ => arg
+        }
+    }
+
+    match instr {
+        LD(o, i)      => LD(arg8(o, data), arg8(i, data)),
+        LDH(o, i)     => LDH(arg8(o, data), arg8(i, data)),
+        LD16(o, i)    => LD16(arg16(o, data), arg16(i, data)),
+        LDHL16(_)     => LDHL16(data as i8),
+        ADD(i)        => ADD(arg8(i, data)),
+        ADC(i)        => ADC(arg8(i, data)),
+        SUB(i)        => SUB(arg8(i, data)),
+        SBC(i)        => SBC(arg8(i, data)),
+        AND(i)        => AND(arg8(i, data)),
+        OR(i)         => OR(arg8(i, data)),
+        XOR(i)        => XOR(arg8(i, data)),
+        CP(i)         => CP(arg8(i, data)),
+        ADD16SP(_)    => ADD16SP(data as i8),
+        JP(c, i)      => JP(c, arg16(i, data)),
+        JR(c, _)      => JR(c, data as i8),
+        CALL(c, i)    => CALL(c, arg16(i, data)),
+        _             => instr
+    }
+}
+
+// Extract the immediate data of an instruction, the inverse of with_operand
+fn immediate(instr: Instruction) -> u16 {
+    fn arg8(arg: Arg8) -> Option<u16> {
+        match arg {
+            Arg8::Imm(v) | Arg8::Ind(IndirectAddr::Imm8(v)) => Some(v as u16),
+            Arg8::Ind(IndirectAddr::Imm16(v)) => Some(v),
+            _ => None
+        }
+    }
+
+    fn arg16(arg: Arg16) -> Option<u16> {
+        match arg {
+            Arg16::Imm(v) | Arg16::Ind(IndirectAddr::Imm16(v)) => Some(v),
+            _ => None
+        }
+    }
+
+    let value = match instr {
+        LD(o, i) | LDH(o, i) => arg8(o).or(arg8(i)),
+        LD16(o, i) => arg16(o).or(arg16(i)),
+        ADD(i) | ADC(i) | SUB(i) | SBC(i) | AND(i) | OR(i) | XOR(i) | CP(i) => arg8(i),
+        LDHL16(v) | ADD16SP(v) | JR(_, v) => Some(v as u8 as u16),
+        JP(_, i) | CALL(_, i) => arg16(i),
+        _ => None
+    };
+    value.unwrap_or(0)
+}
+
+// Decode the instruction at the start of a byte slice, returning it along with its length in
+// bytes. Returns None if the slice ends before the instruction does.
+pub fn decode_slice(bytes: &[u8]) -> Option<(Instruction, usize)> {
+    let opcode = match bytes.first() {
+        Some(&code) => &BASE_TABLE[code as usize],
+        None => return None
+    };
+    let length = opcode.length as usize;
+    if bytes.len() < length {
+        return None;
+    }
+    let instr = match opcode.operand {
+        Operand::None   => opcode.instr,
+        Operand::Imm8   => with_operand(opcode.instr, bytes[1] as u16),
+        Operand::Imm16  => with_operand(opcode.instr, bytes[1] as u16 | (bytes[2] as u16) << 8),
+        Operand::Prefix => CB_TABLE[bytes[1] as usize].instr,
+    };
+    Some((instr, length))
+}
+
+// Encode an instruction as bytes. Returns None if the instruction has no encoding.
+pub fn encode(instr: Instruction) -> Option<Vec<u8>> {
+    find_opcode(instr).map(|(code, opcode)| {
+        let mut bytes = if opcode.prefixed { vec![0xCB, code] } else { vec![code] };
+        let data = immediate(instr);
+        match opcode.operand {
+            Operand::Imm8 => bytes.push(data as u8),
+            Operand::Imm16 => {
+                bytes.push((data & 0xFF) as u8);
+                bytes.push((data >> 8) as u8);
+            },
+            _ => {}
+        }
+        bytes
+    })
+}
+
+// Find the table entry which decodes to the given instruction, ignoring any immediate data. Returns
+// the opcode byte (which follows the CB prefix if the entry is prefixed) and the entry.
+pub fn find_opcode(instr: Instruction) -> Option<(u8, &'static Opcode)> {
+    let template = with_operand(instr, 0);
+    for table in &[&BASE_TABLE, &CB_TABLE] {
+        for (code, entry) in table.iter().enumerate() {
+            if entry.operand != Operand::Prefix && entry.instr == template {
+                return Some((code as u8, entry));
+            }
+        }
+    }
+    None
+}
+
+// Number of memory accesses made when reading or writing an argument
+const fn accesses8(arg: Arg8) -> u8 {
+    match arg {
+        Arg8::Ind(_) => 1,
+        _ => 0
+    }
+}
+
+const fn accesses16(arg: Arg16) -> u8 {
+    match arg {
+        Arg16::Ind(_) => 2,
+        _ => 0
+    }
+}
+
+// M-cycle timing of an instruction as (not taken, taken). Every byte of the instruction takes a
+// cycle to fetch, and every memory access or internal delay adds another.
+const fn timing(instr: Instruction, length: u8) -> (u8, u8) {
+    let extra = match instr {
+        LD(o, i) | LDH(o, i) => accesses8(o) + accesses8(i),
+        LDD(o, i) | LDI(o, i) => accesses8(o) + accesses8(i),
+        LD16(Arg16::Reg(SP), Arg16::Reg(HL)) => 1,
+        LD16(o, i) => accesses16(o) + accesses16(i),
+        LDHL16(_) => 1,
+        PUSH(_) => 3,
+        POP(_) => 2,
+        ADD(i) | ADC(i) | SUB(i) | SBC(i) | AND(i) | OR(i) | XOR(i) | CP(i) => accesses8(i),
+        INC(io) | DEC(io) => accesses8(io) * 2,
+        ADD16(_) | INC16(_) | DEC16(_) => 1,
+        ADD16SP(_) => 2,
+        RLC(io) | RL(io) | RRC(io) | RR(io) | SLA(io) | SRA(io) | SWAP(io) | SRL(io) => accesses8(io) * 2,
+        BIT(_, i) => accesses8(i),
+        SET(_, io) | RES(_, io) => accesses8(io) * 2,
+        RST(_) => 3,
+        RETI => 3,
+        _ => 0
+    };
+    let base = length + extra;
+
+    // Control flow. Taking a branch costs an internal cycle to load PC, and calls and returns
+    // additionally push or pop the return address.
+    match instr {
+        // STOP is listed as a single cycle, although the padding byte is still fetched.
+        STOP => (1, 1),
+        JP(_, Arg16::Ind(IndirectAddr::HL)) => (1, 1),
+        JP(Cond::None, _) | JR(Cond::None, _) => (base + 1, base + 1),
+        JP(_, _) | JR(_, _) => (base, base + 1),
+        CALL(Cond::None, _) => (base + 3, base + 3),
+        CALL(_, _) => (base, base + 3),
+        RET(Cond::None) => (base + 3, base + 3),
+        RET(_) => (base + 1, base + 4),
+        _ => (base, base)
+    }
+}
+
+const fn operand_length(operand: Operand) -> u8 {
+    match operand {
+        Operand::None => 0,
+        Operand::Imm8 | Operand::Prefix => 1,
+        Operand::Imm16 => 2
+    }
+}
+
+const fn table_entry(instr: Instruction, operand: Operand, prefixed: bool) -> Opcode {
+    let length = 1 + operand_length(operand) + if prefixed { 1 } else { 0 };
+    let (cycles, cycles_taken) = timing(instr, length);
+    Opcode {
+        instr: instr,
+        operand: operand,
+        prefixed: prefixed,
+        length: length,
+        cycles: cycles,
+        cycles_taken: cycles_taken
+    }
+}
+
+const fn operand8(arg: Arg8) -> Operand {
+    match arg {
+        Arg8::Imm(_) | Arg8::Ind(IndirectAddr::Imm8(_)) => Operand::Imm8,
+        Arg8::Ind(IndirectAddr::Imm16(_)) => Operand::Imm16,
+        _ => Operand::None
+    }
+}
+
+const fn operand16(arg: Arg16) -> Operand {
+    match arg {
+        Arg16::Imm(_) | Arg16::Ind(IndirectAddr::Imm16(_)) => Operand::Imm16,
+        _ => Operand::None
+    }
+}
+
+const fn either(first: Operand, second: Operand) -> Operand {
+    match first {
+        Operand::None => second,
+        _ => first
+    }
+}
+
+// Build the table entry for an instruction from its arguments, without searching the tables. The
+// result matches the entry the instruction was decoded from, which the tests below check for
+// every opcode.
+pub const fn entry_for(instr: Instruction) -> Opcode {
+    let operand = match instr {
+        LD(o, i) | LDH(o, i) => either(operand8(o), operand8(i)),
+        LD16(o, i) => either(operand16(o), operand16(i)),
+        ADD(i) | ADC(i) | SUB(i) | SBC(i) | AND(i) | OR(i) | XOR(i) | CP(i) => operand8(i),
+        LDHL16(_) | ADD16SP(_) | JR(_, _) => Operand::Imm8,
+        // STOP is followed by a padding byte
+        STOP => Operand::Imm8,
+        JP(_, i) | CALL(_, i) => operand16(i),
+        _ => Operand::None
+    };
+    let prefixed = match instr {
+        RLC(_) | RL(_) | RRC(_) | RR(_) | SLA(_) | SRA(_) | SWAP(_) | SRL(_) => true,
+        BIT(_, _) | SET(_, _) | RES(_, _) => true,
+        _ => false
+    };
+    table_entry(instr, operand, prefixed)
+}
+
+const fn build_table(cb: bool) -> [Opcode; 256] {
+    let mut table = [table_entry(NOP, Operand::None, false); 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = if cb {
+            table_entry(decode_cb(i as u8), Operand::None, true)
+        } else {
+            let (instr, operand) = decode(i as u8);
+            table_entry(instr, operand, false)
+        };
+        i += 1;
+    }
+    table
+}
+
 #[cfg(test)]
 mod test {
     use cpu::decoder;
+    use cpu::decoder::Operand;
     use cpu::ops::Instruction;
     use cpu::ops::Instruction::*;
-    use cpu::ops::Cont;
     use cpu::Cond;
     use cpu::ops::Arg8;
     use cpu::ops::Arg16;
@@ -322,11 +600,7 @@ mod test {
     use cpu::registers::Reg16::*;
 
     fn fetch_custom(op: u8) -> Instruction {
-        match decoder::decode(op) {
-            Cont::Partial8(f)  => f(0),
-            Cont::Partial16(f) => f(0),
-            Cont::Done(d)      => d,
-        }
+        decoder::BASE_TABLE[op as usize].instr
     }
 
     // Panics if any of the instructions is not handled in the decoder
@@ -357,6 +631,60 @@ mod test {
         assert_eq!(fetch_custom(0xFF), REF[0xFF]);
     }
 
+    #[test]
+    fn table_operands() {
+        assert_eq!(decoder::BASE_TABLE[0x00].operand, Operand::None);
+        assert_eq!(decoder::BASE_TABLE[0x3E].operand, Operand::Imm8);
+        assert_eq!(decoder::BASE_TABLE[0x21].operand, Operand::Imm16);
+        assert_eq!(decoder::BASE_TABLE[0xFA].operand, Operand::Imm16);
+        assert_eq!(decoder::BASE_TABLE[0xFA].length, 3);
+        assert_eq!(decoder::BASE_TABLE[0xCB].operand, Operand::Prefix);
+        assert_eq!(decoder::BASE_TABLE[0xCB].length, 2);
+        assert_eq!(decoder::CB_TABLE[0x7C].instr, BIT(7, Arg8::Reg(H)));
+        assert_eq!(decoder::CB_TABLE[0x7C].length, 2);
+    }
+
+    #[test]
+    fn entries_from_instructions() {
+        for table in &[&decoder::BASE_TABLE, &decoder::CB_TABLE] {
+            for entry in table.iter().filter(|entry| entry.operand != Operand::Prefix) {
+                let built = decoder::entry_for(entry.instr);
+                assert_eq!(built.operand, entry.operand, "{:?}", entry.instr);
+                assert_eq!(built.prefixed, entry.prefixed, "{:?}", entry.instr);
+                assert_eq!(built.length, entry.length, "{:?}", entry.instr);
+                assert_eq!(built.cycles, entry.cycles, "{:?}", entry.instr);
+                assert_eq!(built.cycles_taken, entry.cycles_taken, "{:?}", entry.instr);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        for code in 0..256 {
+            for &data in &[0x00u16, 0x12, 0xFF80, 0xC0DE] {
+                let bytes = [code as u8, (data & 0xFF) as u8, (data >> 8) as u8];
+                let (instr, length) = decoder::decode_slice(&bytes).unwrap();
+                // STOP discards its padding byte, which is always encoded as 0.
+                if instr == STOP && data & 0xFF != 0 {
+                    continue;
+                }
+                assert_eq!(decoder::encode(instr).unwrap(), &bytes[0..length], "{:?}", instr);
+            }
+        }
+        assert_eq!(decoder::decode_slice(&[0xC3, 0x50]), None);
+    }
+
+    #[test]
+    fn fill_operands() {
+        assert_eq!(decoder::with_operand(fetch_custom(0x3E), 0x12), LD(Arg8::Reg(A), Arg8::Imm(0x12)));
+        assert_eq!(decoder::with_operand(fetch_custom(0x08), 0xC123),
+                   LD16(Arg16::Ind(IndirectAddr::Imm16(0xC123)), Arg16::Reg(SP)));
+        assert_eq!(decoder::with_operand(fetch_custom(0xEA), 0xC123),
+                   LD(Arg8::Ind(IndirectAddr::Imm16(0xC123)), Arg8::Reg(A)));
+        assert_eq!(decoder::with_operand(fetch_custom(0x20), 0xFE), JR(Cond::NZ, -2));
+        assert_eq!(decoder::with_operand(fetch_custom(0xCD), 0x1234), CALL(Cond::None, Arg16::Imm(0x1234)));
+    }
+
     // Decode reference output (adapted from the manual):
     // The opcode is the index within the array
     static REF: &'static [Instruction] = &[
@@ -367,7 +695,7 @@ mod test {
         INC(Arg8::Reg(B)),
         DEC(Arg8::Reg(B)),
         LD(Arg8::Reg(B), Arg8::Imm(0)),
-        RLC(Arg8::Reg(A)),
+        RLCA,
         LD16(Arg16::Ind(IndirectAddr::Imm16(0)), Arg16::Reg(SP)),
         ADD16(Arg16::Reg(BC)),
         LD(Arg8::Reg(A), Arg8::Ind(IndirectAddr::BC)),
@@ -375,7 +703,7 @@ mod test {
         INC(Arg8::Reg(C)),
         DEC(Arg8::Reg(C)),
         LD(Arg8::Reg(C), Arg8::Imm(0)),
-        RRC(Arg8::Reg(A)),
+        RRCA,
         STOP,
         LD16(Arg16::Reg(DE), Arg16::Imm(0)),
         LD(Arg8::Ind(IndirectAddr::DE), Arg8::Reg(A)),
@@ -383,7 +711,7 @@ mod test {
         INC(Arg8::Reg(D)),
         DEC(Arg8::Reg(D)),
         LD(Arg8::Reg(D), Arg8::Imm(0)),
-        RL(Arg8::Reg(A)),
+        RLA,
         JR(Cond::None, 0),
         ADD16(Arg16::Reg(DE)),
         LD(Arg8::Reg(A), Arg8::Ind(IndirectAddr::DE)),
@@ -391,7 +719,7 @@ mod test {
         INC(Arg8::Reg(E)),
         DEC(Arg8::Reg(E)),
         LD(Arg8::Reg(E), Arg8::Imm(0)),
-        RR(Arg8::Reg(A)),
+        RRA,
         JR(Cond::NZ, 0),
         LD16(Arg16::Reg(HL), Arg16::Imm(0)),
         LDI(Arg8::Ind(IndirectAddr::HL), Arg8::Reg(A)),
@@ -571,7 +899,7 @@ mod test {
         RET(Cond::NC),
         POP(Arg16::Reg(DE)),
         JP(Cond::NC, Arg16::Imm(0)),
-        NOP,
+        ILLEGAL(0xD3),
         CALL(Cond::NC, Arg16::Imm(0)),
         PUSH(Arg16::Reg(DE)),
         SUB(Arg8::Imm(0)),
@@ -579,41 +907,41 @@ mod test {
         RET(Cond::C),
         RETI,
         JP(Cond::C, Arg16::Imm(0)),
-        NOP,
+        ILLEGAL(0xDB),
         CALL(Cond::C, Arg16::Imm(0)),
-        NOP,
+        ILLEGAL(0xDD),
         SBC(Arg8::Imm(0)),
         RST(24),
         LDH(Arg8::Ind(IndirectAddr::Imm8(0)), Arg8::Reg(A)),
         POP(Arg16::Reg(HL)),
         LD(Arg8::Ind(IndirectAddr::C), Arg8::Reg(A)),
-        NOP,
-        NOP,
+        ILLEGAL(0xE3),
+        ILLEGAL(0xE4),
         PUSH(Arg16::Reg(HL)),
         AND(Arg8::Imm(0)),
         RST(32),
         ADD16SP(0),
         JP(Cond::None, Arg16::Ind(IndirectAddr::HL)),
-        LD(Arg8::Ind(IndirectAddr::Imm8(0)), Arg8::Reg(A)),
-        NOP,
-        NOP,
-        NOP,
+        LD(Arg8::Ind(IndirectAddr::Imm16(0)), Arg8::Reg(A)),
+        ILLEGAL(0xEB),
+        ILLEGAL(0xEC),
+        ILLEGAL(0xED),
         XOR(Arg8::Imm(0)),
         RST(40),
         LDH(Arg8::Reg(A), Arg8::Ind(IndirectAddr::Imm8(0))),
         POP(Arg16::Reg(AF)),
         LD(Arg8::Reg(A), Arg8::Ind(IndirectAddr::C)),
         DI,
-        NOP,
+        ILLEGAL(0xF4),
         PUSH(Arg16::Reg(AF)),
         OR(Arg8::Imm(0)),
         RST(48),
         LDHL16(0),
         LD16(Arg16::Reg(SP), Arg16::Reg(HL)),
-        LD(Arg8::Reg(A), Arg8::Ind(IndirectAddr::Imm8(0))),
+        LD(Arg8::Reg(A), Arg8::Ind(IndirectAddr::Imm16(0))),
         EI,
-        NOP,
-        NOP,
+        ILLEGAL(0xFC),
+        ILLEGAL(0xFD),
         CP(Arg8::Imm(0)),
         RST(56)
     ];
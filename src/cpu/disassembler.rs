@@ -0,0 +1,339 @@
+/* Disassembler producing RGBDS syntax */
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use cpu::{Cond, IndirectAddr};
+use cpu::bus::Bus;
+use cpu::fetcher::Fetcher;
+use cpu::ops::*;
+use cpu::ops::Instruction::*;
+use cpu::registers::*;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    // Print JR targets as absolute addresses instead of relative to the instruction (@+n).
+    pub absolute_jumps: bool
+}
+
+// A single line of a listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "${:04X}  {:<9} {}", self.addr, bytes.join(" "), self.text)
+    }
+}
+
+// Fetches instruction bytes through an arbitrary read function
+struct Reader<F: Fn(u16) -> u8> {
+    read: F,
+    pc: u16
+}
+
+impl<F: Fn(u16) -> u8> Fetcher for Reader<F> {
+    fn fetch_u8(&mut self) -> u8 {
+        let byte = (self.read)(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        byte
+    }
+
+    fn fetch_u16(&mut self) -> u16 {
+        let low = self.fetch_u8() as u16;
+        let high = self.fetch_u8() as u16;
+        (high << 8) | low
+    }
+}
+
+fn reg8(r: Reg8) -> &'static str {
+    match r {
+        Reg8::A => "a", Reg8::F => "f", Reg8::B => "b", Reg8::C => "c",
+        Reg8::D => "d", Reg8::E => "e", Reg8::H => "h", Reg8::L => "l"
+    }
+}
+
+fn reg16(r: Reg16) -> &'static str {
+    match r {
+        Reg16::AF => "af", Reg16::BC => "bc", Reg16::DE => "de",
+        Reg16::HL => "hl", Reg16::SP => "sp", Reg16::PC => "pc"
+    }
+}
+
+fn cond(c: Cond) -> &'static str {
+    match c {
+        Cond::None => "", Cond::NZ => "nz", Cond::Z => "z", Cond::NC => "nc", Cond::C => "c"
+    }
+}
+
+fn signed(v: i8) -> String {
+    if v < 0 {
+        format!("-${:02X}", -(v as i16))
+    } else {
+        format!("${:02X}", v)
+    }
+}
+
+fn indirect(addr: IndirectAddr) -> String {
+    match addr {
+        IndirectAddr::BC => "[bc]".to_string(),
+        IndirectAddr::DE => "[de]".to_string(),
+        IndirectAddr::HL => "[hl]".to_string(),
+        IndirectAddr::C => "[c]".to_string(),
+        IndirectAddr::Imm8(n) => format!("[${:04X}]", 0xFF00 | n as u16),
+        IndirectAddr::Imm16(n) => format!("[${:04X}]", n)
+    }
+}
+
+fn arg8(arg: Arg8) -> String {
+    match arg {
+        Arg8::Reg(r) => reg8(r).to_string(),
+        Arg8::Ind(addr) => indirect(addr),
+        Arg8::Imm(v) => format!("${:02X}", v)
+    }
+}
+
+fn arg16(arg: Arg16) -> String {
+    match arg {
+        Arg16::Reg(r) => reg16(r).to_string(),
+        Arg16::Ind(addr) => indirect(addr),
+        Arg16::Imm(v) => format!("${:04X}", v)
+    }
+}
+
+// Prefix an operand with a condition, if there is one
+fn with_cond(c: Cond, operand: String) -> String {
+    match c {
+        Cond::None => operand,
+        _ => format!("{}, {}", cond(c), operand)
+    }
+}
+
+// Format an instruction with no knowledge of its address. Relative jumps are printed relative to
+// the start of the instruction, which RGBDS writes as @.
+pub fn format(instr: &Instruction) -> String {
+    format_instr(instr, None)
+}
+
+// Format an instruction located at the given address.
+pub fn format_at(instr: &Instruction, addr: u16, options: &Options) -> String {
+    format_instr(instr, if options.absolute_jumps { Some(addr) } else { None })
+}
+
+// Format a jump or call with its target replaced by a label.
+pub fn format_with_label(instr: &Instruction, label: &str) -> String {
+    match *instr {
+        JP(c, Arg16::Imm(_)) => format!("jp {}", with_cond(c, label.to_string())),
+        JR(c, _) => format!("jr {}", with_cond(c, label.to_string())),
+        CALL(c, Arg16::Imm(_)) => format!("call {}", with_cond(c, label.to_string())),
+        _ => format(instr)
+    }
+}
+
+// The offset of a relative jump is from the end of the 2 byte instruction.
+fn jr_target(offset: i8, addr: Option<u16>) -> String {
+    let target = offset as i32 + 2;
+    match addr {
+        Some(addr) => format!("${:04X}", (addr as i32 + target) as u16),
+        None if target < 0 => format!("@-{}", -target),
+        None => format!("@+{}", target)
+    }
+}
+
+fn format_instr(instr: &Instruction, addr: Option<u16>) -> String {
+    match *instr {
+        // 8-bit load
+        LD(o @ Arg8::Ind(IndirectAddr::C), i) | LD(o, i @ Arg8::Ind(IndirectAddr::C)) =>
+            format!("ldh {}, {}", arg8(o), arg8(i)),
+        LD(o, i) => format!("ld {}, {}", arg8(o), arg8(i)),
+        LDD(Arg8::Ind(IndirectAddr::HL), i) => format!("ld [hl-], {}", arg8(i)),
+        LDD(o, _) => format!("ld {}, [hl-]", arg8(o)),
+        LDI(Arg8::Ind(IndirectAddr::HL), i) => format!("ld [hl+], {}", arg8(i)),
+        LDI(o, _) => format!("ld {}, [hl+]", arg8(o)),
+        LDH(o, i) => format!("ldh {}, {}", arg8(o), arg8(i)),
+        // 16-bit load
+        LD16(o, i) => format!("ld {}, {}", arg16(o), arg16(i)),
+        LDHL16(v) => format!("ld hl, sp{}{}", if v < 0 { "" } else { "+" }, signed(v)),
+        PUSH(i) => format!("push {}", arg16(i)),
+        POP(o) => format!("pop {}", arg16(o)),
+        // 8-bit arithmetic
+        ADD(i) => format!("add a, {}", arg8(i)),
+        ADC(i) => format!("adc a, {}", arg8(i)),
+        SUB(i) => format!("sub a, {}", arg8(i)),
+        SBC(i) => format!("sbc a, {}", arg8(i)),
+        AND(i) => format!("and a, {}", arg8(i)),
+        OR(i) => format!("or a, {}", arg8(i)),
+        XOR(i) => format!("xor a, {}", arg8(i)),
+        CP(i) => format!("cp a, {}", arg8(i)),
+        INC(io) => format!("inc {}", arg8(io)),
+        DEC(io) => format!("dec {}", arg8(io)),
+        // 16-bit arithmetic
+        ADD16(i) => format!("add hl, {}", arg16(i)),
+        ADD16SP(v) => format!("add sp, {}", signed(v)),
+        INC16(io) => format!("inc {}", arg16(io)),
+        DEC16(io) => format!("dec {}", arg16(io)),
+        // misc
+        NOP => "nop".to_string(),
+        DAA => "daa".to_string(),
+        CPL => "cpl".to_string(),
+        CCF => "ccf".to_string(),
+        SCF => "scf".to_string(),
+        HALT => "halt".to_string(),
+        STOP => "stop".to_string(),
+        ILLEGAL(op) => format!("db ${:02X}", op),
+        EI => "ei".to_string(),
+        DI => "di".to_string(),
+        // rotate and shift
+        RLCA => "rlca".to_string(),
+        RLA => "rla".to_string(),
+        RRCA => "rrca".to_string(),
+        RRA => "rra".to_string(),
+        RLC(io) => format!("rlc {}", arg8(io)),
+        RL(io) => format!("rl {}", arg8(io)),
+        RRC(io) => format!("rrc {}", arg8(io)),
+        RR(io) => format!("rr {}", arg8(io)),
+        SLA(io) => format!("sla {}", arg8(io)),
+        SRA(io) => format!("sra {}", arg8(io)),
+        SWAP(io) => format!("swap {}", arg8(io)),
+        SRL(io) => format!("srl {}", arg8(io)),
+        // bit manipulation
+        BIT(b, i) => format!("bit {}, {}", b, arg8(i)),
+        SET(b, io) => format!("set {}, {}", b, arg8(io)),
+        RES(b, io) => format!("res {}, {}", b, arg8(io)),
+        // control
+        JP(Cond::None, Arg16::Ind(IndirectAddr::HL)) => "jp hl".to_string(),
+        JP(c, dest) => format!("jp {}", with_cond(c, arg16(dest))),
+        JR(c, offset) => format!("jr {}", with_cond(c, jr_target(offset, addr))),
+        CALL(c, dest) => format!("call {}", with_cond(c, arg16(dest))),
+        RST(v) => format!("rst ${:02X}", v),
+        RET(Cond::None) => "ret".to_string(),
+        RET(c) => format!("ret {}", cond(c)),
+        RETI => "reti".to_string(),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format(self))
+    }
+}
+
+// Disassemble an inclusive range of addresses linearly, reading bytes with the given function.
+pub fn disassemble<F: Fn(u16) -> u8>(read: F, range: RangeInclusive<u16>, options: &Options) -> Vec<Line> {
+    // Addresses are tracked as u32 so that a range ending at 0xFFFF doesn't wrap around.
+    let end = *range.end() as u32;
+    let mut addr = *range.start() as u32;
+    let mut lines = Vec::new();
+    let mut reader = Reader { read: read, pc: *range.start() };
+    while addr <= end {
+        let instr = reader.fetch_instr();
+        let length = reader.pc.wrapping_sub(addr as u16) as u32;
+
+        // An instruction which runs off the end of the range is printed as data.
+        if addr + length > end + 1 {
+            for a in addr..end + 1 {
+                let byte = (reader.read)(a as u16);
+                lines.push(Line { addr: a as u16, bytes: vec![byte], text: format!("db ${:02X}", byte) });
+            }
+            break;
+        }
+
+        let bytes = (0..length).map(|i| (reader.read)((addr + i) as u16)).collect();
+        lines.push(Line { addr: addr as u16, bytes: bytes, text: format_at(&instr, addr as u16, options) });
+        addr += length;
+    }
+    lines
+}
+
+// Disassemble a raw ROM image. The first byte of data is located at base.
+pub fn disassemble_slice(data: &[u8], base: u16, options: &Options) -> Vec<Line> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let end = base as usize + data.len() - 1;
+    let end = if end > 0xFFFF { 0xFFFF } else { end as u16 };
+    disassemble(|addr| data.get(addr.wrapping_sub(base) as usize).cloned().unwrap_or(0), base..=end, options)
+}
+
+// Disassemble a range of the address space without side effects on the hardware.
+pub fn disassemble_memory<B: Bus>(bus: &B, range: RangeInclusive<u16>, options: &Options) -> Vec<Line> {
+    disassemble(|addr| bus.peek_u8(addr), range, options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::decoder;
+    use cpu::bus::FlatRam;
+
+    #[test]
+    fn format_instructions() {
+        assert_eq!(format(&LDH(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::Imm8(0x05)))), "ldh a, [$FF05]");
+        assert_eq!(format(&LD(Arg8::Ind(IndirectAddr::Imm16(0xC000)), Arg8::Reg(Reg8::A))), "ld [$C000], a");
+        assert_eq!(format(&LD(Arg8::Reg(Reg8::B), Arg8::Imm(0x2A))), "ld b, $2A");
+        assert_eq!(format(&LDI(Arg8::Reg(Reg8::A), Arg8::Ind(IndirectAddr::HL))), "ld a, [hl+]");
+        assert_eq!(format(&LDD(Arg8::Ind(IndirectAddr::HL), Arg8::Reg(Reg8::A))), "ld [hl-], a");
+        assert_eq!(format(&LD(Arg8::Ind(IndirectAddr::C), Arg8::Reg(Reg8::A))), "ldh [c], a");
+        assert_eq!(format(&LDHL16(-3)), "ld hl, sp-$03");
+        assert_eq!(format(&ADD16SP(16)), "add sp, $10");
+        assert_eq!(format(&CP(Arg8::Ind(IndirectAddr::HL))), "cp a, [hl]");
+        assert_eq!(format(&BIT(7, Arg8::Reg(Reg8::H))), "bit 7, h");
+        assert_eq!(format(&JP(Cond::None, Arg16::Ind(IndirectAddr::HL))), "jp hl");
+        assert_eq!(format(&CALL(Cond::NC, Arg16::Imm(0x0150))), "call nc, $0150");
+        assert_eq!(format(&RET(Cond::Z)), "ret z");
+        assert_eq!(format(&RST(0x38)), "rst $38");
+        assert_eq!(format(&JR(Cond::NZ, -5)), "jr nz, @-3");
+        assert_eq!(format(&JR(Cond::None, 0)), "jr @+2");
+        assert_eq!(format_with_label(&JR(Cond::C, 7), "Loop"), "jr c, Loop");
+        assert_eq!(format_with_label(&CALL(Cond::None, Arg16::Imm(0x4000)), "Func"), "call Func");
+    }
+
+    #[test]
+    fn format_every_opcode() {
+        for code in 0..256 {
+            assert!(!format(&decoder::BASE_TABLE[code].instr).is_empty());
+            assert!(!format(&decoder::CB_TABLE[code].instr).is_empty());
+        }
+    }
+
+    #[test]
+    fn listing() {
+        let rom = [0x3E, 0x05, 0xCB, 0x7C, 0x20, 0xFA, 0xC3, 0x50, 0x01, 0xFA];
+        let lines = disassemble_slice(&rom, 0x0150, &Options { absolute_jumps: true });
+        let text: Vec<String> = lines.iter().map(|l| l.text.clone()).collect();
+        assert_eq!(text, vec!["ld a, $05", "bit 7, h", "jr nz, $0150", "jp $0150", "db $FA"]);
+        assert_eq!(lines[1].addr, 0x0152);
+        assert_eq!(lines[1].bytes, vec![0xCB, 0x7C]);
+        assert_eq!(format!("{}", lines[2]), "$0154  20 FA     jr nz, $0150");
+
+        let lines = disassemble_slice(&rom[4..6], 0x0154, &Options::default());
+        assert_eq!(lines[0].text, "jr nz, @-4");
+        assert!(disassemble_slice(&[], 0x0150, &Options::default()).is_empty());
+    }
+
+    #[test]
+    fn listing_end_of_memory() {
+        let mut ram = FlatRam::new();
+        ram.write_u8(0xFFFD, 0x3E);
+        ram.write_u8(0xFFFE, 0x05);
+        ram.write_u8(0xFFFF, 0x3C);
+        let lines = disassemble_memory(&ram, 0xFFFD..=0xFFFF, &Options::default());
+        let text: Vec<String> = lines.iter().map(|l| l.text.clone()).collect();
+        assert_eq!(text, vec!["ld a, $05", "inc a"]);
+        assert_eq!(lines[1].addr, 0xFFFF);
+
+        // The operand of an instruction at the end of memory would wrap around, so it's data.
+        ram.write_u8(0xFFFF, 0x3E);
+        let lines = disassemble_memory(&ram, 0xFFFE..=0xFFFF, &Options::default());
+        let text: Vec<String> = lines.iter().map(|l| l.text.clone()).collect();
+        assert_eq!(text, vec!["dec b", "db $3E"]);
+
+        // The same goes for a ROM image which ends there.
+        let lines = disassemble_slice(&[0xC3], 0xFFFF, &Options::default());
+        assert_eq!(lines[0].text, "db $C3");
+    }
+}
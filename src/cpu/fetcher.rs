@@ -1,24 +1,24 @@
 /* Fetch instructions */
 
 use cpu::ops::Instruction;
-use cpu::ops::Cont;
 use cpu::decoder;
+use cpu::decoder::Operand;
 
 pub trait Fetcher {
     fn fetch_u8(&mut self) -> u8;
     fn fetch_u16(&mut self) -> u16;
 
     fn fetch_instr(&mut self) -> Instruction {
-        let instr = self.fetch_u8();
+        let opcode = &decoder::BASE_TABLE[self.fetch_u8() as usize];
 
-        // The decoded instruction is either some partial
-        // instruction which needs further data (a single or double word),
-        // or a 'done' instruction which is ready to be fed to the execution
-        // unit
-        match decoder::decode(instr) {
-            Cont::Partial8(f)  => f(self.fetch_u8()),
-            Cont::Partial16(f) => f(self.fetch_u16()),
-            Cont::Done(d)      => d,
+        // The table entry says how much immediate data follows the opcode. The immediate is
+        // patched into the entry's template instruction, and the CB prefix selects an entry
+        // from the second table instead.
+        match opcode.operand {
+            Operand::None   => opcode.instr,
+            Operand::Imm8   => decoder::with_operand(opcode.instr, self.fetch_u8() as u16),
+            Operand::Imm16  => decoder::with_operand(opcode.instr, self.fetch_u16()),
+            Operand::Prefix => decoder::CB_TABLE[self.fetch_u8() as usize].instr,
         }
     }
 }
@@ -1,21 +1,31 @@
-use std::rc::Rc;
-use std::cell::RefCell;
 use memory::*;
-use cpu::{Cond, IndirectAddr};
+use cpu::bus::Bus;
+use cpu::{Cond, CpuEvent, IndirectAddr};
 use cpu::registers::*;
 use cpu::ops::*;
 use cpu::fetcher::*;
+use cpu::trace::Tracer;
+use model::Model;
+
+// M-cycles the CPU is paused for after STOP switches speed, while the clock settles
+const SPEED_SWITCH_CYCLES: u32 = 2050;
 
 // CPU Data
-pub struct Cpu {
+pub struct Cpu<B: Bus> {
     pub running: bool,
-    memory: Rc<RefCell<Memory>>,
+    bus: B,
     pub regs: Registers,
     pub clock: Clock,
     pub last_instr_time: u16,
 
     interrupts_enabled: bool,
-    halted: bool
+    halted: bool,
+    stopped: bool,
+    locked: bool,
+    breakpoints: bool,
+
+    events: Vec<CpuEvent>,
+    tracer: Option<Tracer>
 }
 
 pub struct Clock {
@@ -23,7 +33,7 @@ pub struct Clock {
     pub t: u32  // 1.0kHz
 }
 
-fn get_address(cpu: &Cpu, a: &IndirectAddr) -> u16 {
+fn get_address<B: Bus>(cpu: &Cpu<B>, a: &IndirectAddr) -> u16 {
     match *a {
         IndirectAddr::BC => cpu.regs.bc(),
         IndirectAddr::DE => cpu.regs.de(),
@@ -34,46 +44,139 @@ fn get_address(cpu: &Cpu, a: &IndirectAddr) -> u16 {
     }
 }
 
-impl Cpu {
-    pub fn new(memory: Rc<RefCell<Memory>>) -> Cpu {
+impl<B: Bus> Cpu<B> {
+    pub fn new(bus: B) -> Cpu<B> {
         Cpu {
             running: true,
-            memory: memory,
-            regs: Registers::new(true),
+            bus: bus,
+            // Emulator sets the registers for its model once the cartridge is loaded.
+            regs: Model::Dmg.post_boot_registers(false),
             clock: Clock {
                 m: 0,
                 t: 0
             },
             last_instr_time: 0,
             interrupts_enabled: false,
-            halted: false
+            halted: false,
+            stopped: false,
+            locked: false,
+            breakpoints: false,
+            events: Vec::new(),
+            tracer: None
         }
     }
 
     pub fn tick(&mut self) {
+        // After an illegal opcode, the CPU never executes anything again, but the rest of the
+        // hardware keeps running.
+        if self.locked {
+            self.internal_cycle();
+            return;
+        }
+
+        // No clocks run while stopped, until a selected joypad line goes low.
+        if self.stopped {
+            if !self.bus.joypad_line_low() {
+                return;
+            }
+            self.stopped = false;
+        }
+
         self.check_for_interrupt();
 
         if self.halted {
-            // Increase cycle count.
+            // Time still passes while halted.
+            self.internal_cycle();
         } else {
-            if self.regs.pc == 0x100 {
-                self.memory.borrow_mut().set_boot_mode(false);
-                println!("status: Finished booting");
-            }
-
             self.last_instr_time = 0;
-            //let pc = self.regs.pc;
+            self.bus.begin_instruction(self.regs.pc, self.regs.sp);
+            if self.tracer.is_some() {
+                self.trace_instr();
+            }
             let instr = self.fetch_instr();
-            //let state = self.dump_state_small();
             self.dispatch(instr);
-            //println!("0x{:04x}\t{}\t{}\t{:?}", pc, state, self.last_instr_time, instr);
-            self.clock.m = self.clock.m.wrapping_add(self.last_instr_time as u32);
-            self.clock.t = self.clock.t.wrapping_add(self.last_instr_time as u32 * 4);
         }
     }
 
+    // Instruction tracing
+    pub fn start_trace(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn stop_trace(&mut self) -> Result<(), String> {
+        match self.tracer.take() {
+            Some(mut tracer) => {
+                println!("status: Wrote {} trace lines", tracer.line_count());
+                tracer.flush()
+            },
+            None => Ok(())
+        }
+    }
+
+    fn trace_instr(&mut self) {
+        let pc = self.regs.pc;
+        let result = {
+            let tracer = self.tracer.as_mut().unwrap();
+            if !tracer.wants(pc, self.bus.rom_bank(pc)) {
+                return;
+            }
+            // Peek at memory, as this is not a bus access.
+            let mut pcmem = [0u8; 4];
+            for (i, byte) in pcmem.iter_mut().enumerate() {
+                *byte = self.bus.peek_u8(pc.wrapping_add(i as u16));
+            }
+            tracer.trace(&self.regs, pcmem)
+        };
+        let finished = self.tracer.as_ref().map_or(true, |t| t.is_finished());
+        let failed = match result {
+            Err(e) => { println!("WARNING: {}", e); true },
+            Ok(()) => false
+        };
+        if failed || finished {
+            if let Err(e) = self.stop_trace() {
+                println!("WARNING: {}", e);
+            }
+        }
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    pub fn set_interrupts_enabled(&mut self, enabled: bool) {
+        self.interrupts_enabled = enabled;
+    }
+
+    // Whether LD B,B reports a breakpoint event. Games execute it too, so this is only turned on
+    // for test ROMs and debugging.
+    pub fn set_breakpoints(&mut self, enabled: bool) {
+        self.breakpoints = enabled;
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    // Take the events reported since the last call.
+    pub fn take_events(&mut self) -> Vec<CpuEvent> {
+        self.events.drain(..).collect()
+    }
+
     fn check_for_interrupt(&mut self) {
-        let interrupt_register = self.mem_read_u8(INTERRUPTS_ENABLED_REG);
+        // Checking for interrupts is not a bus access, so doesn't take a cycle.
+        let interrupt_register = self.bus.peek_u8(INTERRUPTS_ENABLED_REG);
         if interrupt_register & INTERRUPT_ENABLE_VBLANK != 0 {
             //println!("INTERRUPT_ENABLE_VBLANK")
         } else if interrupt_register & INTERRUPT_ENABLE_LCDC != 0 {
@@ -100,8 +203,11 @@ impl Cpu {
 
     fn push_u16(&mut self, value: u16) {
         //println!("push {:x}", self.regs.sp);
-        self.regs.sp = self.regs.sp.wrapping_sub(2);
-        unborrow!(self.mem_write_u16(self.regs.sp, value));
+        // The high byte is written first.
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        unborrow!(self.mem_write_u8(self.regs.sp, (value >> 8) as u8));
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        unborrow!(self.mem_write_u8(self.regs.sp, value as u8));
     }
 
     fn pop_u16(&mut self) -> u16 {
@@ -111,40 +217,47 @@ impl Cpu {
         result
     }
 
-    // Cycles
-    fn read_cycle(&mut self) {
+    // Cycles. Each one advances the rest of the hardware by one M-cycle before the CPU accesses
+    // the bus, so memory accesses happen at the correct point within an instruction.
+    fn cycle(&mut self) {
         self.last_instr_time += 1;
+        self.clock.m = self.clock.m.wrapping_add(1);
+        self.clock.t = self.clock.t.wrapping_add(4);
+        self.bus.tick(4);
+    }
+
+    fn read_cycle(&mut self) {
+        self.cycle();
     }
 
     fn write_cycle(&mut self) {
-        self.last_instr_time += 1;
+        self.cycle();
     }
 
     fn internal_cycle(&mut self) {
-        self.last_instr_time += 1;
+        self.cycle();
     }
 
     // Memory reading helper functions
     fn mem_read_u8(&mut self, addr: u16) -> u8 {
         self.read_cycle();
-        self.memory.borrow().read_u8(addr)
+        self.bus.read_u8(addr)
     }
 
     fn mem_read_u16(&mut self, addr: u16) -> u16 {
-        self.read_cycle();
-        self.read_cycle();
-        self.memory.borrow().read_u16(addr)
+        let low = self.mem_read_u8(addr) as u16;
+        let high = self.mem_read_u8(addr.wrapping_add(1)) as u16;
+        (high << 8) | low
     }
 
     fn mem_write_u8(&mut self, addr: u16, data: u8) {
         self.write_cycle();
-        self.memory.borrow_mut().write_u8(addr, data);
+        self.bus.write_u8(addr, data);
     }
 
     fn mem_write_u16(&mut self, addr: u16, data: u16) {
-        self.write_cycle();
-        self.write_cycle();
-        self.memory.borrow_mut().write_u16(addr, data);
+        self.mem_write_u8(addr, data as u8);
+        self.mem_write_u8(addr.wrapping_add(1), (data >> 8) as u8);
     }
 
     pub fn dump_state(&self) {
@@ -174,7 +287,7 @@ impl Cpu {
     }
 }
 
-impl Fetcher for Cpu {
+impl<B: Bus> Fetcher for Cpu<B> {
     fn fetch_u8(&mut self) -> u8 {
         let byte = unborrow!(self.mem_read_u8(self.regs.pc));
         self.regs.pc += 1;
@@ -189,7 +302,7 @@ impl Fetcher for Cpu {
 }
     
 // Interpreter implementation of the CPU ops defined in the ops module
-impl CpuOps for Cpu {
+impl<B: Bus> CpuOps for Cpu<B> {
     fn read_arg8(&mut self, arg: Arg8) -> u8 {
         match arg {
             Arg8::Reg(r) => match r {
@@ -275,6 +388,9 @@ impl CpuOps for Cpu {
     }
 
     fn ld(&mut self, o: Arg8, i: Arg8) {
+        if self.breakpoints && o == Arg8::Reg(Reg8::B) && i == Arg8::Reg(Reg8::B) {
+            unborrow!(self.events.push(CpuEvent::Breakpoint { addr: self.regs.pc.wrapping_sub(1) }));
+        }
         let value = self.read_arg8(i);
         self.write_arg8(o, value);
     }
@@ -510,7 +626,14 @@ impl CpuOps for Cpu {
     }
 
     fn stop(&mut self) {
-        println!("STOPPED");
+        if self.bus.speed_switch() {
+            for _ in 0..SPEED_SWITCH_CYCLES {
+                self.internal_cycle();
+            }
+            return;
+        }
+        self.bus.stop();
+        self.stopped = true;
     }
 
     fn ei(&mut self) {
@@ -522,6 +645,26 @@ impl CpuOps for Cpu {
     }
 
     // rotate and shift
+    fn rlca(&mut self) {
+        self.rlc(Arg8::Reg(Reg8::A));
+        self.regs.flag(Flag::Z, false);
+    }
+
+    fn rla(&mut self) {
+        self.rl(Arg8::Reg(Reg8::A));
+        self.regs.flag(Flag::Z, false);
+    }
+
+    fn rrca(&mut self) {
+        self.rrc(Arg8::Reg(Reg8::A));
+        self.regs.flag(Flag::Z, false);
+    }
+
+    fn rra(&mut self) {
+        self.rr(Arg8::Reg(Reg8::A));
+        self.regs.flag(Flag::Z, false);
+    }
+
     fn rlc(&mut self, io: Arg8) {
         // Rotate left, update carry flag
         let value = self.read_arg8(io);
@@ -630,6 +773,11 @@ impl CpuOps for Cpu {
 
     // control
     fn jp(&mut self, cond: Cond, dest: Arg16) {
+        // JP HL loads PC from HL directly, without reading memory or an extra cycle.
+        if dest == Arg16::Ind(IndirectAddr::HL) {
+            self.regs.pc = self.regs.hl();
+            return;
+        }
         let dest_addr = self.read_arg16(dest);
         if self.check_condition(cond) {
             self.regs.pc = dest_addr;
@@ -648,20 +796,23 @@ impl CpuOps for Cpu {
         if self.check_condition(cond) {
             // The current value of the program counter is the _next_ instruction, so push that to
             // the stack.
+            self.internal_cycle();
             unborrow!(self.push_u16(self.regs.pc));
             self.regs.pc = self.read_arg16(dest);
-            self.internal_cycle();
         }
     }
 
     fn rst(&mut self, offset: u8) {
+        self.internal_cycle();
         unborrow!(self.push_u16(self.regs.pc));
         self.regs.pc = offset as u16;
-        self.internal_cycle();
     }
 
     fn ret(&mut self, cond: Cond) {
-        self.internal_cycle();
+        // Only conditional returns spend a cycle checking the condition.
+        if cond != Cond::None {
+            self.internal_cycle();
+        }
         if self.check_condition(cond) {
             self.regs.pc = self.pop_u16();
             self.internal_cycle();
@@ -672,18 +823,23 @@ impl CpuOps for Cpu {
         self.ret(Cond::None);
         self.ei();
     }
+
+    fn illegal(&mut self, opcode: u8) {
+        self.locked = true;
+        unborrow!(self.events.push(CpuEvent::IllegalOpcode { addr: self.regs.pc.wrapping_sub(1), opcode: opcode }));
+    }
 }
 
 // Test cases
 #[cfg(test)]
 mod test {
-    use std::rc::Rc;
-    use std::cell::RefCell;
     use memory::Memory;
     use super::*;
-    use cpu::ops::*;
     use cpu::registers::Reg8::*;
     use cpu::registers::Reg16::*;
+    use cpu::assembler::assemble;
+    use cartridge::Cartridge;
+    use model::Model;
 
     fn test_u8() -> u8 {
         // 0x90
@@ -695,13 +851,32 @@ mod test {
         47628u16
     }
 
-    fn init_cpu() -> Cpu {
-        Cpu::new(Rc::new(RefCell::new(Memory::new())))
+    fn init_cpu() -> Cpu<Memory> {
+        Cpu::new(Memory::new(Model::Dmg))
+    }
+
+    // Assemble a program at 0x100, load it as a cartridge and run it until PC reaches the Done
+    // label. CGB programs run on a CGB.
+    fn run_program(source: &str) -> Cpu<Memory> {
+        let source = format!("SECTION \"Test\", ROM0[$0100]\n{}", source);
+        let program = assemble(&source, 0).unwrap();
+        let done = program.label("Done").unwrap();
+        let cartridge = Cartridge::from_rom(program.bytes).unwrap();
+        let mut memory = Memory::new(if cartridge.is_cgb_enabled() { Model::Cgb } else { Model::Dmg });
+        memory.load_cartridge(cartridge);
+        let mut cpu = Cpu::new(memory);
+        for _ in 0..1000 {
+            if cpu.regs.pc == done {
+                return cpu;
+            }
+            cpu.tick();
+        }
+        panic!("Program did not reach Done");
     }
 
     #[test]
     fn ld_a_then_b() {
-        let mut cpu = &mut init_cpu();
+        let cpu = &mut init_cpu();
         cpu.ld(Arg8::Reg(A), Arg8::Imm(test_u8()));
         cpu.ld(Arg8::Reg(B), Arg8::Reg(A));
         assert_eq!(cpu.regs.a, test_u8());
@@ -710,10 +885,187 @@ mod test {
 
     #[test]
     fn ld16_bc_then_de() {
-        let mut cpu = &mut init_cpu();
+        let cpu = &mut init_cpu();
         cpu.ld16(Arg16::Reg(BC), Arg16::Imm(test_u16()));
         cpu.ld16(Arg16::Reg(DE), Arg16::Reg(BC));
         assert_eq!(cpu.regs.bc(), test_u16());
         assert_eq!(cpu.regs.bc(), cpu.regs.de());
     }
+
+    #[test]
+    fn rotate_a_resets_zero() {
+        let cpu = &mut init_cpu();
+        // RLC A sets Z for a zero result, but RLCA always resets it.
+        cpu.regs.a = 0;
+        cpu.rlc(Arg8::Reg(A));
+        assert!(cpu.regs.get_flag(Flag::Z));
+        cpu.rlca();
+        assert!(!cpu.regs.get_flag(Flag::Z));
+
+        cpu.regs.a = 0x80;
+        cpu.rla();
+        assert_eq!(cpu.regs.a, 0x00);
+        assert!(!cpu.regs.get_flag(Flag::Z));
+        assert!(cpu.regs.get_flag(Flag::C));
+        cpu.rra();
+        assert_eq!(cpu.regs.a, 0x80);
+        cpu.rrca();
+        assert_eq!(cpu.regs.a, 0x40);
+        assert!(!cpu.regs.get_flag(Flag::Z));
+    }
+
+    #[test]
+    fn sum_loop() {
+        let cpu = run_program("
+    xor a
+    ld b, 5
+.loop:
+    add a, b
+    dec b
+    jr nz, .loop
+    ld [$C000], a
+    ld hl, $C000
+    ld c, [hl]
+Done:
+    jr Done");
+        assert_eq!(cpu.regs.a, 15);
+        assert_eq!(cpu.regs.c, 15);
+        assert_eq!(cpu.regs.b, 0);
+    }
+
+    #[test]
+    fn call_and_return() {
+        let cpu = run_program("
+    ld sp, $DFFF
+    ld de, $1234
+    call Swap
+Done:
+    jr Done
+Swap:
+    push de
+    pop bc
+    ret");
+        assert_eq!(cpu.regs.bc(), 0x1234);
+        assert_eq!(cpu.regs.sp, 0xDFFF);
+    }
+
+    #[test]
+    fn instruction_timing() {
+        // Every bus access and internal cycle advances the clock by one M-cycle.
+        let cpu = run_program("
+    ld sp, $DFFF        ; 3
+    ld hl, Target       ; 3
+    call Sub            ; 6
+    jp hl               ; 1
+Target:
+    push bc             ; 4
+    pop de              ; 3
+    ld a, [$C000]       ; 4
+    ld [$C001], a       ; 4
+    ld hl, $C002        ; 3
+    ld [hl], 0          ; 3
+    inc [hl]            ; 3
+    bit 0, [hl]         ; 3
+    ret z               ; 2 (not taken, bit 0 of [hl] is now set)
+Done:
+    jr Done
+Sub:
+    ret                 ; 4");
+        assert_eq!(cpu.clock.m, 3 + 3 + 6 + 4 + 1 + 4 + 3 + 4 + 4 + 3 + 3 + 3 + 3 + 2);
+        assert_eq!(cpu.clock.t, cpu.clock.m * 4);
+    }
+
+    #[test]
+    fn push_writes_high_byte_first() {
+        let mut cpu = run_program("
+    ld sp, $D000
+    ld bc, $1234
+    push bc
+Done:
+    jr Done");
+        assert_eq!(cpu.regs.sp, 0xCFFE);
+        assert_eq!(cpu.mem_read_u8(0xCFFF), 0x12);
+        assert_eq!(cpu.mem_read_u8(0xCFFE), 0x34);
+    }
+
+    #[test]
+    fn double_speed_switch() {
+        let cpu = run_program("
+    jp Start
+    SECTION \"Header\", ROM0[$0143]
+    db $80              ; CGB
+    SECTION \"Main\", ROM0[$0150]
+Start:
+    ld a, 1
+    ldh [$FF4D], a
+    stop
+    ldh a, [$FF4D]
+    ld b, a
+    xor a
+    ldh [$FF04], a
+    nop
+    nop
+    nop
+Done:
+    jr Done");
+        assert_eq!(cpu.regs.b, 0xFE);
+        // 11 M-cycles up to and including STOP run at normal speed. The pause while switching and
+        // the 11 M-cycles after it run at double speed.
+        assert_eq!(cpu.clock.m, 22 + 2050);
+        assert_eq!(cpu.bus().cycles(), 11 * 4 + (2050 + 11) * 2);
+    }
+
+    #[test]
+    fn stop_sleeps() {
+        let mut cpu = run_program("
+    ld a, $20
+    ldh [$FF00], a      ; Select the direction row
+    stop
+Done:
+    jr Done");
+        cpu.tick();
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.regs.pc, 0x106);
+        assert_eq!(cpu.bus().read_u8(0xFF04), 0);
+
+        // No buttons are pressed, so the CPU stays asleep.
+        let clock = cpu.clock.m;
+        cpu.tick();
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.clock.m, clock);
+    }
+
+    #[test]
+    fn breakpoints_are_opt_in() {
+        let mut cpu = run_program("
+Done:
+    ld b, b
+    ld b, b");
+        cpu.tick();
+        assert_eq!(cpu.take_events(), vec![]);
+        cpu.set_breakpoints(true);
+        cpu.tick();
+        assert_eq!(cpu.take_events(), vec![CpuEvent::Breakpoint { addr: 0x101 }]);
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up() {
+        let mut cpu = run_program("
+    ld a, 1
+Done:
+    db $D3
+    ld a, 2");
+        cpu.tick();
+        assert!(cpu.is_locked());
+        assert_eq!(cpu.take_events(), vec![CpuEvent::IllegalOpcode { addr: 0x102, opcode: 0xD3 }]);
+
+        // Time passes, but nothing else is executed.
+        let clock = cpu.clock.m;
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.regs.a, 1);
+        assert_eq!(cpu.regs.pc, 0x103);
+        assert_eq!(cpu.clock.m, clock + 2);
+        assert!(cpu.take_events().is_empty());
+    }
 }
@@ -1,10 +1,40 @@
-mod registers;
+pub mod registers;
 mod ops;
 mod fetcher;
 mod decoder;
 
 // Implementations
 pub mod interpreter;
+pub mod bus;
+
+// Tools
+pub mod disassembler;
+pub mod analyser;
+pub mod assembler;
+pub mod trace;
+pub mod trace_diff;
+
+use std::fmt;
+
+// Events reported by the CPU for frontends and debuggers to surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuEvent {
+    // An unmapped opcode was executed and the CPU has locked up
+    IllegalOpcode { addr: u16, opcode: u8 },
+    // LD B,B was executed, which test ROMs and debuggers use as a software breakpoint
+    Breakpoint { addr: u16 }
+}
+
+impl fmt::Display for CpuEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuEvent::IllegalOpcode { addr, opcode } =>
+                write!(f, "Illegal opcode 0x{:02X} at 0x{:04X}, CPU locked up", opcode, addr),
+            CpuEvent::Breakpoint { addr } =>
+                write!(f, "Breakpoint at 0x{:04X}", addr)
+        }
+    }
+}
 
 // Control Conditions
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -39,6 +39,8 @@
 use cpu::Cond;
 use cpu::IndirectAddr;
 use cpu::registers::*;
+use cpu::decoder;
+use cpu::decoder::Opcode;
 
 // Instruction arguments
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -55,13 +57,6 @@ pub enum Arg16 {
     Imm(u16)
 }
 
-// Instruction decoding is implemented in a continuation passing style.
-pub enum Cont<R> {
-    Partial8(Box<Fn(u8) -> R>),
-    Partial16(Box<Fn(u16) -> R>),
-    Done(R)
-}
-
 // Synchronised with the trait below
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
@@ -102,6 +97,10 @@ pub enum Instruction {
     EI,                 // EI
     DI,                 // DI
     // rotate and shift
+    RLCA,               // RLCA         (RLC A, but always resets the zero flag)
+    RLA,                // RLA          (RL A, but always resets the zero flag)
+    RRCA,               // RRCA         (RRC A, but always resets the zero flag)
+    RRA,                // RRA          (RR A, but always resets the zero flag)
     RLC(Arg8),          // RLC inout
     RL(Arg8),           // RL inout
     RRC(Arg8),          // RRC inout
@@ -121,6 +120,71 @@ pub enum Instruction {
     RST(u8),            // RST n
     RET(Cond),          // RET / RET cond
     RETI,               // RETI
+    // unmapped
+    ILLEGAL(u8),        // Unmapped opcode, which locks up the CPU
+}
+
+// Static instruction metadata, matching the decoder's opcode tables
+impl Instruction {
+    fn opcode(&self) -> Opcode {
+        decoder::entry_for(*self)
+    }
+
+    // Length in bytes, including any prefix and immediate data
+    pub fn length(&self) -> u8 {
+        self.opcode().length
+    }
+
+    // M-cycles taken to execute, or M-cycles when a conditional branch is not taken
+    pub fn cycles(&self) -> u8 {
+        self.opcode().cycles
+    }
+
+    // M-cycles taken to execute when a conditional branch is taken
+    pub fn cycles_taken(&self) -> u8 {
+        self.opcode().cycles_taken
+    }
+
+    pub fn flags_read(&self) -> FlagSet {
+        use cpu::ops::Instruction::*;
+        fn cond_flags(cond: Cond) -> FlagSet {
+            match cond {
+                Cond::None => FlagSet::NONE,
+                Cond::NZ | Cond::Z => FlagSet::Z,
+                Cond::NC | Cond::C => FlagSet::C
+            }
+        }
+        match *self {
+            ADC(_) | SBC(_)                 => FlagSet::C,
+            RLA | RRA | RL(_) | RR(_)       => FlagSet::C,
+            CCF                             => FlagSet::C,
+            DAA                             => FlagSet::N | FlagSet::H | FlagSet::C,
+            PUSH(Arg16::Reg(Reg16::AF))     => FlagSet::ALL,
+            JP(c, _) | JR(c, _)             => cond_flags(c),
+            CALL(c, _) | RET(c)             => cond_flags(c),
+            _                               => FlagSet::NONE
+        }
+    }
+
+    pub fn flags_written(&self) -> FlagSet {
+        use cpu::ops::Instruction::*;
+        match *self {
+            ADD(_) | ADC(_) | SUB(_) | SBC(_)           => FlagSet::ALL,
+            AND(_) | OR(_) | XOR(_) | CP(_)             => FlagSet::ALL,
+            INC(_) | DEC(_)                             => FlagSet::Z | FlagSet::N | FlagSet::H,
+            ADD16(_)                                    => FlagSet::N | FlagSet::H | FlagSet::C,
+            ADD16SP(_) | LDHL16(_)                      => FlagSet::ALL,
+            DAA                                         => FlagSet::Z | FlagSet::H | FlagSet::C,
+            CPL                                         => FlagSet::N | FlagSet::H,
+            CCF | SCF                                   => FlagSet::N | FlagSet::H | FlagSet::C,
+            RLCA | RLA | RRCA | RRA                     => FlagSet::ALL,
+            RLC(_) | RL(_) | RRC(_) | RR(_)             => FlagSet::ALL,
+            SLA(_) | SRA(_) | SWAP(_) | SRL(_)          => FlagSet::ALL,
+            BIT(_, _)                                   => FlagSet::Z | FlagSet::N | FlagSet::H,
+            POP(Arg16::Reg(Reg16::AF))                  => FlagSet::ALL,
+            _                                           => FlagSet::NONE
+        }
+    }
 }
     
 pub trait CpuOps {
@@ -165,6 +229,10 @@ pub trait CpuOps {
     fn ei(&mut self);
     fn di(&mut self);
     // rotate and shift
+    fn rlca(&mut self);
+    fn rla(&mut self);
+    fn rrca(&mut self);
+    fn rra(&mut self);
     fn rlc(&mut self, io: Arg8);
     fn rl(&mut self, io: Arg8);
     fn rrc(&mut self, io: Arg8);
@@ -184,6 +252,8 @@ pub trait CpuOps {
     fn rst(&mut self, offset: u8);
     fn ret(&mut self, cond: Cond);
     fn reti(&mut self);
+    // unmapped
+    fn illegal(&mut self, opcode: u8);
 
     // dispatch an instruction to the trait methods
     fn dispatch(&mut self, instr: Instruction) {
@@ -220,6 +290,10 @@ pub trait CpuOps {
             STOP        => self.stop(),
             EI          => self.ei(),
             DI          => self.di(),
+            RLCA        => self.rlca(),
+            RLA         => self.rla(),
+            RRCA        => self.rrca(),
+            RRA         => self.rra(),
             RLC(io)     => self.rlc(io),
             RL(io)      => self.rl(io),
             RRC(io)     => self.rrc(io),
@@ -237,6 +311,107 @@ pub trait CpuOps {
             RST(v)      => self.rst(v),
             RET(c)      => self.ret(c),
             RETI        => self.reti(),
+            ILLEGAL(op) => self.illegal(op),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cpu::decoder;
+    use cpu::Cond;
+    use cpu::ops::*;
+    use cpu::registers::Reg8::*;
+    use cpu::registers::Reg16::*;
+
+    // Timing tables from the Pan Docs / gbdev opcode tables, in M-cycles. Conditional branches list
+    // the not-taken time here and the taken time below. Zero marks illegal opcodes and the prefix.
+    static BASE_CYCLES: [u8; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    static BASE_CYCLES_TAKEN: [(u8, u8); 16] = [
+        (0x20, 3), (0x28, 3), (0x30, 3), (0x38, 3), // JR cc
+        (0xC0, 5), (0xC8, 5), (0xD0, 5), (0xD8, 5), // RET cc
+        (0xC2, 4), (0xCA, 4), (0xD2, 4), (0xDA, 4), // JP cc
+        (0xC4, 6), (0xCC, 6), (0xD4, 6), (0xDC, 6), // CALL cc
+    ];
+
+    fn cb_cycles(code: u8) -> u8 {
+        match (code >> 6, code & 0x7) {
+            (1, 6) => 3, // BIT b, (HL)
+            (_, 6) => 4, // Read-modify-write (HL)
+            _ => 2
+        }
+    }
+
+    #[test]
+    fn base_timing_matches_published_table() {
+        let mut mismatches = Vec::new();
+        for code in 0..256 {
+            let expected = BASE_CYCLES[code];
+            if expected == 0 {
+                continue;
+            }
+            let instr = decoder::BASE_TABLE[code].instr;
+            let taken = BASE_CYCLES_TAKEN.iter()
+                .find(|&&(c, _)| c as usize == code)
+                .map_or(expected, |&(_, t)| t);
+            if instr.cycles() != expected || instr.cycles_taken() != taken {
+                mismatches.push(format!("{:02X} {:?}: {}/{} expected {}/{}",
+                    code, instr, instr.cycles(), instr.cycles_taken(), expected, taken));
+            }
+        }
+        assert!(mismatches.is_empty(), "Timing mismatches:\n{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn cb_timing_matches_published_table() {
+        for code in 0..256 {
+            let instr = decoder::CB_TABLE[code].instr;
+            assert_eq!(instr.cycles(), cb_cycles(code as u8), "CB {:02X} {:?}", code, instr);
+            assert_eq!(instr.length(), 2);
         }
     }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(Instruction::NOP.length(), 1);
+        assert_eq!(Instruction::LD(Arg8::Reg(A), Arg8::Imm(5)).length(), 2);
+        assert_eq!(Instruction::LDH(Arg8::Reg(A), Arg8::Ind(IndirectAddr::Imm8(5))).length(), 2);
+        assert_eq!(Instruction::LD(Arg8::Reg(A), Arg8::Ind(IndirectAddr::Imm16(0xC000))).length(), 3);
+        assert_eq!(Instruction::CALL(Cond::NZ, Arg16::Imm(0x1234)).length(), 3);
+        assert_eq!(Instruction::RLCA.length(), 1);
+        assert_eq!(Instruction::STOP.length(), 2);
+        assert_eq!(Instruction::RLC(Arg8::Reg(A)).length(), 2);
+    }
+
+    #[test]
+    fn flags() {
+        let adc = Instruction::ADC(Arg8::Reg(B));
+        assert_eq!(adc.flags_read(), FlagSet::C);
+        assert_eq!(adc.flags_written(), FlagSet::ALL);
+        let jr = Instruction::JR(Cond::NZ, 4);
+        assert!(jr.flags_read().contains(Flag::Z));
+        assert!(!jr.flags_read().contains(Flag::C));
+        assert_eq!(jr.flags_written(), FlagSet::NONE);
+        assert_eq!(Instruction::INC16(Arg16::Reg(HL)).flags_written(), FlagSet::NONE);
+        assert_eq!(Instruction::POP(Arg16::Reg(AF)).flags_written(), FlagSet::ALL);
+        assert_eq!(Instruction::POP(Arg16::Reg(BC)).flags_written(), FlagSet::NONE);
+    }
 }
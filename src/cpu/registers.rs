@@ -9,6 +9,8 @@
     3-0  -     -   -    Not used (always zero)
 */
 
+use std::ops::BitOr;
+
 pub struct Registers {
     pub a: u8,
     pub f: u8,
@@ -37,6 +39,31 @@ pub enum Flag {
     Z, N, H, C
 }
 
+// A set of flags, using the same bit layout as the flag register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlagSet(pub u8);
+
+impl FlagSet {
+    pub const NONE: FlagSet = FlagSet(0);
+    pub const Z: FlagSet    = FlagSet(0b1000_0000);
+    pub const N: FlagSet    = FlagSet(0b0100_0000);
+    pub const H: FlagSet    = FlagSet(0b0010_0000);
+    pub const C: FlagSet    = FlagSet(0b0001_0000);
+    pub const ALL: FlagSet  = FlagSet(0b1111_0000);
+
+    pub fn contains(&self, f: Flag) -> bool {
+        (self.0 >> Registers::select_flag(f)) & 1 == 1
+    }
+}
+
+impl BitOr for FlagSet {
+    type Output = FlagSet;
+
+    fn bitor(self, other: FlagSet) -> FlagSet {
+        FlagSet(self.0 | other.0)
+    }
+}
+
 macro_rules! read_reg_pair {
     ($regs:expr, $h:ident, $l:ident) => {
         (($regs.$h as u16) << 8) | $regs.$l as u16
@@ -51,33 +78,20 @@ macro_rules! write_reg_pair {
 }
 
 impl Registers {
-    pub fn new(skip_boot: bool) -> Registers {
-        if skip_boot {
-            Registers {
-                a: 0x01,
-                f: 0xB0,
-                b: 0x00,
-                c: 0x13,
-                d: 0x00,
-                e: 0xD8,
-                h: 0x01,
-                l: 0x4D,
-                pc: 0x0100,
-                sp: 0xFFFE,
-            }
-        } else {
-            Registers {
-                a: 0,
-                f: 0,
-                b: 0,
-                c: 0,
-                d: 0,
-                e: 0,
-                h: 0,
-                l: 0,
-                pc: 0,
-                sp: 0,
-            }
+    // The registers at power on. See Model::post_boot_registers for the state the boot ROM
+    // leaves behind.
+    pub fn new() -> Registers {
+        Registers {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            pc: 0,
+            sp: 0,
         }
     }
 
@@ -141,35 +155,35 @@ mod test {
 
     #[test]
     fn set_z_flag() {
-        let mut r = Registers::new(false);
+        let mut r = Registers::new();
         r.flag(Flag::Z, true);
         assert_eq!(r.f, 0b10000000)
     }
 
     #[test]
     fn set_n_flag() {
-        let mut r = Registers::new(false);
+        let mut r = Registers::new();
         r.flag(Flag::N, true);
         assert_eq!(r.f, 0b01000000)
     }
 
     #[test]
     fn set_h_flag() {
-        let mut r = Registers::new(false);
+        let mut r = Registers::new();
         r.flag(Flag::H, true);
         assert_eq!(r.f, 0b00100000)
     }
 
     #[test]
     fn set_c_flag() {
-        let mut r = Registers::new(false);
+        let mut r = Registers::new();
         r.flag(Flag::C, true);
         assert_eq!(r.f, 0b00010000)
     }
 
     #[test]
     fn reset_c_flag_only() {
-        let mut r = Registers::new(false);
+        let mut r = Registers::new();
         r.f = 0b00010000;
         r.flag(Flag::C, false);
         assert_eq!(r.f, 0b00000000)
@@ -177,7 +191,7 @@ mod test {
 
     #[test]
     fn reset_z_and_c_flag_with_all_set() {
-        let mut r = Registers::new(false);
+        let mut r = Registers::new();
         r.f = 0b11010000;
         r.flag(Flag::Z, false);
         r.flag(Flag::C, false);
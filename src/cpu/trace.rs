@@ -0,0 +1,108 @@
+/*
+    Instruction trace logging in the Gameboy Doctor format, for comparing the CPU against
+    reference logs: https://github.com/robert/gameboy-doctor
+
+    Each line records the registers before an instruction executes, along with the 4 bytes of
+    memory at PC:
+    A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+*/
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use cpu::registers::Registers;
+
+#[derive(Default, Clone)]
+pub struct TraceOptions {
+    pub pc_range: Option<Range<u16>>,   // Only trace instructions within this range
+    pub bank: Option<usize>,            // Only trace instructions in this ROM bank
+    pub limit: Option<usize>            // Stop after this many lines
+}
+
+pub struct Tracer {
+    out: Box<Write + Send>,
+    options: TraceOptions,
+    lines: usize
+}
+
+pub fn doctor_line(regs: &Registers, pcmem: [u8; 4]) -> String {
+    format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l, regs.sp, regs.pc,
+        pcmem[0], pcmem[1], pcmem[2], pcmem[3])
+}
+
+impl Tracer {
+    pub fn new(out: Box<Write + Send>, options: TraceOptions) -> Tracer {
+        Tracer {
+            out: out,
+            options: options,
+            lines: 0
+        }
+    }
+
+    pub fn create(filename: &str, options: TraceOptions) -> Result<Tracer, String> {
+        match File::create(filename) {
+            Ok(f) => Ok(Tracer::new(Box::new(BufWriter::new(f)), options)),
+            Err(e) => Err(format!("Unable to create trace file: {}", e))
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.options.limit.map_or(false, |limit| self.lines >= limit)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines
+    }
+
+    // Should an instruction at pc, in the given ROM bank, be traced? The bank is None outside ROM.
+    pub fn wants(&self, pc: u16, bank: Option<usize>) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        if let Some(ref range) = self.options.pc_range {
+            if pc < range.start || pc >= range.end {
+                return false;
+            }
+        }
+        match self.options.bank {
+            Some(wanted) => bank == Some(wanted),
+            None => true
+        }
+    }
+
+    pub fn trace(&mut self, regs: &Registers, pcmem: [u8; 4]) -> Result<(), String> {
+        self.lines += 1;
+        writeln!(self.out, "{}", doctor_line(regs, pcmem)).map_err(|e| format!("Unable to write trace: {}", e))
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|e| format!("Unable to write trace: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use model::Model;
+
+    #[test]
+    fn line_format() {
+        let regs = Model::Dmg.post_boot_registers(false);
+        assert_eq!(doctor_line(&regs, [0x00, 0xC3, 0x13, 0x02]),
+                   "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02");
+    }
+
+    #[test]
+    fn filters() {
+        let options = TraceOptions { pc_range: Some(0x4000..0x4100), bank: Some(2), limit: Some(1) };
+        let mut tracer = Tracer::new(Box::new(Vec::new()), options);
+        assert!(!tracer.wants(0x0100, Some(0)));
+        assert!(!tracer.wants(0x4000, Some(1)));
+        assert!(!tracer.wants(0x4100, Some(2)));
+        assert!(tracer.wants(0x4000, Some(2)));
+        tracer.trace(&Model::Dmg.post_boot_registers(false), [0; 4]).unwrap();
+        assert!(tracer.is_finished());
+        assert!(!tracer.wants(0x4000, Some(2)));
+    }
+}
@@ -0,0 +1,167 @@
+/*
+    Find the first divergence between two instruction traces, e.g. one from this emulator and one
+    from a reference emulator.
+
+    Lines are parsed as whitespace separated key:value pairs, which covers both the Gameboy Doctor
+    format and the format of Cpu::dump_state_small. A leading 0x.... token is taken as the PC.
+    Only registers present in both traces are compared.
+*/
+
+use std::fs::File;
+use std::io::Read;
+
+const FLAGS: [(&'static str, u8); 4] = [("Z", 0x80), ("N", 0x40), ("H", 0x20), ("C", 0x10)];
+
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub line: usize,            // Index of the first differing line
+    pub differences: Vec<String>
+}
+
+// Parse a trace line into (register, value) pairs, with register names in upper case.
+pub fn parse_line(line: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for (i, token) in line.split_whitespace().enumerate() {
+        if i == 0 && token.starts_with("0x") && !token.contains(':') {
+            fields.push(("PC".to_string(), token[2..].to_uppercase()));
+            continue;
+        }
+        if let Some(colon) = token.find(':') {
+            let key = token[..colon].to_uppercase();
+            let value = token[colon + 1..].to_uppercase();
+            // The flags are compared through F.
+            if key != "ZNHC" {
+                fields.push((key, value));
+            }
+        }
+    }
+    fields
+}
+
+fn value(fields: &[(String, String)], key: &str) -> Option<String> {
+    fields.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v.clone())
+}
+
+// Compare two parsed lines, returning a description of each differing register and flag.
+pub fn compare(ours: &[(String, String)], theirs: &[(String, String)]) -> Vec<String> {
+    let mut differences = Vec::new();
+    for &(ref key, ref our_value) in ours {
+        let their_value = match value(theirs, key) {
+            Some(v) => v,
+            None => continue
+        };
+        let equal = match (u16::from_str_radix(our_value, 16), u16::from_str_radix(&their_value, 16)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => *our_value == their_value
+        };
+        if equal {
+            continue;
+        }
+        differences.push(format!("{}: {} != {}", key, our_value, their_value));
+        if key == "F" {
+            if let (Ok(a), Ok(b)) = (u8::from_str_radix(our_value, 16), u8::from_str_radix(&their_value, 16)) {
+                for &(flag, mask) in FLAGS.iter() {
+                    if a & mask != b & mask {
+                        differences.push(format!("Flag {}: {} != {}", flag, (a & mask != 0) as u8, (b & mask != 0) as u8));
+                    }
+                }
+            }
+        }
+    }
+    differences
+}
+
+pub fn first_divergence(ours: &[&str], theirs: &[&str]) -> Option<Divergence> {
+    for (line, (a, b)) in ours.iter().zip(theirs.iter()).enumerate() {
+        let differences = compare(&parse_line(a), &parse_line(b));
+        if !differences.is_empty() {
+            return Some(Divergence { line: line, differences: differences });
+        }
+    }
+    if ours.len() != theirs.len() {
+        let line = if ours.len() < theirs.len() { ours.len() } else { theirs.len() };
+        let which = if ours.len() < theirs.len() { "Our" } else { "Reference" };
+        return Some(Divergence { line: line, differences: vec![format!("{} trace ended", which)] });
+    }
+    None
+}
+
+// Describe the divergence with up to context lines of each trace before it.
+pub fn report(ours: &[&str], theirs: &[&str], divergence: &Divergence, context: usize) -> String {
+    let mut out = format!("Traces diverge at line {}:\n", divergence.line + 1);
+    for difference in &divergence.differences {
+        out.push_str(&format!("  {}\n", difference));
+    }
+    let start = divergence.line.saturating_sub(context);
+    for &(name, lines) in &[("ours", ours), ("reference", theirs)] {
+        out.push_str(&format!("\n{}:\n", name));
+        for i in start..divergence.line + 1 {
+            if let Some(line) = lines.get(i) {
+                let marker = if i == divergence.line { ">" } else { " " };
+                out.push_str(&format!("{} {:>8}  {}\n", marker, i + 1, line));
+            }
+        }
+    }
+    out
+}
+
+fn read_file(filename: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    match File::open(filename) {
+        Ok(mut f) => match f.read_to_string(&mut contents) {
+            Ok(_) => Ok(contents),
+            Err(e) => Err(format!("Unable to read {}: {}", filename, e))
+        },
+        Err(e) => Err(format!("Unable to open {}: {}", filename, e))
+    }
+}
+
+// Diff two trace files, returning the report or None if they match.
+pub fn diff_files(ours: &str, theirs: &str, context: usize) -> Result<Option<String>, String> {
+    let ours = read_file(ours)?;
+    let theirs = read_file(theirs)?;
+    let ours: Vec<&str> = ours.lines().filter(|l| !l.trim().is_empty()).collect();
+    let theirs: Vec<&str> = theirs.lines().filter(|l| !l.trim().is_empty()).collect();
+    Ok(first_divergence(&ours, &theirs).map(|d| report(&ours, &theirs, &d, context)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn doctor_traces() {
+        let ours = [
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,13,02,CE",
+            "A:01 F:90 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0213 PCMEM:AF,21,00,C0",
+        ];
+        let theirs = [
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,13,02,CE",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0213 PCMEM:AF,21,00,C0",
+        ];
+        assert_eq!(first_divergence(&ours[..2], &theirs[..2]), None);
+        let divergence = first_divergence(&ours, &theirs).unwrap();
+        assert_eq!(divergence.line, 2);
+        assert_eq!(divergence.differences, vec!["F: 90 != B0", "Flag H: 0 != 1"]);
+        let report = report(&ours, &theirs, &divergence, 1);
+        assert!(report.starts_with("Traces diverge at line 3:\n  F: 90 != B0\n"));
+        assert!(!report.contains("PC:0100"));
+        assert!(report.contains(">        3  A:01 F:90"));
+    }
+
+    #[test]
+    fn mixed_formats() {
+        let ours = ["sp:fffe a:01 f:b0 b:00 c:13 d:00 e:d8 h:01 l:4d znhc:1011"];
+        let theirs = ["A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"];
+        assert_eq!(first_divergence(&ours, &theirs), None);
+
+        let ours = ["0x0100\tsp:fffe a:02 f:b0 b:00 c:13 d:00 e:d8 h:01 l:4d znhc:1011"];
+        let divergence = first_divergence(&ours, &theirs).unwrap();
+        assert_eq!(divergence.differences, vec!["A: 02 != 01"]);
+
+        let divergence = first_divergence(&ours[..0], &theirs).unwrap();
+        assert_eq!(divergence.differences, vec!["Our trace ended"]);
+    }
+}
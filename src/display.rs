@@ -0,0 +1,63 @@
+extern crate rasteriser;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use self::rasteriser::driver;
+use gpu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+pub use self::rasteriser::driver::{Colour, KeyCode, KeyState};
+
+// The window which shows the screen. This belongs to the frontend rather than the emulator, as
+// windows can't be moved between threads.
+pub struct Display {
+    driver: Box<driver::Driver>,
+    window: Box<driver::Window>,
+    scale: usize,
+    keys: Rc<RefCell<Vec<(KeyCode, KeyState)>>>
+}
+
+impl Display {
+    pub fn new(scale: usize) -> Display {
+        let driver = driver::create(driver::DriverType::GL);
+        let mut window = driver.create_window(SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale, "GBC Emulator");
+        let keys = Rc::new(RefCell::new(Vec::new()));
+        let keys_ref = keys.clone();
+        window.set_input_callback(Box::new(move |code: KeyCode, state: KeyState| {
+            keys_ref.borrow_mut().push((code, state));
+        }));
+        Display {
+            driver: driver,
+            window: window,
+            scale: scale,
+            keys: keys
+        }
+    }
+
+    // Draw a framebuffer of RGB pixels, 3 bytes per pixel.
+    pub fn draw(&mut self, framebuffer: &[u8]) {
+        for (i, pixel) in framebuffer.chunks(3).enumerate() {
+            let colour = Colour::RGBA(pixel[0], pixel[1], pixel[2], 255);
+            self.put_pixel(i % SCREEN_WIDTH, i / SCREEN_WIDTH, &colour);
+        }
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, colour: &Colour) {
+        // Naive implementation. Upstream should instead have a way of specifying the
+        // "backbuffer" size in addition to the window size.
+        for y_offset in 0..self.scale {
+            for x_offset in 0..self.scale {
+                self.window.draw_pixel(x * self.scale + x_offset, y * self.scale + y_offset, colour);
+            }
+        }
+    }
+
+    // Present the image and process window input. Returns false if the window was closed.
+    pub fn update(&mut self) -> bool {
+        self.window.update(false)
+    }
+
+    // Take the key presses and releases since the last call.
+    pub fn take_key_events(&mut self) -> Vec<(KeyCode, KeyState)> {
+        self.keys.borrow_mut().drain(..).collect()
+    }
+}
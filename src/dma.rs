@@ -0,0 +1,46 @@
+use io::IoDevice;
+
+pub const DMA_REG: u16 = 0xFF46;
+
+// OAM DMA. Writing the upper byte of a source address to DMA copies 160 bytes from there to OAM.
+// Memory performs the copy, as it happens on the bus.
+pub struct Dma {
+    source: u8,
+    pending: bool
+}
+
+impl Dma {
+    pub fn new() -> Dma {
+        Dma {
+            source: 0,
+            pending: false
+        }
+    }
+
+    // The source address of a transfer which has been started, if any.
+    pub fn take_transfer(&mut self) -> Option<u16> {
+        if self.pending {
+            self.pending = false;
+            Some((self.source as u16) << 8)
+        } else {
+            None
+        }
+    }
+}
+
+impl IoDevice for Dma {
+    fn read_io(&self, _addr: u16) -> u8 {
+        self.source
+    }
+
+    fn write_io(&mut self, _addr: u16, value: u8) -> u8 {
+        self.source = value;
+        self.pending = true;
+        0
+    }
+
+    // Set the register without starting a transfer.
+    fn poke_io(&mut self, _addr: u16, value: u8) {
+        self.source = value;
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io::Read;
+use cpu::CpuEvent;
+use cpu::interpreter::Cpu;
+use cpu::trace::{Tracer, TraceOptions};
+use cpu::registers::Registers;
+use memory::Memory;
+use input::{KeyCode, KeyState, InputButton};
+use display::Display;
+use cartridge::Cartridge;
+use lint::Lint;
+use power_on::PowerOnFill;
+use model::Model;
+use boot_rom::DMG_BOOT_ROM;
+
+// The emulated hardware. This has no window of its own, so frontends draw the framebuffer and
+// pass on key input. It can be moved to another thread.
+pub struct Emulator {
+    cpu: Cpu<Memory>,
+    events: Vec<CpuEvent>
+}
+
+impl Emulator {
+    pub fn new(model: Model) -> Emulator {
+        Emulator {
+            cpu: Cpu::new(Memory::new(model)),
+            events: Vec::new()
+        }
+    }
+
+    pub fn load(&mut self, rom: &str) {
+        match Cartridge::load(rom) {
+            Ok(c) => self.load_cartridge(c),
+            Err(e) => println!("WARNING: Cartridge failed to load. Reason: {}", e)
+        }
+    }
+
+    // Run the boot ROM if one has been loaded, otherwise skip it.
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cpu.bus_mut().load_cartridge(cartridge);
+        if !self.cpu.bus().boot_rom_mapped() {
+            self.skip_boot();
+        }
+    }
+
+    // Start from the state the model's boot ROM leaves behind for the cartridge.
+    pub fn skip_boot(&mut self) {
+        self.cpu.bus_mut().skip_boot();
+        let cgb_mode = self.cpu.bus().cgb_mode();
+        self.cpu.regs = self.model().post_boot_registers(cgb_mode);
+    }
+
+    // Start from power on, running a boot ROM. This fails once a cartridge has been loaded, as
+    // loading the cartridge skips the boot ROM if there isn't one.
+    pub fn set_boot_rom(&mut self, rom: Vec<u8>) -> Result<(), String> {
+        self.cpu.bus_mut().load_boot_rom(rom)?;
+        self.cpu.regs = Registers::new();
+        Ok(())
+    }
+
+    pub fn load_boot_rom(&mut self, filename: &str) -> Result<(), String> {
+        let mut rom = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut rom))
+            .map_err(|e| format!("Unable to read {}: {}", filename, e))?;
+        self.set_boot_rom(rom)
+    }
+
+    pub fn model(&self) -> Model {
+        self.cpu.bus().model()
+    }
+
+    pub fn cpu(&self) -> &Cpu<Memory> {
+        &self.cpu
+    }
+
+    // Read memory without affecting the hardware.
+    pub fn read_u8(&self, addr: u16) -> u8 {
+        self.cpu.bus().peek(addr)
+    }
+
+    // Read a bank:address pair, e.g. a ROM bank which isn't currently mapped.
+    pub fn peek_bank(&self, bank: usize, addr: u16) -> u8 {
+        self.cpu.bus().peek_bank(bank, addr)
+    }
+
+    // Change memory without affecting the hardware, e.g. to patch the ROM.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.cpu.bus_mut().poke(addr, value);
+    }
+
+    pub fn poke_bank(&mut self, bank: usize, addr: u16, value: u8) {
+        self.cpu.bus_mut().poke_bank(bank, addr, value);
+    }
+
+    // The screen as RGB, 3 bytes per pixel.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.cpu.bus().framebuffer().to_vec()
+    }
+
+    pub fn key_input(&mut self, code: KeyCode, state: KeyState) {
+        self.cpu.bus_mut().key_input(code, state);
+    }
+
+    pub fn serial_output(&self) -> Vec<u8> {
+        self.cpu.bus().serial_output().to_vec()
+    }
+
+    // Take the CPU events that occurred since the last call.
+    pub fn take_events(&mut self) -> Vec<CpuEvent> {
+        self.events.drain(..).collect()
+    }
+
+    // Run the built in boot ROM. There is only one for the DMG, other models need theirs loaded
+    // from a file.
+    pub fn boot(&mut self) -> Result<(), String> {
+        match self.model() {
+            Model::Dmg => self.set_boot_rom(DMG_BOOT_ROM.to_vec()),
+            model => Err(format!("There is no built in boot ROM for the {:?}", model))
+        }
+    }
+
+    // Stop frames early when LD B,B is executed, reporting it as a breakpoint. This is off by
+    // default, as games execute LD B,B too.
+    pub fn set_breakpoints(&mut self, enabled: bool) {
+        self.cpu.set_breakpoints(enabled);
+    }
+
+    // Hold buttons while the boot ROM is skipped, as the CGB boot ROM picks palettes for DMG
+    // games from them. This has to be set before the cartridge is loaded.
+    pub fn set_boot_buttons(&mut self, buttons: Vec<InputButton>) {
+        self.cpu.bus_mut().set_boot_buttons(buttons);
+    }
+
+    // Fill RAM with its contents at power on, before the emulator starts running.
+    pub fn set_power_on_fill(&mut self, fill: PowerOnFill) {
+        self.cpu.bus_mut().set_power_on_fill(fill);
+    }
+
+    // Whether the CPU is locked out of VRAM and OAM while the PPU is using them, as on hardware.
+    // Turning this off helps with games that only work on inaccurate emulators.
+    pub fn set_access_locks(&mut self, enabled: bool) {
+        self.cpu.bus_mut().set_access_locks(enabled);
+    }
+
+    // The number of VRAM and OAM accesses which were blocked, for finding code which would break
+    // on hardware.
+    pub fn blocked_accesses(&self) -> u64 {
+        self.cpu.bus().blocked_accesses()
+    }
+
+    // Warn about things the program does which would fail on hardware.
+    pub fn start_lint(&mut self) {
+        self.cpu.bus_mut().start_lint();
+    }
+
+    pub fn lints(&self) -> Vec<Lint> {
+        self.cpu.bus().lints().to_vec()
+    }
+
+    // Start recording APU register writes.
+    pub fn start_audio_log(&mut self) {
+        self.cpu.bus_mut().start_audio_log();
+    }
+
+    // Stop recording APU register writes and save them as a VGM file.
+    pub fn save_audio_log(&mut self, filename: &str) -> Result<(), String> {
+        match self.cpu.bus_mut().stop_audio_log() {
+            Some(log) => {
+                println!("status: Saving {} APU register writes to {}", log.write_count(), filename);
+                log.save(filename)
+            },
+            None => Err("Audio logging was not started".to_string())
+        }
+    }
+
+    // Write a Gameboy Doctor format instruction trace to a file.
+    pub fn start_trace(&mut self, filename: &str, options: TraceOptions) -> Result<(), String> {
+        let tracer = Tracer::create(filename, options)?;
+        self.cpu.start_trace(tracer);
+        Ok(())
+    }
+
+    pub fn stop_trace(&mut self) -> Result<(), String> {
+        self.cpu.stop_trace()
+    }
+
+    // Run until the end of the frame, or until a breakpoint is hit. Returns false if the emulator
+    // should stop.
+    pub fn frame(&mut self) -> bool {
+        // A frame is 70224 cycles at normal speed, which is twice as many CPU cycles in double
+        // speed mode. Memory counts cycles at normal speed so handles both.
+        let frame_end = self.cpu.bus().cycles() + 70224;
+        while self.cpu.bus().cycles() < frame_end {
+            // Nothing is clocked while the CPU is stopped, so return to let the frontend keep
+            // processing input until a button wakes it up.
+            if self.cpu.is_stopped() {
+                self.cpu.tick();
+                return true;
+            }
+
+            // CPU.
+            self.cpu.tick();
+            let events = self.cpu.take_events();
+            let breakpoint = events.iter().any(|e| match *e { CpuEvent::Breakpoint { .. } => true, _ => false });
+            for event in events {
+                println!("WARNING: {}", event);
+                self.events.push(event);
+            }
+            if !self.cpu.running {
+                println!("status: CPU has stopped running");
+                return false;
+            }
+            if breakpoint {
+                return true;
+            }
+        }
+        true
+    }
+
+    // Run frames until the window is closed, drawing each one to the display.
+    pub fn run(&mut self, display: &mut Display) {
+        loop {
+            let running = self.frame();
+            // Events have already been reported.
+            self.events.clear();
+            display.draw(self.cpu.bus().framebuffer());
+            if !running || !display.update() {
+                break;
+            }
+            for (code, state) in display.take_key_events() {
+                self.key_input(code, state);
+            }
+        }
+
+        // Dump memory on exit.
+        println!("{}", self.cpu.bus().dump_state(0x8000..0x9FFF));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn runs_on_another_thread() {
+        let mut emulator = Emulator::new(Model::Dmg);
+        emulator.load_cartridge(Cartridge::from_rom(Vec::new()).unwrap());
+        let emulator = thread::spawn(move || {
+            emulator.frame();
+            emulator
+        }).join().unwrap();
+        assert!(emulator.cpu().clock.m > 0);
+    }
+
+    #[test]
+    fn post_boot_state() {
+        let mut emulator = Emulator::new(Model::Agb);
+        emulator.load_cartridge(Cartridge::from_rom(vec![0; 0x8000]).unwrap());
+        assert_eq!((emulator.cpu().regs.a, emulator.cpu().regs.b), (0x11, 0x01));
+        assert_eq!(emulator.cpu().regs.de(), 0x0008);
+        assert_eq!(emulator.read_u8(0xFF40), 0x91);
+        assert_eq!(emulator.read_u8(0xFF26), 0xF1);
+        assert_eq!(emulator.read_u8(0xFF0F), 0xE1);
+    }
+
+    #[test]
+    fn boot_rom() {
+        let mut emulator = Emulator::new(Model::Dmg);
+        assert!(emulator.set_boot_rom(vec![0; 0x900]).is_err());
+        // Turn the boot ROM off straight away.
+        let mut rom = vec![0; 0x100];
+        rom[0..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        emulator.set_boot_rom(rom).unwrap();
+        let mut cartridge = vec![0; 0x8000];
+        cartridge[0] = 0x42;
+        emulator.load_cartridge(Cartridge::from_rom(cartridge).unwrap());
+        assert_eq!(emulator.read_u8(0x0000), 0x3E);
+        assert_eq!(emulator.cpu().regs.pc, 0x0000);
+        emulator.cpu.tick();
+        emulator.cpu.tick();
+        assert_eq!(emulator.read_u8(0x0000), 0x42);
+        assert_eq!(emulator.cpu().regs.pc, 0x0004);
+
+        // It's too late once the cartridge has been loaded.
+        assert!(emulator.boot().is_err());
+        assert_eq!(emulator.cpu().regs.pc, 0x0004);
+        assert_eq!(emulator.read_u8(0x0000), 0x42);
+
+        assert!(Emulator::new(Model::Dmg).boot().is_ok());
+        assert!(Emulator::new(Model::Cgb).boot().is_err());
+    }
+
+    #[test]
+    fn cgb_boot_rom_with_dmg_cartridge() {
+        let mut emulator = Emulator::new(Model::Cgb);
+        // Make background colour 0 red, pick DMG compatibility mode, turn the LCD on and finish.
+        let mut rom = vec![0; 0x900];
+        let code = [0x3E, 0x80, 0xE0, 0x68, 0x3E, 0x1F, 0xE0, 0x69, 0x3E, 0x00, 0xE0, 0x69,
+                    0x3E, 0x04, 0xE0, 0x4C, 0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x01, 0xE0, 0x50];
+        rom[..code.len()].copy_from_slice(&code);
+        emulator.set_boot_rom(rom).unwrap();
+        emulator.load_cartridge(Cartridge::from_rom(vec![0; 0x8000]).unwrap());
+        for _ in 0..7 {
+            emulator.cpu.tick();
+        }
+        // The palette registers are mapped while the boot ROM runs, whatever the cartridge.
+        assert_eq!(emulator.read_u8(0xFF68), 0xC2);
+        assert_eq!(emulator.read_u8(0xFF4C), 0x00);
+        for _ in 0..5 {
+            emulator.cpu.tick();
+        }
+        assert!(!emulator.cpu().bus().boot_rom_mapped());
+        assert!(!emulator.cpu().bus().cgb_mode());
+        assert_eq!(emulator.read_u8(0xFF68), 0xFF);
+        assert_eq!(emulator.read_u8(0xFF4C), 0xFF);
+
+        emulator.frame();
+        emulator.frame();
+        assert_eq!(&emulator.framebuffer()[0..3], &[255, 0, 0]);
+    }
+
+    #[test]
+    fn runs_without_cartridge() {
+        let mut emulator = Emulator::new(Model::Dmg);
+        assert!(emulator.frame());
+        assert_eq!(emulator.read_u8(0x0100), 0xFF);
+    }
+}
@@ -1,43 +1,18 @@
-extern crate rasteriser;
+use std::cell::Cell;
+use io::IoDevice;
+use lint::LintKind;
+use power_on::{PowerOnFill, RamRegion};
+use model::Model;
+use colourisation::{CompatPalettes, Palette};
 
-use self::rasteriser::driver;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
-pub use self::rasteriser::driver::{Colour, KeyCode, KeyState};
-
-struct Display {
-    driver: Box<driver::Driver>,
-    window: Box<driver::Window>,
-    scale: usize
-}
-
-impl Display {
-    fn new(scale: usize) -> Display {
-        let gbc_screen_size = (160, 144);
-        let driver = driver::create(driver::DriverType::GL);
-        Display {
-            window: driver.create_window(gbc_screen_size.0 * scale, gbc_screen_size.1 * scale, "GBC Emulator"),
-            driver: driver,
-            scale: scale
-        }
-    }
-
-    fn put_pixel(&mut self, x: usize, y: usize, colour: &Colour) {
-        // Naive implementation. Upstream should instead have a way of specifying the
-        // "backbuffer" size in addition to the window size.
-        for y_offset in 0..self.scale {
-            for x_offset in 0..self.scale {
-                self.window.draw_pixel(x * self.scale + x_offset, y * self.scale + y_offset, colour);
-            }
-        }
-    }
-
-    fn update(&mut self) -> bool {
-        self.window.update(false)
-    }
-}
+// RGB values of the 4 DMG shades, from lightest to darkest.
+const DMG_SHADES: [(u8, u8, u8); 4] = [(255, 255, 255), (192, 192, 192), (96, 96, 96), (0, 0, 0)];
 
 pub struct Gpu {
-    display: Display,
+    framebuffer: Vec<u8>,   // RGB, 3 bytes per pixel
     modeclock: u32,
     mode: u8, // see http://gbdev.gg8.se/files/docs/mirrors/pandocs.html#lcdstatusregister
     line: u8,
@@ -54,12 +29,35 @@ pub struct Gpu {
     r_scx: u8, // Screen X
     r_scy: u8, // Screen Y
     r_bgp: u8, // BG palette register
+    r_lyc: u8, // LY compare
+    r_obp0: u8, // OBJ palette 0 register
+    r_obp1: u8, // OBJ palette 1 register
+    r_wy: u8, // Window Y
+    r_wx: u8, // Window X + 7
+
+    // The CPU is locked out of VRAM during mode 3 and OAM during modes 2 and 3. Reads return 0xFF
+    // and writes are ignored. The count of blocked accesses helps to find code which would break
+    // on hardware.
+    access_locks: bool,
+    blocked_accesses: Cell<u64>,
+
+    // CGB palette RAM, 8 palettes of 4 colours as little endian 15 bit RGB, and the BCPS/OCPS
+    // index registers. Bit 7 of an index increments it after each write to the data register.
+    bg_palette_ram: [u8; 64],
+    obj_palette_ram: [u8; 64],
+    bcps: u8,
+    ocps: u8,
+
+    // Whether a DMG game is being drawn with the palettes the CGB boot ROM picked for it.
+    compat_palettes: bool,
+
+    model: Model,
 }
 
 impl Gpu {
-    pub fn new() -> Gpu {
+    pub fn new(model: Model) -> Gpu {
         Gpu {
-            display: Display::new(2),
+            framebuffer: vec![255; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
             modeclock: 0,
             mode: 0,
             line: 0,
@@ -72,14 +70,23 @@ impl Gpu {
             r_scx: 0,
             r_scy: 0,
             r_bgp: 0,
+            r_lyc: 0,
+            r_obp0: 0,
+            r_obp1: 0,
+            r_wy: 0,
+            r_wx: 0,
+            access_locks: true,
+            blocked_accesses: Cell::new(0),
+            bg_palette_ram: [0u8; 64],
+            obj_palette_ram: [0u8; 64],
+            bcps: 0,
+            ocps: 0,
+            compat_palettes: false,
+            model: model,
         }
     }
 
-    pub fn set_key_callback(&mut self, callback: Box<FnMut(KeyCode, KeyState)>) {
-        self.display.window.set_input_callback(callback);
-    }
-
-    pub fn tick(&mut self, cycles: u32) -> bool {
+    pub fn tick(&mut self, cycles: u32) {
         self.modeclock = self.modeclock.wrapping_add(cycles);
         match self.mode {
             // OAM read mode.
@@ -90,15 +97,12 @@ impl Gpu {
                 self.mode = 0;
                 self.render_scanline();
             },
-            // Hblank. After the last Hblank, update the screen.
+            // Hblank. After the last Hblank, the frame is complete.
             0 => if self.modeclock >= 204 {
                 self.modeclock = 0;
                 self.line += 1;
                 if self.line == 143 {
                     self.mode = 1;
-                    if !self.display_image() {
-                        return false;
-                    }
                 } else {
                     self.mode = 2;
                 }
@@ -115,12 +119,75 @@ impl Gpu {
             },
             _ => panic!("ERROR: Invalid GPU mode {}", self.mode)
         };
-        true
+    }
+
+    pub fn set_access_locks(&mut self, enabled: bool) {
+        self.access_locks = enabled;
+    }
+
+    pub fn blocked_accesses(&self) -> u64 {
+        self.blocked_accesses.get()
+    }
+
+    pub fn vram_locked(&self) -> bool {
+        self.access_locks && self.switchlcd == 1 && self.mode == 3
+    }
+
+    pub fn oam_locked(&self) -> bool {
+        self.access_locks && self.switchlcd == 1 && (self.mode == 2 || self.mode == 3)
+    }
+
+    fn locked(&self, addr: u16) -> bool {
+        match addr {
+            0x8000...0x9FFF => self.vram_locked(),
+            0xFE00...0xFE9F => self.oam_locked(),
+            _ => false
+        }
+    }
+
+    // Whether a CPU access would be unsafe on hardware, whether or not the locks are enabled.
+    // The value is given for writes.
+    pub fn lint(&self, addr: u16, write: Option<u8>) -> Option<LintKind> {
+        let lcd_on = self.switchlcd == 1;
+        match addr {
+            0x8000...0x9FFF if lcd_on && self.mode == 3 => Some(LintKind::VramLocked),
+            0xFE00...0xFE9F if lcd_on && (self.mode == 2 || self.mode == 3) => Some(LintKind::OamLocked),
+            0xFF40 => match write {
+                Some(value) if lcd_on && value & 0x80 == 0 && self.mode != 1 => Some(LintKind::LcdOffOutsideVblank),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    fn block_access(&self) {
+        self.blocked_accesses.set(self.blocked_accesses.get() + 1);
+    }
+
+    // The unusable area after OAM reads as 0 on the DMG, or 0xFF while OAM is locked. The CGB
+    // repeats the upper nibble of the low address byte, so 0xFEA0-0xFEAF reads 0xAA.
+    pub fn read_unusable(&self, addr: u16) -> u8 {
+        if self.model.is_cgb() {
+            let nibble = (addr as u8) & 0xF0;
+            nibble | (nibble >> 4)
+        } else if self.oam_locked() {
+            0xFF
+        } else {
+            0
+        }
+    }
+
+    fn vram_u8(&self, addr: u16) -> u8 {
+        self.vram[addr as usize - 0x8000]
     }
 
     pub fn read_u8(&self, addr: u16) -> u8 {
         match addr {
-            0x8000...0x9FFF => self.vram[addr as usize - 0x8000],
+            _ if self.locked(addr) => {
+                self.block_access();
+                0xFF
+            },
+            0x8000...0x9FFF => self.vram_u8(addr),
             0xFE00...0xFE9F => self.oam[addr as usize - 0xFE00],
             0xFF40 => {
                 self.switchlcd * 0x80 |
@@ -134,13 +201,67 @@ impl Gpu {
             0xFF42 => self.r_scy,
             0xFF43 => self.r_scx,
             0xFF44 => self.line,
+            0xFF45 => self.r_lyc,
             0xFF47 => { println!("WARNING: Attempting to read from background palette (write only)"); 0 },
+            0xFF48 => self.r_obp0,
+            0xFF49 => self.r_obp1,
+            0xFF4A => self.r_wy,
+            0xFF4B => self.r_wx,
+            0xFF68 => self.bcps,
+            0xFF69 => self.bg_palette_ram[(self.bcps & 0x3F) as usize],
+            0xFF6A => self.ocps,
+            0xFF6B => self.obj_palette_ram[(self.ocps & 0x3F) as usize],
             _ => { println!("WARNING: GPU cannot read from this memory address. Addr = 0x{:X}", addr); 0},
         }
     }
 
+    // Read VRAM, OAM or a register without logging. The write only palette reads back as written.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000...0x9FFF => self.vram_u8(addr),
+            0xFE00...0xFE9F => self.oam[addr as usize - 0xFE00],
+            0xFF40...0xFF45 => self.read_u8(addr),
+            0xFF47 => self.r_bgp,
+            0xFF48...0xFF4B => self.read_u8(addr),
+            0xFF68...0xFF6B => self.read_u8(addr),
+            _ => 0
+        }
+    }
+
+    // Write VRAM, OAM or a register without logging. LY can be set directly.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000...0x9FFF => self.vram[addr as usize - 0x8000] = value,
+            0xFE00...0xFE9F => self.oam[addr as usize - 0xFE00] = value,
+            0xFF40 => {
+                self.switchlcd = (value >> 7) & 1;
+                self.bgtile = (value >> 4) & 1;
+                self.bgmap = (value >> 3) & 1;
+                self.switchbg = value & 1;
+            },
+            0xFF41 => self.mode = value & 0x03,
+            0xFF42 => self.r_scy = value,
+            0xFF43 => self.r_scx = value,
+            0xFF44 => self.line = value,
+            0xFF45 => self.r_lyc = value,
+            0xFF47 => self.r_bgp = value,
+            0xFF48 => self.r_obp0 = value,
+            0xFF49 => self.r_obp1 = value,
+            0xFF4A => self.r_wy = value,
+            0xFF4B => self.r_wx = value,
+            0xFF68 => self.bcps = value & 0xBF,
+            0xFF69 => self.bg_palette_ram[(self.bcps & 0x3F) as usize] = value,
+            0xFF6A => self.ocps = value & 0xBF,
+            0xFF6B => self.obj_palette_ram[(self.ocps & 0x3F) as usize] = value,
+            _ => {}
+        }
+    }
+
     pub fn write_u8(&mut self, addr: u16, value: u8) {
         match addr {
+            _ if self.locked(addr) => {
+                self.block_access();
+            },
             0x8000...0x9FFF => self.vram[addr as usize - 0x8000] = value,
             0xFE00...0xFE9F => self.oam[addr as usize - 0xFE00] = value,
             0xFF40 => {
@@ -156,7 +277,22 @@ impl Gpu {
             0xFF42 => { println!("New Screen Y: {}", value); self.r_scy = value },
             0xFF43 => { println!("New Screen X: {}", value); self.r_scx = value },
             0xFF44 => println!("WARNING: Attempting to write to current scan line (read only)"),
+            0xFF45 => self.r_lyc = value,
             0xFF47 => self.r_bgp = value,
+            0xFF48 => self.r_obp0 = value,
+            0xFF49 => self.r_obp1 = value,
+            0xFF4A => self.r_wy = value,
+            0xFF4B => self.r_wx = value,
+            0xFF68 => self.bcps = value & 0xBF,
+            0xFF69 => {
+                self.bg_palette_ram[(self.bcps & 0x3F) as usize] = value;
+                self.bcps = increment_palette_index(self.bcps);
+            },
+            0xFF6A => self.ocps = value & 0xBF,
+            0xFF6B => {
+                self.obj_palette_ram[(self.ocps & 0x3F) as usize] = value;
+                self.ocps = increment_palette_index(self.ocps);
+            },
             _ => println!("WARNING: GPU cannot write to this memory address. Addr = 0x{:X}", addr),
         }
     }
@@ -178,7 +314,7 @@ impl Gpu {
             let pixel_x = screen_x.wrapping_add(self.r_scx) & 0x7; // Pixel X within tile.
 
             // Get tile number.
-            let tile = self.read_u8(tile_row_start + tile_x as u16);
+            let tile = self.vram_u8(tile_row_start + tile_x as u16);
 
             // Get row data within tile.
             // Assuming tile set 1.
@@ -188,8 +324,8 @@ impl Gpu {
                 0x8800u16 + ((((tile as i8) as i16) + 128) as u16) * 16
             };
             let tile_row_offset = (pixel_y as u16) << 1; // Row offset within tile. 2 bytes per row.
-            let tile_data_low = self.read_u8(tile_data_start + tile_row_offset);
-            let tile_data_high = self.read_u8(tile_data_start + tile_row_offset + 1);
+            let tile_data_low = self.vram_u8(tile_data_start + tile_row_offset);
+            let tile_data_high = self.vram_u8(tile_data_start + tile_row_offset + 1);
 
             // Read pixel data.
             let bit_index = 7 - pixel_x;
@@ -198,18 +334,95 @@ impl Gpu {
             // Map to real colour based on palette.
             let palette = self.r_bgp;
             let colour = (palette >> (colour_data * 2)) & 0b11;
-            let display_colour = match colour {
-                0 => Colour::RGBA(255, 255, 255, 255),
-                1 => Colour::RGBA(192, 192, 192, 255),
-                2 => Colour::RGBA(96, 96, 96, 255),
-                3 => Colour::RGBA(0, 0, 0, 255),
-                _ => panic!("ERROR: Unknown display colour {}", colour)
+            let (r, g, b) = if self.compat_palettes {
+                palette_rgb(&self.bg_palette_ram, 0, colour)
+            } else {
+                DMG_SHADES[colour as usize]
             };
-            self.display.put_pixel(screen_x as usize, screen_y as usize, &display_colour);
+            self.put_pixel(screen_x as usize, screen_y as usize, r, g, b);
+        }
+    }
+
+    // Load the palettes the CGB boot ROM picks for a DMG game, which are used in place of the DMG
+    // shades. BGP maps to the BG palette, and OBP0 and OBP1 to the first two OBJ palettes.
+    pub fn load_compat_palettes(&mut self, palettes: &CompatPalettes) {
+        write_palette(&mut self.bg_palette_ram, 0, &palettes.bg);
+        write_palette(&mut self.obj_palette_ram, 0, &palettes.obj0);
+        write_palette(&mut self.obj_palette_ram, 1, &palettes.obj1);
+        self.compat_palettes = true;
+    }
+
+    // Draw DMG games with the palettes already in palette RAM, as left by the CGB boot ROM.
+    pub fn use_compat_palettes(&mut self) {
+        self.compat_palettes = true;
+    }
+
+    pub fn fill_ram(&mut self, fill: PowerOnFill) {
+        fill.fill(RamRegion::Vram, &mut self.vram);
+        fill.fill(RamRegion::Oam, &mut self.oam);
+    }
+
+    // Blank the screen while the LCD is stopped.
+    pub fn blank(&mut self) {
+        for pixel in self.framebuffer.iter_mut() {
+            *pixel = 255;
         }
     }
 
-    fn display_image(&mut self) -> bool {
-        self.display.update()
+    // The last image drawn, as RGB with 3 bytes per pixel.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
+        let offset = (y * SCREEN_WIDTH + x) * 3;
+        self.framebuffer[offset..offset + 3].copy_from_slice(&[r, g, b]);
+    }
+}
+
+// Increment the index of BCPS or OCPS if auto increment is on, wrapping within palette RAM.
+fn increment_palette_index(index: u8) -> u8 {
+    if index & 0x80 != 0 {
+        0x80 | (index.wrapping_add(1) & 0x3F)
+    } else {
+        index
+    }
+}
+
+fn write_palette(ram: &mut [u8; 64], palette: usize, colours: &Palette) {
+    for (i, colour) in colours.iter().enumerate() {
+        let offset = palette * 8 + i * 2;
+        ram[offset] = *colour as u8;
+        ram[offset + 1] = (*colour >> 8) as u8;
+    }
+}
+
+// A colour from palette RAM as RGB. The 5 bit channels are scaled up to 8 bits.
+fn palette_rgb(ram: &[u8; 64], palette: usize, colour: u8) -> (u8, u8, u8) {
+    let offset = palette * 8 + colour as usize * 2;
+    let rgb = ram[offset] as u16 | (ram[offset + 1] as u16) << 8;
+    let scale = |c: u16| -> u8 {
+        let c = (c & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    };
+    (scale(rgb), scale(rgb >> 5), scale(rgb >> 10))
+}
+
+impl IoDevice for Gpu {
+    fn read_io(&self, addr: u16) -> u8 {
+        self.read_u8(addr)
+    }
+
+    fn write_io(&mut self, addr: u16, value: u8) -> u8 {
+        self.write_u8(addr, value);
+        0
+    }
+
+    fn peek_io(&self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn poke_io(&mut self, addr: u16, value: u8) {
+        self.poke(addr, value);
     }
 }
@@ -1,5 +1,7 @@
-use gpu::{KeyCode, KeyState};
+pub use display::{KeyCode, KeyState};
+use io::IoDevice;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InputButton {
     A,
     B,
@@ -11,6 +13,22 @@ pub enum InputButton {
     Down
 }
 
+impl InputButton {
+    pub fn parse(text: &str) -> Result<InputButton, String> {
+        match text.to_lowercase().as_str() {
+            "a" => Ok(InputButton::A),
+            "b" => Ok(InputButton::B),
+            "select" => Ok(InputButton::Select),
+            "start" => Ok(InputButton::Start),
+            "left" => Ok(InputButton::Left),
+            "right" => Ok(InputButton::Right),
+            "up" => Ok(InputButton::Up),
+            "down" => Ok(InputButton::Down),
+            _ => Err(format!("Unknown button {}", text))
+        }
+    }
+}
+
 pub struct Input {
     rows: [u8; 2], // Row 0: A, B, Select, Start. Row 1: Right, Left, Down, Up
     row_select: u8, // 0x10 to select row 0, 0x20 to select row 1.
@@ -28,16 +46,7 @@ impl Input {
 
     pub fn on_key_input(&mut self, code: KeyCode, state: KeyState) {
         if let Some(button) = (self.input_map)(code) {
-            let (mask, row) = match button {
-                InputButton::A => (0b0001, 0),
-                InputButton::B => (0b0010, 0),
-                InputButton::Select => (0b0100, 0),
-                InputButton::Start => (0b1000, 0),
-                InputButton::Right => (0b0001, 1),
-                InputButton::Left => (0b0010, 1),
-                InputButton::Down => (0b0100, 1),
-                InputButton::Up => (0b1000, 1),
-            };
+            let (mask, row) = button_mask(button);
             if state == KeyState::Pressed {
                 // Clear mask bit.
                 self.rows[row] &= !mask;
@@ -48,15 +57,105 @@ impl Input {
         }
     }
 
-    pub fn read_u8(&self) -> u8 {
-        self.row_select | match self.row_select {
-            0x10 => self.rows[0],
-            0x20 => self.rows[1],
-            _ => 0u8
+    // Whether a button is held, regardless of which rows are selected.
+    pub fn is_pressed(&self, button: InputButton) -> bool {
+        let (mask, row) = button_mask(button);
+        self.rows[row] & mask == 0
+    }
+
+    // The input lines of the selected rows. A line is low if its button is pressed in any of
+    // the selected rows.
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.row_select & 0x20 == 0 {
+            lines &= self.rows[0];
         }
+        if self.row_select & 0x10 == 0 {
+            lines &= self.rows[1];
+        }
+        lines
+    }
+
+    // True if any selected line is low, which wakes the CPU from STOP.
+    pub fn any_line_low(&self) -> bool {
+        self.lines() != 0x0F
+    }
+
+    pub fn read_u8(&self) -> u8 {
+        self.row_select | self.lines()
     }
 
     pub fn write_u8(&mut self, value: u8) {
         self.row_select = value & 0x30;
     }
-}
\ No newline at end of file
+}
+
+// The bit of a button's line, and the row it's in.
+fn button_mask(button: InputButton) -> (u8, usize) {
+    match button {
+        InputButton::A => (0b0001, 0),
+        InputButton::B => (0b0010, 0),
+        InputButton::Select => (0b0100, 0),
+        InputButton::Start => (0b1000, 0),
+        InputButton::Right => (0b0001, 1),
+        InputButton::Left => (0b0010, 1),
+        InputButton::Down => (0b0100, 1),
+        InputButton::Up => (0b1000, 1),
+    }
+}
+
+impl IoDevice for Input {
+    fn read_io(&self, _addr: u16) -> u8 {
+        self.read_u8()
+    }
+
+    fn write_io(&mut self, _addr: u16, value: u8) -> u8 {
+        self.write_u8(value);
+        0
+    }
+
+    fn poke_io(&mut self, _addr: u16, value: u8) {
+        self.write_u8(value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn init_input() -> Input {
+        Input::new(|code: KeyCode| -> Option<InputButton> {
+            match code {
+                KeyCode::Z => Some(InputButton::A),
+                KeyCode::Up => Some(InputButton::Up),
+                _ => None
+            }
+        })
+    }
+
+    #[test]
+    fn selected_rows() {
+        let mut input = init_input();
+        input.on_key_input(KeyCode::Up, KeyState::Pressed);
+
+        // Buttons only.
+        input.write_u8(0x10);
+        assert_eq!(input.read_u8(), 0x1F);
+        assert!(!input.any_line_low());
+
+        // Directions only.
+        input.write_u8(0x20);
+        assert_eq!(input.read_u8(), 0x27);
+        assert!(input.any_line_low());
+
+        // Both rows, with A also pressed.
+        input.on_key_input(KeyCode::Z, KeyState::Pressed);
+        input.write_u8(0x00);
+        assert_eq!(input.read_u8(), 0x06);
+
+        // Neither row.
+        input.write_u8(0x30);
+        assert_eq!(input.read_u8(), 0x3F);
+        assert!(!input.any_line_low());
+    }
+}
@@ -0,0 +1,183 @@
+/*
+    The I/O registers at 0xFF00-0xFF7F. Each device registers the address ranges it handles with
+    an IoMap, and Memory routes accesses through it. Unmapped ports read as 0xFF and ignore writes,
+    like an open bus, and bits which aren't implemented by a register always read as 1.
+*/
+
+use std::fmt;
+
+pub const IO_START: u16 = 0xFF00;
+pub const IO_END: u16   = 0xFF7F;
+
+pub trait IoDevice {
+    fn read_io(&self, addr: u16) -> u8;
+
+    // Returns any interrupts requested by the write, using the bit layout of IF.
+    fn write_io(&mut self, addr: u16, value: u8) -> u8;
+
+    // Access a register for a debugger, without side effects or logging. Most devices have no
+    // side effects on reads.
+    fn peek_io(&self, addr: u16) -> u8 {
+        self.read_io(addr)
+    }
+
+    fn poke_io(&mut self, addr: u16, value: u8);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoDeviceId {
+    Joypad,
+    Serial,
+    Timer,
+    Interrupts,
+    Apu,
+    Ppu,
+    Dma,
+    BootRom,
+    Cgb
+}
+
+impl fmt::Display for IoDeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            IoDeviceId::Joypad => "Joypad",
+            IoDeviceId::Serial => "Serial",
+            IoDeviceId::Timer => "Timer",
+            IoDeviceId::Interrupts => "Interrupts",
+            IoDeviceId::Apu => "APU",
+            IoDeviceId::Ppu => "PPU",
+            IoDeviceId::Dma => "OAM DMA",
+            IoDeviceId::BootRom => "Boot ROM",
+            IoDeviceId::Cgb => "CGB registers"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// An inclusive range of ports handled by a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoMapping {
+    pub start: u16,
+    pub end: u16,
+    pub device: IoDeviceId
+}
+
+pub struct IoMap {
+    mappings: Vec<IoMapping>,
+    ports: [Option<IoDeviceId>; (IO_END - IO_START + 1) as usize]
+}
+
+impl IoMap {
+    pub fn new() -> IoMap {
+        IoMap {
+            mappings: Vec::new(),
+            ports: [None; (IO_END - IO_START + 1) as usize]
+        }
+    }
+
+    // The devices present on a DMG, or on a CGB when running in CGB mode.
+    pub fn standard(cgb: bool) -> IoMap {
+        let mut map = IoMap::new();
+        map.map(0xFF00, 0xFF00, IoDeviceId::Joypad);
+        map.map(0xFF01, 0xFF02, IoDeviceId::Serial);
+        map.map(0xFF04, 0xFF07, IoDeviceId::Timer);
+        map.map(0xFF0F, 0xFF0F, IoDeviceId::Interrupts);
+        map.map(0xFF10, 0xFF3F, IoDeviceId::Apu);
+        map.map(0xFF40, 0xFF45, IoDeviceId::Ppu);
+        map.map(0xFF46, 0xFF46, IoDeviceId::Dma);
+        map.map(0xFF47, 0xFF4B, IoDeviceId::Ppu);
+        map.map(0xFF50, 0xFF50, IoDeviceId::BootRom);
+        if cgb {
+            map.map(0xFF4D, 0xFF4D, IoDeviceId::Cgb);
+            map.map(0xFF51, 0xFF56, IoDeviceId::Cgb);
+            map.map(0xFF68, 0xFF6B, IoDeviceId::Ppu);
+            map.map(0xFF70, 0xFF70, IoDeviceId::Cgb);
+        }
+        map
+    }
+
+    // The devices present while the CGB boot ROM runs. It's always in CGB mode, and KEY0 is only
+    // mapped until it finishes, so that it can pick the mode for the cartridge.
+    pub fn cgb_boot() -> IoMap {
+        let mut map = IoMap::standard(true);
+        map.map(0xFF4C, 0xFF4C, IoDeviceId::Cgb);
+        map
+    }
+
+    pub fn map(&mut self, start: u16, end: u16, device: IoDeviceId) {
+        assert!(IO_START <= start && start <= end && end <= IO_END, "Invalid I/O range 0x{:04X}-0x{:04X}", start, end);
+        for addr in start..end + 1 {
+            let port = &mut self.ports[(addr - IO_START) as usize];
+            if let Some(existing) = *port {
+                panic!("ERROR: {} and {} are both mapped to 0x{:04X}", existing, device, addr);
+            }
+            *port = Some(device);
+        }
+        self.mappings.push(IoMapping { start: start, end: end, device: device });
+    }
+
+    pub fn device_at(&self, addr: u16) -> Option<IoDeviceId> {
+        match addr {
+            IO_START...IO_END => self.ports[(addr - IO_START) as usize],
+            _ => None
+        }
+    }
+
+    // Every mapped range, in the order they were registered, for debugging tools.
+    pub fn mappings(&self) -> &[IoMapping] {
+        &self.mappings
+    }
+}
+
+// Bits of a register which aren't implemented, and so always read as 1. Write-only registers
+// read as 0xFF.
+pub fn unused_bits(addr: u16) -> u8 {
+    match addr {
+        0xFF00 => 0xC0,                             // P1
+        0xFF02 => 0x7E,                             // SC
+        0xFF07 => 0xF8,                             // TAC
+        0xFF0F => 0xE0,                             // IF
+        0xFF10 => 0x80,                             // NR10
+        0xFF11 | 0xFF16 => 0x3F,                    // NR11, NR21
+        0xFF13 | 0xFF18 | 0xFF1B | 0xFF1D => 0xFF,  // Frequency and length, write-only
+        0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => 0xBF,  // NRx4
+        0xFF1A => 0x7F,                             // NR30
+        0xFF1C => 0x9F,                             // NR32
+        0xFF20 => 0xFF,                             // NR41
+        0xFF26 => 0x70,                             // NR52
+        0xFF15 | 0xFF1F | 0xFF27...0xFF2F => 0xFF,  // Unused
+        0xFF41 => 0x80,                             // STAT
+        0xFF4D => 0x7E,                             // KEY1
+        0xFF51...0xFF54 => 0xFF,                    // HDMA1-4, write-only
+        0xFF56 => 0x3C,                             // RP
+        0xFF68 | 0xFF6A => 0x40,                    // BCPS, OCPS
+        0xFF70 => 0xF8,                             // SVBK
+        _ => 0x00
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn standard_map() {
+        let dmg = IoMap::standard(false);
+        assert_eq!(dmg.device_at(0xFF00), Some(IoDeviceId::Joypad));
+        assert_eq!(dmg.device_at(0xFF03), None);
+        assert_eq!(dmg.device_at(0xFF46), Some(IoDeviceId::Dma));
+        assert_eq!(dmg.device_at(0xFF4D), None);
+        assert_eq!(dmg.device_at(0xFFFF), None);
+        assert_eq!(IoMap::standard(true).device_at(0xFF4D), Some(IoDeviceId::Cgb));
+        assert_eq!(IoMap::standard(true).device_at(0xFF4C), None);
+        assert_eq!(IoMap::cgb_boot().device_at(0xFF4C), Some(IoDeviceId::Cgb));
+    }
+
+    #[test]
+    #[should_panic]
+    fn overlapping_ranges() {
+        let mut map = IoMap::new();
+        map.map(0xFF04, 0xFF07, IoDeviceId::Timer);
+        map.map(0xFF07, 0xFF07, IoDeviceId::Dma);
+    }
+}
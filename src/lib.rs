@@ -0,0 +1,26 @@
+#![allow(dead_code)]
+
+extern crate time;
+
+#[macro_use]
+extern crate unborrow;
+
+pub mod cpu;
+pub mod io;
+pub mod cartridge;
+pub mod gpu;
+pub mod display;
+pub mod input;
+pub mod memory;
+pub mod timer;
+pub mod serial;
+pub mod apu;
+pub mod dma;
+pub mod cgb;
+pub mod lint;
+pub mod power_on;
+pub mod model;
+pub mod boot_rom;
+pub mod colourisation;
+pub mod vgm;
+pub mod emulator;
@@ -0,0 +1,162 @@
+/*
+    Checks for things which happen to work in emulators but fail on hardware, for homebrew
+    developers. Memory and the GPU report what the running program does, and each issue is
+    reported once for each instruction which causes it:
+
+    - VRAM accessed during mode 3, or OAM during modes 2 and 3.
+    - The LCD turned off outside of vblank, which can damage a DMG's screen.
+    - WRAM or HRAM read before anything was written to it, which is random on power up.
+    - Writes to ROM on a cartridge with no MBC.
+    - Any access to echo RAM at 0xE000-0xFDFF, which some flash carts don't mirror.
+    - OAM DMA from 0xE000 or above, which copies garbage.
+    - The stack pointer in ROM, where pushes are lost.
+*/
+
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    VramLocked,
+    OamLocked,
+    LcdOffOutsideVblank,
+    UninitialisedRead,
+    RomWrite,
+    EchoRam,
+    DmaSource,
+    StackInRom
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            LintKind::VramLocked => "VRAM accessed during mode 3",
+            LintKind::OamLocked => "OAM accessed during mode 2 or 3",
+            LintKind::LcdOffOutsideVblank => "LCD turned off outside of vblank",
+            LintKind::UninitialisedRead => "Uninitialised RAM read",
+            LintKind::RomWrite => "Write to ROM with no MBC",
+            LintKind::EchoRam => "Echo RAM accessed",
+            LintKind::DmaSource => "OAM DMA from an invalid source",
+            LintKind::StackInRom => "Stack pointer in ROM"
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub kind: LintKind,
+    pub addr: u16,
+    pub pc: u16,
+    // The ROM bank of the instruction, if it ran from cartridge ROM.
+    pub bank: Option<usize>
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{} at {:04X}, PC = {:02X}:{:04X}", self.kind, self.addr, bank, self.pc),
+            None => write!(f, "{} at {:04X}, PC = {:04X}", self.kind, self.addr, self.pc)
+        }
+    }
+}
+
+pub struct Linter {
+    lints: Vec<Lint>,
+    reported: HashSet<(LintKind, u16, Option<usize>)>,
+    // The instruction being executed.
+    pc: u16,
+    bank: Option<usize>,
+    stack_in_rom: bool,
+    // Which bytes of WRAM and HRAM have been written.
+    wram_written: Vec<bool>,
+    hram_written: Vec<bool>
+}
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter {
+            lints: Vec::new(),
+            reported: HashSet::new(),
+            pc: 0,
+            bank: None,
+            stack_in_rom: false,
+            wram_written: vec![false; 0x2000],
+            hram_written: vec![false; 0x7F]
+        }
+    }
+
+    pub fn begin_instruction(&mut self, pc: u16, bank: Option<usize>, sp: u16) {
+        self.pc = pc;
+        self.bank = bank;
+        // A stack pointer of 0 pushes to HRAM, so is fine. Only report the instruction which
+        // moved the stack into ROM, rather than every one after it.
+        let stack_in_rom = sp != 0 && sp <= 0x8000;
+        if stack_in_rom && !self.stack_in_rom {
+            self.report(LintKind::StackInRom, sp);
+        }
+        self.stack_in_rom = stack_in_rom;
+    }
+
+    pub fn report(&mut self, kind: LintKind, addr: u16) {
+        if self.reported.insert((kind, self.pc, self.bank)) {
+            let lint = Lint { kind: kind, addr: addr, pc: self.pc, bank: self.bank };
+            println!("WARNING: Lint: {}", lint);
+            self.lints.push(lint);
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) {
+        let written = match addr {
+            0xC000...0xDFFF => self.wram_written[addr as usize - 0xC000],
+            0xE000...0xFDFF => {
+                self.report(LintKind::EchoRam, addr);
+                self.wram_written[addr as usize - 0xE000]
+            },
+            0xFF80...0xFFFE => self.hram_written[addr as usize - 0xFF80],
+            _ => true
+        };
+        if !written {
+            self.report(LintKind::UninitialisedRead, addr);
+        }
+    }
+
+    pub fn write(&mut self, addr: u16) {
+        match addr {
+            0xC000...0xDFFF => self.wram_written[addr as usize - 0xC000] = true,
+            0xE000...0xFDFF => {
+                self.report(LintKind::EchoRam, addr);
+                self.wram_written[addr as usize - 0xE000] = true;
+            },
+            0xFF80...0xFFFE => self.hram_written[addr as usize - 0xFF80] = true,
+            _ => {}
+        }
+    }
+
+    pub fn lints(&self) -> &[Lint] {
+        &self.lints
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_once() {
+        let mut linter = Linter::new();
+        linter.begin_instruction(0x4123, Some(2), 0xFFFE);
+        linter.read(0xC000);
+        linter.read(0xC001);
+        linter.write(0xC002);
+        linter.read(0xC002);
+        linter.begin_instruction(0x0150, Some(0), 0x4000);
+        linter.read(0xC000);
+        assert_eq!(linter.lints(), &[
+            Lint { kind: LintKind::UninitialisedRead, addr: 0xC000, pc: 0x4123, bank: Some(2) },
+            Lint { kind: LintKind::StackInRom, addr: 0x4000, pc: 0x0150, bank: Some(0) },
+            Lint { kind: LintKind::UninitialisedRead, addr: 0xC000, pc: 0x0150, bank: Some(0) }
+        ]);
+        assert_eq!(format!("{}", linter.lints()[0]), "Uninitialised RAM read at C000, PC = 02:4123");
+    }
+}
@@ -1,101 +1,186 @@
-#![allow(dead_code)]
+extern crate gbc_rs;
 
-extern crate time;
+use std::env;
+use std::ops::Range;
 
-#[macro_use]
-extern crate unborrow;
+use gbc_rs::emulator::Emulator;
+use gbc_rs::display::Display;
+use gbc_rs::cpu::analyser::Analysis;
+use gbc_rs::cpu::trace::TraceOptions;
+use gbc_rs::cpu::trace_diff;
+use gbc_rs::cartridge::Cartridge;
+use gbc_rs::power_on::PowerOnFill;
+use gbc_rs::model::Model;
+use gbc_rs::input::InputButton;
 
-mod cpu;
-mod cartridge;
-mod gpu;
-mod input;
-mod memory;
+// Parse a hex address, with an optional $ or 0x prefix.
+fn parse_addr(text: &str) -> Option<u16> {
+    let text = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(text, 16).ok()
+}
 
-use std::rc::Rc;
-use std::cell::RefCell;
+// Parse an address range such as 0100-0200, which excludes the end address.
+fn parse_addr_range(text: &str) -> Option<Range<u16>> {
+    let mut parts = text.splitn(2, '-');
+    match (parts.next().and_then(parse_addr), parts.next().and_then(parse_addr)) {
+        (Some(start), Some(end)) => Some(start..end),
+        _ => None
+    }
+}
 
-use cpu::interpreter::Cpu;
-use memory::Memory;
-use cartridge::Cartridge;
+// Parse a bank:address pair such as 02:4000, with the bank in hex.
+fn parse_bank_addr(text: &str) -> Option<(usize, u16)> {
+    let mut parts = text.splitn(2, ':');
+    match (parts.next().and_then(|b| usize::from_str_radix(b, 16).ok()), parts.next().and_then(parse_addr)) {
+        (Some(bank), Some(addr)) => Some((bank, addr)),
+        _ => None
+    }
+}
 
-pub struct Emulator {
-    cpu: Cpu,
-    memory: Rc<RefCell<Memory>>,
+// Parse buttons joined with +, such as up+a.
+fn parse_buttons(text: &str) -> Result<Vec<InputButton>, String> {
+    text.split('+').map(InputButton::parse).collect()
 }
 
-impl Emulator {
-    pub fn new() -> Emulator {
-        let m = Rc::new(RefCell::new(Memory::new()));
-        Emulator {
-            cpu: Cpu::new(m.clone()),
-            memory: m.clone()
+// diff <ours> <reference> [--context N]
+// Find the first line where two instruction traces diverge.
+fn diff_traces(args: Vec<String>) {
+    let mut files = Vec::new();
+    let mut context = 5;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => context = args.next().and_then(|a| a.parse().ok()).unwrap_or(context),
+            _ => files.push(arg)
         }
     }
-
-    pub fn load(&mut self, rom: &str) {
-        match Cartridge::load(rom) {
-            Ok(c) => self.memory.borrow_mut().load_cartridge(c),
-            Err(e) => println!("WARNING: Cartridge failed to load. Reason: {}", e)
-        }
+    if files.len() != 2 {
+        println!("Usage: diff <ours> <reference> [--context N]");
+        return;
     }
-
-    pub fn boot(&mut self) {
-        self.memory.borrow_mut().write_u8(0xFF50, 0x0);
-        self.memory.borrow_mut().load_bios(BOOTROM);
+    match trace_diff::diff_files(&files[0], &files[1], context) {
+        Ok(Some(report)) => print!("{}", report),
+        Ok(None) => println!("status: Traces match"),
+        Err(e) => println!("WARNING: Failed to diff traces. Reason: {}", e)
     }
+}
 
-    fn frame(&mut self) -> bool {
-        let frame_clock = self.cpu.clock.t + 70224;
-        while self.cpu.clock.t < frame_clock {
-            // CPU.
-            self.cpu.tick();
-            if !self.cpu.running {
-                println!("status: CPU has stopped running");
-                return false;
-            }
+fn main() {
+    if env::args().nth(1).map_or(false, |a| a == "diff") {
+        diff_traces(env::args().skip(2).collect());
+        return;
+    }
 
-            // MMU (and hardware).
-            if !self.memory.borrow_mut().tick((self.cpu.last_instr_time * 4) as u32) {
-                return false;
-            }
+    let mut rom = "roms/opus5.gb".to_string();
+    let mut vgm_file = None;
+    let mut disassembly_dir = None;
+    let mut entry_points = Vec::new();
+    let mut trace_file = None;
+    let mut trace_options = TraceOptions::default();
+    let mut access_locks = true;
+    let mut lint = false;
+    let mut power_on_fill = None;
+    let mut model = Model::Dmg;
+    let mut boot_rom = None;
+    let mut boot_buttons = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vgm" => vgm_file = args.next(),
+            "--disassemble" => disassembly_dir = args.next(),
+            "--entry" => match args.next().and_then(|a| parse_bank_addr(&a)) {
+                Some(entry) => entry_points.push(entry),
+                None => println!("WARNING: --entry needs a bank:address pair, e.g. 02:4000")
+            },
+            "--trace" => trace_file = args.next(),
+            "--trace-pc" => trace_options.pc_range = args.next().and_then(|a| parse_addr_range(&a)),
+            "--trace-bank" => match args.next().and_then(|a| usize::from_str_radix(&a, 16).ok()) {
+                Some(bank) => trace_options.bank = Some(bank),
+                None => println!("WARNING: --trace-bank needs a bank number in hex, e.g. 0A")
+            },
+            "--trace-limit" => trace_options.limit = args.next().and_then(|a| a.parse().ok()),
+            "--no-access-locks" => access_locks = false,
+            "--lint" => lint = true,
+            "--boot-rom" => boot_rom = args.next(),
+            "--model" => match args.next().map(|a| Model::parse(&a)) {
+                Some(Ok(m)) => model = m,
+                Some(Err(e)) => println!("WARNING: {}", e),
+                None => println!("WARNING: --model needs dmg, mgb, sgb, sgb2, cgb or agb")
+            },
+            "--ram-fill" => match args.next().map(|a| PowerOnFill::parse(&a)) {
+                Some(Ok(fill)) => power_on_fill = Some(fill),
+                Some(Err(e)) => println!("WARNING: {}", e),
+                None => println!("WARNING: --ram-fill needs zero, ones, random:<seed>, dmg or cgb")
+            },
+            "--hold" => match args.next().map(|a| parse_buttons(&a)) {
+                Some(Ok(buttons)) => boot_buttons = buttons,
+                Some(Err(e)) => println!("WARNING: {}", e),
+                None => println!("WARNING: --hold needs buttons joined with +, e.g. up+a")
+            },
+            _ => rom = arg
         }
-        true
     }
 
-    pub fn run(&mut self) {
-        // Run frames.
-        loop {
-            if !self.frame() {
-                break;
-            }
+    // Write the ROM out as RGBDS source instead of running it.
+    if let Some(dir) = disassembly_dir {
+        match Cartridge::load(&rom) {
+            Ok(c) => {
+                let mut analysis = Analysis::new(&*c.mbc);
+                for &(bank, addr) in &entry_points {
+                    if let Err(e) = analysis.add_entry_point(bank, addr) {
+                        println!("WARNING: Failed to add entry point. Reason: {}", e);
+                    }
+                }
+                println!("status: Found {} labels in {} banks", analysis.labels().len(), analysis.bank_count());
+                if let Err(e) = analysis.save(&dir) {
+                    println!("WARNING: Failed to save disassembly. Reason: {}", e);
+                }
+            },
+            Err(e) => println!("WARNING: Cartridge failed to load. Reason: {}", e)
         }
-
-        // Dump memory on exit.
-        println!("{}", self.memory.borrow().dump_state(0x8000..0x9FFF));
+        return;
     }
-}
 
-fn main() {
-    let mut device = Emulator::new();
-    device.load("roms/opus5.gb");
-    device.boot();
-    device.run();
+    let mut device = Emulator::new(model);
+    if let Some(fill) = power_on_fill {
+        device.set_power_on_fill(fill);
+    }
+    device.set_boot_buttons(boot_buttons);
+    // The boot ROM has to be loaded before the cartridge, or it's skipped.
+    let booted = match boot_rom.as_ref().map(|s| s.as_str()) {
+        Some("builtin") => device.boot(),
+        Some(filename) => device.load_boot_rom(filename),
+        None => Ok(())
+    };
+    if let Err(e) = booted {
+        println!("WARNING: Failed to load boot ROM, skipping it. Reason: {}", e);
+    }
+    device.load(&rom);
+    device.set_access_locks(access_locks);
+    if lint {
+        device.start_lint();
+    }
+    if vgm_file.is_some() {
+        device.start_audio_log();
+    }
+    if let Some(filename) = trace_file {
+        if let Err(e) = device.start_trace(&filename, trace_options) {
+            println!("WARNING: Failed to start trace. Reason: {}", e);
+        }
+    }
+    device.run(&mut Display::new(2));
+    if device.blocked_accesses() > 0 {
+        println!("status: {} VRAM/OAM accesses were blocked by the PPU", device.blocked_accesses());
+    }
+    if lint {
+        println!("status: Found {} lint issues", device.lints().len());
+    }
+    if let Err(e) = device.stop_trace() {
+        println!("WARNING: Failed to save trace. Reason: {}", e);
+    }
+    if let Some(filename) = vgm_file {
+        if let Err(e) = device.save_audio_log(&filename) {
+            println!("WARNING: Failed to save audio log. Reason: {}", e);
+        }
+    }
 }
-
-static BOOTROM: [u8; 256] = [
-    0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
-    0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
-    0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1A, 0xCD, 0x95, 0x00, 0xCD, 0x96, 0x00, 0x13, 0x7B,
-    0xFE, 0x34, 0x20, 0xF3, 0x11, 0xD8, 0x00, 0x06, 0x08, 0x1A, 0x13, 0x22, 0x23, 0x05, 0x20, 0xF9,
-    0x3E, 0x19, 0xEA, 0x10, 0x99, 0x21, 0x2F, 0x99, 0x0E, 0x0C, 0x3D, 0x28, 0x08, 0x32, 0x0D, 0x20,
-    0xF9, 0x2E, 0x0F, 0x18, 0xF3, 0x67, 0x3E, 0x64, 0x57, 0xE0, 0x42, 0x3E, 0x91, 0xE0, 0x40, 0x04,
-    0x1E, 0x02, 0x0E, 0x0C, 0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, 0x0D, 0x20, 0xF7, 0x1D, 0x20, 0xF2,
-    0x0E, 0x13, 0x24, 0x7C, 0x1E, 0x83, 0xFE, 0x62, 0x28, 0x06, 0x1E, 0xC1, 0xFE, 0x64, 0x20, 0x06,
-    0x7B, 0xE2, 0x0C, 0x3E, 0x87, 0xF2, 0xF0, 0x42, 0x90, 0xE0, 0x42, 0x15, 0x20, 0xD2, 0x05, 0x20,
-    0x4F, 0x16, 0x20, 0x18, 0xCB, 0x4F, 0x06, 0x04, 0xC5, 0xCB, 0x11, 0x17, 0xC1, 0xCB, 0x11, 0x17,
-    0x05, 0x20, 0xF5, 0x22, 0x23, 0x22, 0x23, 0xC9, 0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B,
-    0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
-    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
-    0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E, 0x3c, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x4C,
-    0x21, 0x04, 0x01, 0x11, 0xA8, 0x00, 0x1A, 0x13, 0xBE, 0x20, 0xFE, 0x23, 0x7D, 0xFE, 0x34, 0x20,
-    0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x20, 0xFE, 0x3E, 0x01, 0xE0, 0x50];
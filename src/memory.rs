@@ -1,50 +1,61 @@
 use cartridge::Cartridge;
+use cpu::bus::Bus;
 use gpu::Gpu;
-use input::Input;
+use input::{Input, InputButton, KeyCode, KeyState};
+use timer::Timer;
+use serial::Serial;
+use apu::Apu;
+use dma::{Dma, DMA_REG};
+use cgb::CgbRegisters;
+use io::{IoDevice, IoDeviceId, IoMap, IoMapping, unused_bits};
+use vgm::VgmLog;
+use lint::{Lint, LintKind, Linter};
+use power_on::{PowerOnFill, RamRegion};
+use model::Model;
+use boot_rom::BootRom;
+use colourisation;
 use std::ops::Range;
-use std::rc::Rc;
-use std::cell::RefCell;
 
 pub struct Memory {
-    pub boot_mode: bool,
-
     // Hardware
+    boot_rom: BootRom,
     cartridge: Option<Cartridge>,
     gpu: Gpu,
-    input: Rc<RefCell<Input>>,
+    input: Input,
+    timer: Timer,
+    serial: Serial,
+    apu: Apu,
+    dma: Dma,
+    cgb: CgbRegisters,
+    io_map: IoMap,
 
     // Internal RAM structures
-    bios: [u8; 0x100],
     //vram: [u8; 8192],
     bank: [u8; 8192],
     internal: [u8; 8192],
     //oam: [u8; 160],
     zero_page_ram: [u8; 127],
 
+    // Cycles elapsed since power on, at normal speed
+    cycles: u64,
+
     // Registers
-    dmg_status: u8,
+    interrupt_flags: InterruptFlags,
     interrupts_enabled: u8,
 
     // Gameboy Colour specifics
+    model: Model,
     cgb_enabled: bool,
-    cgb_wram_bank_select: u8,
-    cgb_double_speed_prep: u8,
-    cgb_hdma_src_high: u8,
-    cgb_hdma_src_low: u8,
-    cgb_hdma_dest_high: u8,
-    cgb_hdma_dest_low: u8
+    boot_buttons: Vec<InputButton>, // Held while a skipped CGB boot ROM would run
+
+    power_on_fill: PowerOnFill,
+
+    // Debugging
+    linter: Option<Linter>
 }
 
 // Registers
-pub const DMG_STATUS_REG: u16               = 0xFF50;
-pub const CGB_INFRARED_PORT_REG: u16        = 0xFF56;
-pub const CGB_WRAM_BANK_SELECT: u16         = 0xFF70;
-pub const CGB_DOUBLE_SPEED_PREP_REG: u16    = 0xFF4D;
-pub const CGB_HDMA_SOURCE_HIGH_REG: u16     = 0xFF51;
-pub const CGB_HDMA_SOURCE_LOW_REG: u16      = 0xFF52;
-pub const CGB_HDMA_DEST_HIGH_REG: u16       = 0xFF53;
-pub const CGB_HDMA_DEST_LOW_REG: u16        = 0xFF54;
-pub const CGB_HDMA_REG: u16                 = 0xFF55;
+pub const INTERRUPT_FLAGS_REG: u16          = 0xFF0F;
 pub const INTERRUPTS_ENABLED_REG: u16       = 0xFFFF;
 
 // Interrupt Enable masks
@@ -53,16 +64,31 @@ pub const INTERRUPT_ENABLE_LCDC: u8         = 0b00000010;
 pub const INTERRUPT_ENABLE_TIMER: u8        = 0b00000100;
 pub const INTERRUPT_ENABLE_SERIAL_IO: u8    = 0b00001000;
 
-impl Memory {
-    pub fn new() -> Memory {
-        let mut memory = Memory {
-            boot_mode: true,
-            bios: [0u8; 0x100],
+// Requested interrupts, IF.
+struct InterruptFlags(u8);
+
+impl IoDevice for InterruptFlags {
+    fn read_io(&self, _addr: u16) -> u8 {
+        self.0
+    }
+
+    fn write_io(&mut self, _addr: u16, value: u8) -> u8 {
+        self.0 = value & 0x1F;
+        0
+    }
+
+    fn poke_io(&mut self, _addr: u16, value: u8) {
+        self.0 = value & 0x1F;
+    }
+}
 
+impl Memory {
+    pub fn new(model: Model) -> Memory {
+        Memory {
+            boot_rom: BootRom::new(),
             cartridge: None,
-            gpu: Gpu::new(),
-            input: Rc::new(RefCell::new(Input::new(|code: super::gpu::KeyCode| -> Option<super::input::InputButton> {
-                use super::gpu::KeyCode;
+            gpu: Gpu::new(model),
+            input: Input::new(|code: KeyCode| -> Option<super::input::InputButton> {
                 use super::input::InputButton;
                 match code {
                     KeyCode::Z => Some(InputButton::A),
@@ -75,77 +101,192 @@ impl Memory {
                     KeyCode::Down => Some(InputButton::Down),
                     _ => None,
                 }
-            }))),
+            }),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            apu: Apu::new(model),
+            dma: Dma::new(),
+            cgb: CgbRegisters::new(),
+            io_map: IoMap::standard(false),
 
             bank: [0u8; 8192],
             internal: [0u8; 8192],
             zero_page_ram: [0u8; 127],
 
-            dmg_status: 0,
+            cycles: 0,
+
+            interrupt_flags: InterruptFlags(0),
             interrupts_enabled: 0,
 
+            model: model,
             cgb_enabled: false,
-            cgb_wram_bank_select: 0,
-            cgb_double_speed_prep: 0,
-            cgb_hdma_src_low: 0,
-            cgb_hdma_src_high: 0,
-            cgb_hdma_dest_low: 0,
-            cgb_hdma_dest_high: 0
-        };
-        let input_ref = memory.input.clone();
-        memory.gpu.set_key_callback(Box::new(move |code: super::gpu::KeyCode, state: super::gpu::KeyState| {
-            input_ref.borrow_mut().on_key_input(code, state);
-        }));
-        memory
+            boot_buttons: Vec::new(),
+            power_on_fill: PowerOnFill::Zero,
+            linter: None
+        }
     }
 
     // Load cartridge
-    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
-        self.cgb_enabled = cartridge.is_cgb_enabled();
+    // CGB cartridges run in CGB mode on CGB hardware. Everything else runs in DMG mode, which is
+    // the compatibility mode on CGB hardware. A CGB boot ROM runs in CGB mode whatever the
+    // cartridge, and picks the mode when it finishes.
+    pub fn load_cartridge(&mut self, mut cartridge: Cartridge) {
+        if self.model.is_cgb() && self.boot_rom.is_mapped() {
+            self.cgb_enabled = true;
+            self.io_map = IoMap::cgb_boot();
+        } else {
+            self.cgb_enabled = self.model.is_cgb() && cartridge.is_cgb_enabled();
+            self.io_map = IoMap::standard(self.cgb_enabled);
+        }
+        self.power_on_fill.fill(RamRegion::CartridgeRam, cartridge.ram_mut());
         self.cartridge = Some(cartridge);
     }
 
-    // Load BIOS
-    pub fn load_bios(&mut self, bios: [u8; 0x100]) {
-        self.bios = bios;
+    // The buttons held while the boot ROM runs, when it's skipped. The CGB boot ROM uses them to
+    // pick palettes for DMG games.
+    pub fn set_boot_buttons(&mut self, buttons: Vec<InputButton>) {
+        self.boot_buttons = buttons;
+    }
+
+    // Fill RAM with its contents at power on. This also applies to cartridges loaded later.
+    pub fn set_power_on_fill(&mut self, fill: PowerOnFill) {
+        self.power_on_fill = fill;
+        fill.fill(RamRegion::Wram, &mut self.internal);
+        fill.fill(RamRegion::Hram, &mut self.zero_page_ram);
+        self.gpu.fill_ram(fill);
+        if let Some(ref mut c) = self.cartridge {
+            fill.fill(RamRegion::CartridgeRam, c.ram_mut());
+        }
+    }
+
+    // Map a boot ROM over the cartridge, until it unmaps itself. This has to happen before the
+    // cartridge is loaded, as loading it sets up the I/O registers for whether it will boot.
+    pub fn load_boot_rom(&mut self, rom: Vec<u8>) -> Result<(), String> {
+        if self.cartridge.is_some() {
+            return Err("The boot ROM has to be loaded before the cartridge".to_string());
+        }
+        self.boot_rom.load(rom, self.model)
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_mapped()
+    }
+
+    // Set the I/O registers to the values the boot ROM leaves behind, and unmap it. On CGB
+    // hardware, DMG games are also given the palettes the boot ROM would pick, using the boot
+    // buttons.
+    pub fn skip_boot(&mut self) {
+        for (addr, value) in self.model.post_boot_io() {
+            self.poke(addr, value);
+        }
+        if let Some(ref c) = self.cartridge {
+            if !c.logo_valid(self.model.is_cgb()) {
+                println!("WARNING: The Nintendo logo in the cartridge header is wrong, so it would not boot on hardware");
+            }
+            if self.model.is_cgb() && !self.cgb_enabled {
+                let combination = colourisation::select(c, &self.boot_buttons);
+                self.gpu.load_compat_palettes(&colourisation::palettes(combination));
+                println!("status: Colourising DMG game with palettes {}, title checksum 0x{:02X}",
+                    combination, colourisation::title_checksum(c));
+            }
+        }
+    }
+
+    // Audio logging
+    pub fn start_audio_log(&mut self) {
+        self.apu.start_audio_log();
+    }
+
+    pub fn stop_audio_log(&mut self) -> Option<VgmLog> {
+        self.apu.stop_audio_log()
+    }
+
+    // Tick. The timer runs at the CPU rate, everything else runs at normal speed.
+    pub fn tick(&mut self, cycles: u32) {
+        if self.timer.tick(cycles) {
+            self.interrupt_flags.0 |= INTERRUPT_ENABLE_TIMER;
+        }
+        let cycles = if self.cgb.double_speed() { cycles / 2 } else { cycles };
+        self.cycles += cycles as u64;
+        self.apu.tick(cycles);
+        self.gpu.tick(cycles);
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // Whether a CGB cartridge is running in CGB mode, rather than DMG or compatibility mode.
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_enabled
     }
 
-    pub fn set_boot_mode(&mut self, boot: bool) {
-        self.boot_mode = boot;
+    // The ROM bank mapped at an address, or None if the address isn't in cartridge ROM.
+    pub fn rom_bank(&self, addr: u16) -> Option<usize> {
+        match (addr, &self.cartridge) {
+            _ if self.boot_rom.maps(addr) => None,
+            (0x0000...0x3FFF, &Some(_)) => Some(0),
+            (0x4000...0x7FFF, &Some(ref c)) => Some(c.rom_bank()),
+            _ => None
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.gpu.framebuffer()
     }
 
-    // Tick
-    pub fn tick(&mut self, cycles: u32) -> bool {
-        self.gpu.tick(cycles)
+    // Every byte sent over the serial port so far.
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    // The I/O devices and the ports they are mapped to.
+    pub fn io_mappings(&self) -> &[IoMapping] {
+        self.io_map.mappings()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Called by STOP when not switching speed. The divider resets and the LCD blanks until the
+    // CPU is woken by the joypad.
+    pub fn stop(&mut self) {
+        if self.timer.reset_div() {
+            self.interrupt_flags.0 |= INTERRUPT_ENABLE_TIMER;
+        }
+        self.gpu.blank();
+    }
+
+    pub fn key_input(&mut self, code: KeyCode, state: KeyState) {
+        self.input.on_key_input(code, state);
+    }
+
+    pub fn joypad_line_low(&self) -> bool {
+        self.input.any_line_low()
+    }
+
+    // Called by STOP. If a speed switch has been armed through KEY1, toggle between normal and
+    // double speed and return true.
+    pub fn speed_switch(&mut self) -> bool {
+        if !self.cgb_enabled || !self.cgb.speed_switch() {
+            return false;
+        }
+        self.timer.reset_div();
+        true
     }
 
     // Memory Reading
     pub fn read_u8(&self, addr: u16) -> u8 {
         match addr {
-            0x0000...0x7FFF => {
-                if addr < 0x100 && self.boot_mode {
-                    self.bios[addr as usize]
-                } else {
-                    if let Some(ref c) = self.cartridge {
-                        c.read_u8(addr)
-                    } else {
-                        panic!("ERROR: No cartridge is loaded!");
-                    }
-                }
-            },
+            _ if self.boot_rom.maps(addr) => self.boot_rom.read_u8(addr),
+            // With no cartridge in the slot nothing drives the bus, so it floats high.
+            0x0000...0x7FFF | 0xA000...0xBFFF => self.cartridge.as_ref().map_or(0xFF, |c| c.read_u8(addr)),
             0x8000...0x9FFF => self.gpu.read_u8(addr),
-            0xA000...0xBFFF => {
-                if let Some(ref c) = self.cartridge {
-                    c.read_u8(addr)
-                } else {
-                    panic!("ERROR: No cartridge is loaded!");
-                }
-            },
             0xC000...0xDFFF => self.internal[addr as usize - 0xC000],
             0xE000...0xFDFF => self.internal[addr as usize - 0xE000],
             0xFE00...0xFE9F => self.gpu.read_u8(addr),
-            0xFEA0...0xFEFF => { println!("WARNING: Reading from unused memory area. Addr = 0x{:X}", addr); 0 },
-            // TODO: 0xFF00..0xFF7F => IO PORTS IN CUSTOM IO MODULE (which should contain registers).
+            0xFEA0...0xFEFF => self.gpu.read_unusable(addr),
             0xFF00...0xFF7F => self.read_u8_io(addr),
             0xFF80...0xFFFE => self.zero_page_ram[addr as usize - 0xFF80],
             0xFFFF => self.interrupts_enabled,
@@ -153,32 +294,20 @@ impl Memory {
         }
     }
 
-    pub fn read_u16(&self, value: u16) -> u16 {
-        return self.read_u8(value) as u16 + ((self.read_u8(value + 1) as u16) << 8);
-    }
-
     // Memory Writing
     pub fn write_u8(&mut self, addr: u16, value: u8) {
         match addr {
-            0x0000...0x7FFF => {
+            0x0000...0x7FFF | 0xA000...0xBFFF => {
                 if let Some(ref mut c) = self.cartridge {
                     c.write_u8(addr, value);
-                } else {
-                    panic!("ERROR: No cartridge is loaded!");
                 }
             },
             0x8000...0x9FFF => self.gpu.write_u8(addr, value),
-            0xA000...0xBFFF =>  {
-                if let Some(ref mut c) = self.cartridge {
-                    c.write_u8(addr, value);
-                } else {
-                    panic!("ERROR: No cartridge is loaded!");
-                }
-            },
             0xC000...0xDFFF => self.internal[addr as usize - 0xC000] = value,
             0xE000...0xFDFF => self.internal[addr as usize - 0xE000] = value,
             0xFE00...0xFE9F => self.gpu.write_u8(addr, value),
-            0xFEA0...0xFEFF => println!("WARNING: Writing to unused memory area. Addr = 0x{:X}", addr),
+            // Writes to the unusable area go nowhere.
+            0xFEA0...0xFEFF => {},
             0xFF00...0xFF7F => self.write_u8_io(addr, value),
             0xFF80...0xFFFE => self.zero_page_ram[addr as usize - 0xFF80] = value,
             0xFFFF => self.interrupts_enabled = value,
@@ -186,65 +315,183 @@ impl Memory {
         }
     }
 
-    pub fn write_u16(&mut self, addr: u16, value: u16) {
-        self.write_u8(addr + 0, ((value >> 0) & 0xFF) as u8);
-        self.write_u8(addr + 1, ((value >> 8) & 0xFF) as u8);
+    // I/O registers, routed to whichever device is mapped at the address.
+    fn io_device(&self, id: IoDeviceId) -> &IoDevice {
+        match id {
+            IoDeviceId::Joypad => &self.input,
+            IoDeviceId::Serial => &self.serial,
+            IoDeviceId::Timer => &self.timer,
+            IoDeviceId::Interrupts => &self.interrupt_flags,
+            IoDeviceId::Apu => &self.apu,
+            IoDeviceId::Ppu => &self.gpu,
+            IoDeviceId::Dma => &self.dma,
+            IoDeviceId::BootRom => &self.boot_rom,
+            IoDeviceId::Cgb => &self.cgb
+        }
     }
 
-    // Registers
-    fn read_u8_io(&self, addr: u16) -> u8{
-        match addr {
-            0xFF00 => self.input.borrow().read_u8(),
-            0xFF40...0xFF4F => self.gpu.read_u8(addr),
-            DMG_STATUS_REG => self.dmg_status,
-            _ => if self.cgb_enabled {
+    fn io_device_mut(&mut self, id: IoDeviceId) -> &mut IoDevice {
+        match id {
+            IoDeviceId::Joypad => &mut self.input,
+            IoDeviceId::Serial => &mut self.serial,
+            IoDeviceId::Timer => &mut self.timer,
+            IoDeviceId::Interrupts => &mut self.interrupt_flags,
+            IoDeviceId::Apu => &mut self.apu,
+            IoDeviceId::Ppu => &mut self.gpu,
+            IoDeviceId::Dma => &mut self.dma,
+            IoDeviceId::BootRom => &mut self.boot_rom,
+            IoDeviceId::Cgb => &mut self.cgb
+        }
+    }
+
+    fn read_u8_io(&self, addr: u16) -> u8 {
+        match self.io_map.device_at(addr) {
+            Some(id) => self.io_device(id).read_io(addr) | unused_bits(addr),
+            None => 0xFF
+        }
+    }
+
+    fn write_u8_io(&mut self, addr: u16, value: u8) {
+        if let Some(id) = self.io_map.device_at(addr) {
+            let booting = self.boot_rom.is_mapped();
+            let interrupts = self.io_device_mut(id).write_io(addr, value);
+            self.interrupt_flags.0 |= interrupts;
+            if booting && !self.boot_rom.is_mapped() {
+                self.finish_boot();
+            }
+        }
+        if let Some(source) = self.dma.take_transfer() {
+            self.oam_dma(source);
+        }
+    }
+
+    // Once the CGB boot ROM unmaps itself, switch to the mode it picked through KEY0. In DMG
+    // compatibility mode, the palettes it wrote to palette RAM are kept for drawing.
+    fn finish_boot(&mut self) {
+        if !self.model.is_cgb() {
+            return;
+        }
+        self.cgb_enabled = !self.cgb.dmg_compatible();
+        self.io_map = IoMap::standard(self.cgb_enabled);
+        if !self.cgb_enabled {
+            self.gpu.use_compat_palettes();
+            println!("status: Running in DMG compatibility mode");
+        }
+    }
+
+    // Copy 160 bytes to OAM. This happens instantly, rather than over 160 cycles. DMA has its
+    // own path to OAM, so isn't locked out by the PPU.
+    fn oam_dma(&mut self, source: u16) {
+        for i in 0..0xA0 {
+            let value = self.read_u8(source + i);
+            self.gpu.poke(0xFE00 + i, value);
+        }
+    }
+
+    // Whether the CPU is locked out of VRAM and OAM while the PPU is using them.
+    pub fn set_access_locks(&mut self, enabled: bool) {
+        self.gpu.set_access_locks(enabled);
+    }
+
+    // The number of CPU accesses to VRAM and OAM which were blocked by the PPU.
+    pub fn blocked_accesses(&self) -> u64 {
+        self.gpu.blocked_accesses()
+    }
+
+    // Report CPU accesses which would be a problem on hardware.
+    pub fn start_lint(&mut self) {
+        self.linter = Some(Linter::new());
+    }
+
+    pub fn lints(&self) -> &[Lint] {
+        self.linter.as_ref().map_or(&[], |l| l.lints())
+    }
+
+    // The value is given for writes.
+    fn lint(&mut self, addr: u16, write: Option<u8>) {
+        if self.linter.is_none() {
+            return;
+        }
+        let gpu_lint = self.gpu.lint(addr, write);
+        let rom_only = self.cartridge.as_ref().map_or(false, |c| c.is_rom_only());
+        let linter = self.linter.as_mut().unwrap();
+        if let Some(kind) = gpu_lint {
+            linter.report(kind, addr);
+        }
+        match write {
+            Some(value) => {
                 match addr {
-                    CGB_INFRARED_PORT_REG => {
-                        println!("WARNING: CGB Infrared Unsupported");
-                        0
-                    },
-                    CGB_WRAM_BANK_SELECT => self.cgb_wram_bank_select,
-                    CGB_DOUBLE_SPEED_PREP_REG => self.cgb_double_speed_prep,
-                    CGB_HDMA_SOURCE_HIGH_REG => self.cgb_hdma_src_high,
-                    CGB_HDMA_SOURCE_LOW_REG => self.cgb_hdma_src_low,
-                    CGB_HDMA_DEST_HIGH_REG => self.cgb_hdma_dest_high,
-                    CGB_HDMA_DEST_LOW_REG => self.cgb_hdma_dest_low,
-                    CGB_HDMA_REG => {
-                        println!("WARNING: HDMA unsupported");
-                        0
-                    },
-                    _ => {
-                        println!("WARNING: Reading from unknown MMU register: 0x{:X}", addr);
-                        0
-                    }
+                    0x0000...0x7FFF if rom_only => linter.report(LintKind::RomWrite, addr),
+                    DMA_REG if value >= 0xE0 => linter.report(LintKind::DmaSource, addr),
+                    _ => {}
                 }
-            } else {
-                println!("WARNING: Cannot read from 0x{:08X} (CGB register) in non-CGB mode!", addr);
-                0
-            }
+                linter.write(addr);
+            },
+            None => linter.read(addr)
         }
     }
-    
-    fn write_u8_io(&mut self, addr: u16, value: u8) {
+
+    // Debugger access. These never log, panic or have side effects on the hardware, and address
+    // banked memory through whichever bank is currently mapped.
+    pub fn peek(&self, addr: u16) -> u8 {
         match addr {
-            0xFF00 => self.input.borrow_mut().write_u8(value),
-            0xFF40...0xFF4F => self.gpu.write_u8(addr, value),
-            DMG_STATUS_REG => self.dmg_status = value,
-            _ => if self.cgb_enabled {
-                match addr {
-                    CGB_INFRARED_PORT_REG => println!("WARNING: CGB Infrared Unsupported"),
-                    CGB_WRAM_BANK_SELECT => self.cgb_wram_bank_select = value,
-                    CGB_DOUBLE_SPEED_PREP_REG => self.cgb_double_speed_prep = value,
-                    CGB_HDMA_SOURCE_HIGH_REG => self.cgb_hdma_src_high = value,
-                    CGB_HDMA_SOURCE_LOW_REG => self.cgb_hdma_src_low = value,
-                    CGB_HDMA_DEST_HIGH_REG => self.cgb_hdma_dest_high = value,
-                    CGB_HDMA_DEST_LOW_REG => self.cgb_hdma_dest_low = value,
-                    CGB_HDMA_REG => println!("WARNING: HDMA unsupported"),
-                    _ => println!("WARNING: Writing to unknown MMU register: 0x{:X}", addr)
+            0x0000...0x3FFF => self.peek_bank(0, addr),
+            0x4000...0x7FFF => self.peek_bank(self.cartridge.as_ref().map_or(1, |c| c.rom_bank()), addr),
+            0xA000...0xBFFF => self.peek_bank(self.cartridge.as_ref().map_or(0, |c| c.ram_bank()), addr),
+            _ => self.peek_bank(0, addr)
+        }
+    }
+
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        let bank = match addr {
+            0x4000...0x7FFF => self.cartridge.as_ref().map_or(1, |c| c.rom_bank()),
+            0xA000...0xBFFF => self.cartridge.as_ref().map_or(0, |c| c.ram_bank()),
+            _ => 0
+        };
+        self.poke_bank(bank, addr, value);
+    }
+
+    // Debugger access to a bank:address pair, e.g. 02:4000 for the start of ROM bank 2. The bank
+    // selects the cartridge ROM bank for 0x0000-0x7FFF and the RAM bank for 0xA000-0xBFFF, and
+    // is ignored elsewhere. Cartridge memory that doesn't exist reads 0xFF.
+    pub fn peek_bank(&self, bank: usize, addr: u16) -> u8 {
+        match addr {
+            _ if bank == 0 && self.boot_rom.maps(addr) => self.boot_rom.read_u8(addr),
+            0x0000...0x7FFF | 0xA000...0xBFFF => self.cartridge.as_ref().map_or(0xFF, |c| c.peek(bank, addr)),
+            0x8000...0x9FFF | 0xFE00...0xFE9F => self.gpu.peek(addr),
+            0xC000...0xDFFF => self.internal[addr as usize - 0xC000],
+            0xE000...0xFDFF => self.internal[addr as usize - 0xE000],
+            0xFEA0...0xFEFF => self.gpu.read_unusable(addr),
+            0xFF00...0xFF7F => match self.io_map.device_at(addr) {
+                Some(id) => self.io_device(id).peek_io(addr) | unused_bits(addr),
+                None => 0xFF
+            },
+            0xFF80...0xFFFE => self.zero_page_ram[addr as usize - 0xFF80],
+            _ => self.interrupts_enabled
+        }
+    }
+
+    // Pokes to cartridge ROM patch the ROM image rather than talking to the MBC, and pokes to
+    // I/O registers set them without starting transfers or requesting interrupts.
+    pub fn poke_bank(&mut self, bank: usize, addr: u16, value: u8) {
+        match addr {
+            _ if bank == 0 && self.boot_rom.maps(addr) => self.boot_rom.write_u8(addr, value),
+            0x0000...0x7FFF | 0xA000...0xBFFF => {
+                if let Some(ref mut c) = self.cartridge {
+                    c.poke(bank, addr, value);
                 }
-            } else {
-                println!("WARNING: Cannot write to 0x{:X} (CGB register) in non-CGB mode!", addr);
-            }
+            },
+            0x8000...0x9FFF | 0xFE00...0xFE9F => self.gpu.poke(addr, value),
+            0xC000...0xDFFF => self.internal[addr as usize - 0xC000] = value,
+            0xE000...0xFDFF => self.internal[addr as usize - 0xE000] = value,
+            0xFEA0...0xFEFF => {},
+            0xFF00...0xFF7F => {
+                if let Some(id) = self.io_map.device_at(addr) {
+                    self.io_device_mut(id).poke_io(addr, value);
+                }
+            },
+            0xFF80...0xFFFE => self.zero_page_ram[addr as usize - 0xFF80] = value,
+            _ => self.interrupts_enabled = value
         }
     }
 
@@ -255,7 +502,7 @@ impl Memory {
         let line_length = 16;
         let mut addr_line = String::new();
         for addr in addr_range {
-            addr_line.push_str(&format!("{:02x} ", self.read_u8(addr)));
+            addr_line.push_str(&format!("{:02x} ", self.peek(addr)));
             counter += 1;
             if counter >= line_length {
                 counter = 0;
@@ -266,3 +513,267 @@ impl Memory {
         return out_str;
     }
 }
+
+impl Bus for Memory {
+    fn read_u8(&mut self, addr: u16) -> u8 {
+        self.lint(addr, None);
+        Memory::read_u8(self, addr)
+    }
+
+    fn peek_u8(&self, addr: u16) -> u8 {
+        Memory::peek(self, addr)
+    }
+
+    fn write_u8(&mut self, addr: u16, value: u8) {
+        self.lint(addr, Some(value));
+        Memory::write_u8(self, addr, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Memory::tick(self, cycles)
+    }
+
+    fn begin_instruction(&mut self, pc: u16, sp: u16) {
+        let bank = self.rom_bank(pc);
+        if let Some(ref mut linter) = self.linter {
+            linter.begin_instruction(pc, bank, sp);
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> Option<usize> {
+        Memory::rom_bank(self, addr)
+    }
+
+    fn joypad_line_low(&self) -> bool {
+        Memory::joypad_line_low(self)
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        Memory::speed_switch(self)
+    }
+
+    fn stop(&mut self) {
+        Memory::stop(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn io_registers() {
+        let mut memory = Memory::new(Model::Dmg);
+        // Unmapped ports and unused bits read as 1.
+        assert_eq!(memory.read_u8(0xFF03), 0xFF);
+        assert_eq!(memory.read_u8(INTERRUPT_FLAGS_REG), 0xE0);
+        // CGB registers aren't mapped in DMG mode.
+        memory.write_u8(0xFF4D, 0x01);
+        assert_eq!(memory.read_u8(0xFF4D), 0xFF);
+
+        // A serial transfer requests an interrupt.
+        memory.write_u8(0xFF01, 0x42);
+        memory.write_u8(0xFF02, 0x81);
+        assert_eq!(memory.serial_output(), &[0x42]);
+        assert_eq!(memory.read_u8(INTERRUPT_FLAGS_REG), 0xE0 | INTERRUPT_ENABLE_SERIAL_IO);
+
+        assert_eq!(memory.io_mappings()[0], IoMapping { start: 0xFF00, end: 0xFF00, device: IoDeviceId::Joypad });
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut memory = Memory::new(Model::Dmg);
+        for i in 0..0xA0 {
+            memory.write_u8(0xC100 + i, i as u8);
+        }
+        memory.write_u8(0xFF46, 0xC1);
+        assert_eq!(memory.read_u8(0xFF46), 0xC1);
+        assert_eq!(memory.read_u8(0xFE00), 0x00);
+        assert_eq!(memory.read_u8(0xFE9F), 0x9F);
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut memory = Memory::new(Model::Dmg);
+        // An empty cartridge slot floats high, and writes to it go nowhere.
+        memory.write_u8(0x2000, 0x01);
+        memory.write_u8(0xA000, 0x42);
+        assert_eq!(memory.read_u8(0x0100), 0xFF);
+        assert_eq!(memory.read_u8(0xA000), 0xFF);
+
+        memory.write_u8(0xFEA0, 0x42);
+        assert_eq!(memory.read_u8(0xFEA0), 0x00);
+        assert_eq!(memory.read_u8(0xFEF5), 0x00);
+
+        // CGB registers read 0xFF in DMG mode.
+        for &addr in &[0xFF4D, 0xFF4F, 0xFF55, 0xFF68, 0xFF69, 0xFF70] {
+            memory.write_u8(addr, 0x00);
+            assert_eq!(memory.read_u8(addr), 0xFF);
+        }
+
+        // The CGB is different, even in compatibility mode.
+        let mut memory = Memory::new(Model::Cgb);
+        memory.load_cartridge(Cartridge::from_rom(vec![0; 0x8000]).unwrap());
+        assert_eq!(memory.read_u8(0xFEA0), 0xAA);
+        assert_eq!(memory.read_u8(0xFEF5), 0xFF);
+        assert_eq!(memory.read_u8(0xFF4D), 0xFF);
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        memory.load_cartridge(Cartridge::from_rom(rom).unwrap());
+        assert_eq!(memory.read_u8(0xFF4D), 0x7E);
+
+        // A CGB cartridge runs in DMG mode on a DMG.
+        let mut memory = Memory::new(Model::Dmg);
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        memory.load_cartridge(Cartridge::from_rom(rom).unwrap());
+        assert_eq!(memory.read_u8(0xFEA0), 0x00);
+        assert_eq!(memory.read_u8(0xFF4D), 0xFF);
+    }
+
+    #[test]
+    fn access_locks() {
+        let mut memory = Memory::new(Model::Dmg);
+        memory.write_u8(0x8000, 0x11);
+        memory.write_u8(0xFE00, 0x22);
+        memory.write_u8(0xFF40, 0x80);
+
+        // The PPU starts in hblank, so this reaches mode 2 of the next line.
+        memory.tick(204);
+        assert_eq!(memory.read_u8(0x8000), 0x11);
+        assert_eq!(memory.read_u8(0xFE00), 0xFF);
+        assert_eq!(memory.read_u8(0xFEA0), 0xFF);
+        memory.write_u8(0xFE00, 0x33);
+
+        // Mode 3.
+        memory.tick(80);
+        assert_eq!(memory.read_u8(0x8000), 0xFF);
+        memory.write_u8(0x8000, 0x44);
+        assert_eq!(memory.blocked_accesses(), 4);
+        assert_eq!(memory.peek(0x8000), 0x11);
+        assert_eq!(memory.peek(0xFE00), 0x22);
+
+        memory.set_access_locks(false);
+        memory.write_u8(0x8000, 0x44);
+        assert_eq!(memory.read_u8(0x8000), 0x44);
+        assert_eq!(memory.blocked_accesses(), 4);
+    }
+
+    #[test]
+    fn lint() {
+        let mut memory = Memory::new(Model::Dmg);
+        memory.load_cartridge(Cartridge::from_rom(vec![0; 0x8000]).unwrap());
+        memory.start_lint();
+        memory.begin_instruction(0x0150, 0xFFFE);
+        Bus::write_u8(&mut memory, 0x2000, 0x01);
+        // The echo RAM write initialises 0xC000.
+        Bus::write_u8(&mut memory, 0xE000, 0x01);
+        Bus::read_u8(&mut memory, 0xC000);
+        Bus::read_u8(&mut memory, 0xC001);
+        memory.begin_instruction(0x0153, 0xFFFE);
+        Bus::read_u8(&mut memory, 0xC000);
+        Bus::write_u8(&mut memory, 0xFF40, 0x80);
+        Bus::write_u8(&mut memory, 0xFF46, 0xFE);
+        memory.begin_instruction(0x0155, 0xFFFE);
+        Bus::write_u8(&mut memory, 0xFF40, 0x00);
+
+        let lints: Vec<(LintKind, u16)> = memory.lints().iter().map(|l| (l.kind, l.pc)).collect();
+        assert_eq!(lints, vec![
+            (LintKind::RomWrite, 0x0150),
+            (LintKind::EchoRam, 0x0150),
+            (LintKind::UninitialisedRead, 0x0150),
+            (LintKind::DmaSource, 0x0153),
+            (LintKind::LcdOffOutsideVblank, 0x0155)
+        ]);
+    }
+
+    #[test]
+    fn power_on_fill() {
+        let mut memory = Memory::new(Model::Dmg);
+        memory.set_power_on_fill(PowerOnFill::Ones);
+        assert_eq!(memory.read_u8(0xC000), 0xFF);
+        assert_eq!(memory.read_u8(0xFF80), 0xFF);
+        assert_eq!(memory.read_u8(0x9FFF), 0xFF);
+        assert_eq!(memory.read_u8(0xFE00), 0xFF);
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        memory.load_cartridge(Cartridge::from_rom(rom).unwrap());
+        memory.set_power_on_fill(PowerOnFill::Cgb);
+        assert_eq!(memory.peek_bank(3, 0xA008), 0xFF);
+        assert_eq!(memory.read_u8(0xC000), 0x00);
+        assert_eq!(memory.read_u8(0xC008), 0xFF);
+        assert_eq!(memory.read_u8(0x8000), 0x00);
+    }
+
+    #[test]
+    fn colourisation() {
+        // Draw a frame of colour 0 tiles, which BGP maps to shade 1, and return the first pixel.
+        let shade_1 = |buttons: Vec<InputButton>| {
+            let mut rom = vec![0; 0x8000];
+            rom[0x134..0x13F].copy_from_slice(b"POKEMON RED");
+            rom[0x14B] = 0x01;
+            let mut memory = Memory::new(Model::Cgb);
+            memory.set_boot_buttons(buttons);
+            memory.load_cartridge(Cartridge::from_rom(rom).unwrap());
+            memory.skip_boot();
+            // The palette registers are only mapped in CGB mode.
+            assert_eq!(memory.read_u8(0xFF68), 0xFF);
+
+            memory.write_u8(0xFF47, 0x55);
+            for _ in 0..70224 / 4 {
+                memory.tick(4);
+            }
+            memory.framebuffer()[0..3].to_vec()
+        };
+        assert_eq!(shade_1(Vec::new()), vec![255, 132, 132]);
+        // Holding Left picks blue instead.
+        assert_eq!(shade_1(vec![InputButton::Left]), vec![99, 165, 255]);
+    }
+
+    #[test]
+    fn ppu_registers() {
+        let mut memory = Memory::new(Model::Dmg);
+        for &(addr, value) in &[(0xFF45, 0x90), (0xFF48, 0xE4), (0xFF49, 0x1B), (0xFF4A, 0x40), (0xFF4B, 0x07)] {
+            memory.write_u8(addr, value);
+            assert_eq!(memory.read_u8(addr), value);
+            assert_eq!(memory.peek(addr), value);
+        }
+    }
+
+    #[test]
+    fn peek_and_poke() {
+        let mut memory = Memory::new(Model::Dmg);
+        // Nothing is loaded, so cartridge memory is open bus rather than a panic.
+        assert_eq!(memory.peek(0x0100), 0xFF);
+        assert_eq!(memory.peek_bank(3, 0xA000), 0xFF);
+
+        let mut rom = vec![0; 0x10000];
+        rom[0x147] = 0x01;
+        rom[0x2 * 0x4000 + 0x10] = 0x22;
+        rom[0x3 * 0x4000 + 0x10] = 0x33;
+        memory.load_cartridge(Cartridge::from_rom(rom).unwrap());
+        assert_eq!(memory.peek(0x4010), 0x00);
+        assert_eq!(memory.peek_bank(2, 0x4010), 0x22);
+        memory.write_u8(0x2000, 0x03);
+        assert_eq!(memory.peek(0x4010), 0x33);
+        assert_eq!(memory.read_u8(0x4010), 0x33);
+
+        // Poking ROM patches it, rather than selecting a bank.
+        memory.poke(0x2000, 0x02);
+        assert_eq!(memory.peek(0x4010), 0x33);
+        assert_eq!(memory.peek_bank(0, 0x2000), 0x02);
+
+        // Poking a register has no side effects.
+        memory.poke(0xFF01, 0x42);
+        memory.poke(0xFF02, 0x81);
+        assert!(memory.serial_output().is_empty());
+        assert_eq!(memory.peek(0xFF02), 0xFF);
+        assert_eq!(memory.peek(INTERRUPT_FLAGS_REG), 0xE0);
+        memory.poke(0xFF46, 0xC1);
+        memory.poke(0xC100, 0x55);
+        assert_eq!(memory.peek(0xFE00), 0x00);
+        assert_eq!(memory.peek(0xFF46), 0xC1);
+    }
+}
@@ -0,0 +1,111 @@
+/*
+    The Gameboy models. They run the same CPU, but differ in the register values left by the
+    boot ROM and in some hardware quirks:
+
+    Model  A   F   B   C   D   E   H   L
+    DMG    01  B0  00  13  00  D8  01  4D
+    MGB    FF  B0  00  13  00  D8  01  4D
+    SGB    01  00  00  14  00  00  C0  60
+    SGB2   FF  00  00  14  00  00  C0  60
+    CGB    11  80  00  00  FF  56  00  0D   (00 08 00 7C for D-L in compatibility mode)
+    AGB    11  00  01  00  FF  56  00  0D   (00 08 00 7C for D-L in compatibility mode)
+
+    The CGB and AGB run CGB cartridges in CGB mode, and older cartridges in a compatibility mode
+    which mostly behaves like a DMG. The other models always run in DMG mode.
+*/
+
+use cpu::registers::Registers;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb
+}
+
+impl Model {
+    // Parse a model from the command line, e.g. cgb.
+    pub fn parse(text: &str) -> Result<Model, String> {
+        match text.to_lowercase().as_str() {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "sgb2" => Ok(Model::Sgb2),
+            "cgb" => Ok(Model::Cgb),
+            "agb" => Ok(Model::Agb),
+            _ => Err(format!("Unknown model {}", text))
+        }
+    }
+
+    // Whether this is CGB hardware, whichever mode it's running in.
+    pub fn is_cgb(&self) -> bool {
+        match *self {
+            Model::Cgb | Model::Agb => true,
+            _ => false
+        }
+    }
+
+    // The registers after the boot ROM has finished.
+    pub fn post_boot_registers(&self, cgb_mode: bool) -> Registers {
+        let (a, f, b, c, d, e, h, l) = match *self {
+            Model::Dmg => (0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Sgb2 => (0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb if cgb_mode => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C),
+            Model::Agb if cgb_mode => (0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            Model::Agb => (0x11, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00, 0x7C)
+        };
+        Registers { a: a, f: f, b: b, c: c, d: d, e: e, h: h, l: l, sp: 0xFFFE, pc: 0x0100 }
+    }
+
+    // The I/O registers after the boot ROM has finished, which includes unmapping it. DIV depends
+    // on how long the boot ROM took, which varies on the models other than the DMG and MGB.
+    pub fn post_boot_io(&self) -> Vec<(u16, u8)> {
+        let div = match *self {
+            Model::Dmg | Model::Mgb => 0xAB,
+            _ => 0x00
+        };
+        // The SGB boot ROM doesn't play the startup sound.
+        let nr52 = match *self {
+            Model::Sgb | Model::Sgb2 => 0xF0,
+            _ => 0xF1
+        };
+        vec![
+            (0xFF00, 0xCF), (0xFF04, div), (0xFF0F, 0xE1),
+            // APU
+            (0xFF26, nr52),
+            (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0xBF),
+            (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+            (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+            (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF),
+            (0xFF24, 0x77), (0xFF25, 0xF3),
+            // PPU
+            (0xFF40, 0x91), (0xFF47, 0xFC),
+            (0xFF50, 0x01)
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn post_boot_registers() {
+        let dmg = Model::Dmg.post_boot_registers(false);
+        assert_eq!((dmg.af(), dmg.bc(), dmg.de(), dmg.hl()), (0x01B0, 0x0013, 0x00D8, 0x014D));
+        let cgb = Model::Cgb.post_boot_registers(true);
+        assert_eq!((cgb.af(), cgb.bc(), cgb.de(), cgb.hl()), (0x1180, 0x0000, 0xFF56, 0x000D));
+        let agb = Model::Agb.post_boot_registers(false);
+        assert_eq!((agb.af(), agb.bc(), agb.de(), agb.hl()), (0x1100, 0x0100, 0x0008, 0x007C));
+        assert_eq!(agb.pc, 0x0100);
+
+        assert!(Model::parse("AGB").unwrap().is_cgb());
+        assert!(!Model::parse("sgb2").unwrap().is_cgb());
+    }
+}
@@ -0,0 +1,119 @@
+/*
+    The contents of RAM at power on. Real hardware doesn't clear its RAM, so it starts out with
+    whatever the cells settle to. Zero filling hides initialisation bugs, and a few games depend
+    on the typical contents, so the fill can be chosen:
+
+    - Zero and Ones fill with 0x00 and 0xFF.
+    - Random fills with noise from a seed, so that runs can be repeated.
+    - Dmg approximates a DMG, where WRAM, HRAM and OAM are noise with most bits set, and VRAM
+      and cartridge RAM are mostly 0x00 with some bits set.
+    - Cgb approximates a CGB, where WRAM alternates 8 bytes of 0x00 and 8 bytes of 0xFF, HRAM is
+      noise, VRAM and OAM are clear and cartridge RAM is 0xFF.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerOnFill {
+    Zero,
+    Ones,
+    Random(u64),
+    Dmg,
+    Cgb
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamRegion {
+    Wram,
+    Hram,
+    Vram,
+    Oam,
+    CartridgeRam
+}
+
+// The seed for the noise in the DMG pattern, so that it's the same each run.
+const DMG_SEED: u64 = 0x0D36_B0A7_5EED;
+
+impl PowerOnFill {
+    // Parse a fill from the command line: zero, ones, random:<seed>, dmg or cgb.
+    pub fn parse(text: &str) -> Result<PowerOnFill, String> {
+        let mut parts = text.splitn(2, ':');
+        match (parts.next().unwrap_or(""), parts.next()) {
+            ("zero", None) => Ok(PowerOnFill::Zero),
+            ("ones", None) => Ok(PowerOnFill::Ones),
+            ("random", Some(seed)) => seed.parse().map(PowerOnFill::Random).map_err(|e| format!("Invalid seed {}: {}", seed, e)),
+            ("random", None) => Ok(PowerOnFill::Random(0)),
+            ("dmg", None) => Ok(PowerOnFill::Dmg),
+            ("cgb", None) => Ok(PowerOnFill::Cgb),
+            _ => Err(format!("Unknown power on fill {}", text))
+        }
+    }
+
+    pub fn fill(&self, region: RamRegion, ram: &mut [u8]) {
+        // Each region gets different noise from the same seed.
+        let mut noise = Noise::new(self.seed() ^ (region as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        for (i, byte) in ram.iter_mut().enumerate() {
+            *byte = match (*self, region) {
+                (PowerOnFill::Zero, _) => 0x00,
+                (PowerOnFill::Ones, _) => 0xFF,
+                (PowerOnFill::Random(_), _) => noise.next(),
+                (PowerOnFill::Dmg, RamRegion::Vram) | (PowerOnFill::Dmg, RamRegion::CartridgeRam) => noise.next() & noise.next() & noise.next(),
+                (PowerOnFill::Dmg, _) => noise.next() | noise.next(),
+                (PowerOnFill::Cgb, RamRegion::Wram) => if i & 0x08 == 0 { 0x00 } else { 0xFF },
+                (PowerOnFill::Cgb, RamRegion::Hram) => noise.next(),
+                (PowerOnFill::Cgb, RamRegion::CartridgeRam) => 0xFF,
+                (PowerOnFill::Cgb, _) => 0x00
+            };
+        }
+    }
+
+    fn seed(&self) -> u64 {
+        match *self {
+            PowerOnFill::Random(seed) => seed,
+            _ => DMG_SEED
+        }
+    }
+}
+
+// xorshift64*, which is plenty for filling RAM.
+struct Noise(u64);
+
+impl Noise {
+    fn new(seed: u64) -> Noise {
+        // The state must never be 0.
+        Noise(if seed == 0 { DMG_SEED } else { seed })
+    }
+
+    fn next(&mut self) -> u8 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fills() {
+        let mut ram = [0x12; 32];
+        PowerOnFill::Ones.fill(RamRegion::Wram, &mut ram);
+        assert!(ram.iter().all(|&b| b == 0xFF));
+        PowerOnFill::Cgb.fill(RamRegion::Wram, &mut ram);
+        assert_eq!(&ram[6..10], &[0x00, 0x00, 0xFF, 0xFF]);
+
+        // The same seed gives the same noise, but different regions differ.
+        let mut other = [0; 32];
+        PowerOnFill::Random(42).fill(RamRegion::Wram, &mut ram);
+        PowerOnFill::Random(42).fill(RamRegion::Wram, &mut other);
+        assert_eq!(ram, other);
+        PowerOnFill::Random(42).fill(RamRegion::Hram, &mut other);
+        assert!(ram != other);
+        PowerOnFill::Random(43).fill(RamRegion::Wram, &mut other);
+        assert!(ram != other);
+
+        assert_eq!(PowerOnFill::parse("random:7"), Ok(PowerOnFill::Random(7)));
+        assert_eq!(PowerOnFill::parse("dmg"), Ok(PowerOnFill::Dmg));
+        assert!(PowerOnFill::parse("garbage").is_err());
+    }
+}
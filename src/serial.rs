@@ -0,0 +1,62 @@
+use io::IoDevice;
+use memory::INTERRUPT_ENABLE_SERIAL_IO;
+
+pub const SERIAL_DATA_REG: u16      = 0xFF01;
+pub const SERIAL_CONTROL_REG: u16   = 0xFF02;
+
+// The serial port. There is never a link partner, so bytes sent are captured instead.
+pub struct Serial {
+    data: u8,
+    control: u8,
+    output: Vec<u8>
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            output: Vec::new()
+        }
+    }
+
+    // Every byte sent so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl IoDevice for Serial {
+    fn read_io(&self, addr: u16) -> u8 {
+        match addr {
+            SERIAL_DATA_REG => self.data,
+            _ => self.control
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            SERIAL_DATA_REG => { self.data = value; 0 },
+            _ => {
+                // A transfer using the internal clock completes immediately. With nothing
+                // connected, 0xFF is shifted in.
+                if value & 0x81 == 0x81 {
+                    self.output.push(self.data);
+                    self.data = 0xFF;
+                    self.control = value & 0x01;
+                    INTERRUPT_ENABLE_SERIAL_IO
+                } else {
+                    self.control = value & 0x81;
+                    0
+                }
+            }
+        }
+    }
+
+    fn poke_io(&mut self, addr: u16, value: u8) {
+        match addr {
+            SERIAL_DATA_REG => self.data = value,
+            _ => self.control = value & 0x81
+        }
+    }
+}
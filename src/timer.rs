@@ -0,0 +1,143 @@
+use io::IoDevice;
+use memory::INTERRUPT_ENABLE_TIMER;
+
+// Timer registers
+pub const DIV_REG: u16  = 0xFF04;
+pub const TIMA_REG: u16 = 0xFF05;
+pub const TMA_REG: u16  = 0xFF06;
+pub const TAC_REG: u16  = 0xFF07;
+
+pub struct Timer {
+    // DIV is the upper 8 bits of this counter, which increments every T-cycle.
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0
+        }
+    }
+
+    // Advance the timer by a number of CPU cycles. Returns true if TIMA overflowed and the timer
+    // interrupt should be requested.
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles {
+            let counter = self.counter.wrapping_add(1);
+            interrupt |= self.set_counter(counter);
+        }
+        interrupt
+    }
+
+    pub fn read_u8(&self, addr: u16) -> u8 {
+        match addr {
+            DIV_REG => (self.counter >> 8) as u8,
+            TIMA_REG => self.tima,
+            TMA_REG => self.tma,
+            TAC_REG => self.tac | 0xF8,
+            _ => panic!("ERROR: Invalid timer register. Addr = 0x{:X}", addr)
+        }
+    }
+
+    // Returns true if the write caused TIMA to overflow.
+    pub fn write_u8(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            DIV_REG => self.reset_div(),
+            TIMA_REG => { self.tima = value; false },
+            TMA_REG => { self.tma = value; false },
+            TAC_REG => {
+                // Changing the frequency or disabling the timer can also cause an increment.
+                let before = self.input();
+                self.tac = value & 0x07;
+                before && !self.input() && self.increment()
+            },
+            _ => panic!("ERROR: Invalid timer register. Addr = 0x{:X}", addr)
+        }
+    }
+
+    // Writing any value to DIV resets the whole counter.
+    pub fn reset_div(&mut self) -> bool {
+        self.set_counter(0)
+    }
+
+    // TIMA increments on the falling edge of the counter bit selected by TAC, while enabled.
+    fn input(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,     // 4096 Hz
+            1 => 3,     // 262144 Hz
+            2 => 5,     // 65536 Hz
+            _ => 7      // 16384 Hz
+        };
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn set_counter(&mut self, counter: u16) -> bool {
+        let before = self.input();
+        self.counter = counter;
+        before && !self.input() && self.increment()
+    }
+
+    fn increment(&mut self) -> bool {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = if overflow { self.tma } else { tima };
+        overflow
+    }
+}
+
+impl IoDevice for Timer {
+    fn read_io(&self, addr: u16) -> u8 {
+        self.read_u8(addr)
+    }
+
+    fn write_io(&mut self, addr: u16, value: u8) -> u8 {
+        if self.write_u8(addr, value) { INTERRUPT_ENABLE_TIMER } else { 0 }
+    }
+
+    // Set a register without TIMA being incremented by a falling edge.
+    fn poke_io(&mut self, addr: u16, value: u8) {
+        match addr {
+            DIV_REG => self.counter = (value as u16) << 8,
+            TIMA_REG => self.tima = value,
+            TMA_REG => self.tma = value,
+            _ => self.tac = value & 0x07
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn div_and_tima() {
+        let mut timer = Timer::new();
+        timer.tick(256);
+        assert_eq!(timer.read_u8(DIV_REG), 1);
+        assert_eq!(timer.read_u8(TIMA_REG), 0);
+
+        // 262144 Hz increments TIMA every 16 cycles.
+        timer.write_u8(DIV_REG, 0);
+        timer.write_u8(TAC_REG, 0x05);
+        timer.tick(16 * 3);
+        assert_eq!(timer.read_u8(DIV_REG), 0);
+        assert_eq!(timer.read_u8(TIMA_REG), 3);
+    }
+
+    #[test]
+    fn overflow_reloads_tma() {
+        let mut timer = Timer::new();
+        timer.write_u8(TMA_REG, 0xF0);
+        timer.write_u8(TIMA_REG, 0xFF);
+        timer.write_u8(TAC_REG, 0x05);
+        assert!(!timer.tick(15));
+        assert!(timer.tick(1));
+        assert_eq!(timer.read_u8(TIMA_REG), 0xF0);
+    }
+}
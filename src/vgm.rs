@@ -0,0 +1,174 @@
+/*
+    VGM 1.70 audio register log.
+
+    Every write to the APU registers (FF10-FF3F) is recorded together with the machine cycle it
+    happened on, and can be written out as a VGM stream using the Game Boy DMG chip commands.
+    See http://vgmrips.net/wiki/VGM_Specification for the format.
+*/
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+// VGM header layout.
+const VGM_VERSION: u32          = 0x0000_0170;
+const VGM_HEADER_SIZE: usize    = 0x100;
+const VGM_SAMPLE_RATE: u64      = 44100;
+const DMG_CLOCK: u64            = 4_194_304;
+
+// VGM commands.
+const CMD_DMG_WRITE: u8         = 0xB3;
+const CMD_WAIT: u8              = 0x61;
+const CMD_WAIT_NTSC: u8         = 0x62; // 735 samples.
+const CMD_WAIT_PAL: u8          = 0x63; // 882 samples.
+const CMD_WAIT_SHORT: u8        = 0x70; // 0x7n waits n+1 samples.
+const CMD_END: u8               = 0x66;
+
+// First APU register. Register offsets in the stream are relative to this.
+const APU_BASE_ADDR: u16        = 0xFF10;
+
+pub struct VgmLog {
+    start_cycle: Option<u64>,
+    writes: Vec<(u64, u8, u8)> // (cycle, register offset, value)
+}
+
+impl VgmLog {
+    pub fn new() -> VgmLog {
+        VgmLog {
+            start_cycle: None,
+            writes: Vec::new()
+        }
+    }
+
+    // Record a write to an APU register at the given cycle (in 4.19MHz clocks).
+    pub fn record(&mut self, cycle: u64, addr: u16, value: u8) {
+        if addr < APU_BASE_ADDR || addr > 0xFF3F {
+            return;
+        }
+        if self.start_cycle.is_none() {
+            self.start_cycle = Some(cycle);
+        }
+        self.writes.push((cycle, (addr - APU_BASE_ADDR) as u8, value));
+    }
+
+    pub fn write_count(&self) -> usize {
+        self.writes.len()
+    }
+
+    // Convert a cycle timestamp to a sample position relative to the start of the log.
+    fn sample_at(&self, cycle: u64) -> u64 {
+        let start = self.start_cycle.unwrap_or(0);
+        (cycle - start) * VGM_SAMPLE_RATE / DMG_CLOCK
+    }
+
+    fn write_wait(data: &mut Vec<u8>, mut samples: u64) {
+        while samples > 0 {
+            match samples {
+                735 => { data.push(CMD_WAIT_NTSC); samples = 0; },
+                882 => { data.push(CMD_WAIT_PAL); samples = 0; },
+                1...16 => { data.push(CMD_WAIT_SHORT | (samples - 1) as u8); samples = 0; },
+                _ => {
+                    let n = if samples > 0xFFFF { 0xFFFF } else { samples };
+                    data.push(CMD_WAIT);
+                    data.push((n & 0xFF) as u8);
+                    data.push((n >> 8) as u8);
+                    samples -= n;
+                }
+            }
+        }
+    }
+
+    // Build the complete VGM file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Command stream.
+        let mut data = Vec::new();
+        let mut last_sample = 0;
+        for &(cycle, reg, value) in &self.writes {
+            let sample = self.sample_at(cycle);
+            VgmLog::write_wait(&mut data, sample - last_sample);
+            last_sample = sample;
+            data.push(CMD_DMG_WRITE);
+            data.push(reg);
+            data.push(value);
+        }
+        data.push(CMD_END);
+
+        // Header.
+        let mut out = vec![0u8; VGM_HEADER_SIZE];
+        let file_size = VGM_HEADER_SIZE + data.len();
+        out[0x00..0x04].copy_from_slice(b"Vgm ");
+        write_u32(&mut out, 0x04, (file_size - 0x04) as u32);    // EoF offset.
+        write_u32(&mut out, 0x08, VGM_VERSION);
+        write_u32(&mut out, 0x18, last_sample as u32);           // Total samples.
+        write_u32(&mut out, 0x34, (VGM_HEADER_SIZE - 0x34) as u32); // VGM data offset.
+        write_u32(&mut out, 0x80, DMG_CLOCK as u32);             // GB DMG clock.
+        out.extend(data);
+        out
+    }
+
+    pub fn write_to(&self, out: &mut Write) -> io::Result<()> {
+        out.write_all(&self.to_bytes())
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        let mut f = match File::create(filename) {
+            Ok(f) => f,
+            Err(_) => return Err("Unable to create VGM file".to_string()),
+        };
+        if let Err(_) = self.write_to(&mut f) {
+            return Err("Unable to write VGM file".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset + 0] = (value >> 0) as u8;
+    buffer[offset + 1] = (value >> 8) as u8;
+    buffer[offset + 2] = (value >> 16) as u8;
+    buffer[offset + 3] = (value >> 24) as u8;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+        buffer[offset] as u32 |
+            (buffer[offset + 1] as u32) << 8 |
+            (buffer[offset + 2] as u32) << 16 |
+            (buffer[offset + 3] as u32) << 24
+    }
+
+    #[test]
+    fn empty_log_has_valid_header() {
+        let bytes = VgmLog::new().to_bytes();
+        assert_eq!(&bytes[0..4], b"Vgm ");
+        assert_eq!(read_u32(&bytes, 0x04) as usize, bytes.len() - 4);
+        assert_eq!(read_u32(&bytes, 0x08), 0x170);
+        assert_eq!(read_u32(&bytes, 0x34) as usize + 0x34, 0x100);
+        assert_eq!(read_u32(&bytes, 0x80), 4194304);
+        assert_eq!(&bytes[0x100..], &[CMD_END]);
+    }
+
+    #[test]
+    fn writes_are_separated_by_waits() {
+        let mut log = VgmLog::new();
+        log.record(1000, 0xFF26, 0x80);
+        log.record(1000 + 70224, 0xFF12, 0xF3); // One frame later.
+        log.record(1000 + 70224 + 200, 0xFF30, 0x01);
+        log.record(1000, 0xFF40, 0x91); // Not an APU register.
+        assert_eq!(log.write_count(), 3);
+
+        let bytes = log.to_bytes();
+        // 70224 cycles = 738 samples, then 200 cycles = 2 samples.
+        assert_eq!(&bytes[0x100..], &[
+            CMD_DMG_WRITE, 0x16, 0x80,
+            CMD_WAIT, 0xE2, 0x02,
+            CMD_DMG_WRITE, 0x02, 0xF3,
+            CMD_WAIT_SHORT | 1,
+            CMD_DMG_WRITE, 0x20, 0x01,
+            CMD_END][..]);
+        assert_eq!(read_u32(&bytes, 0x18), 740);
+    }
+}
@@ -0,0 +1,114 @@
+/*
+    Scaffolding shared by the integration tests. Each test binary only uses some of it.
+*/
+
+#![allow(dead_code)]
+
+use gbc_rs::emulator::Emulator;
+use gbc_rs::cartridge::Cartridge;
+use gbc_rs::model::Model;
+use gbc_rs::cpu::CpuEvent;
+use gbc_rs::cpu::assembler::assemble;
+
+// Test ROMs and references aren't distributed with the emulator, so are looked for here.
+pub const ROM_DIR: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/roms/tests");
+
+pub enum Outcome {
+    Pass,
+    Fail(String),
+    Timeout,
+    Missing
+}
+
+// Assemble a program which starts at the entry point into a cartridge.
+pub fn assembled(source: &str) -> Cartridge {
+    let source = format!("SECTION \"Test\", ROM0[$0100]\n{}", source);
+    Cartridge::from_rom(assemble(&source, 0).unwrap().bytes).unwrap()
+}
+
+// An emulator which starts from the state after the boot ROM, with LD B,B breakpoints turned on.
+pub fn harness(cartridge: Cartridge, model: Model) -> Emulator {
+    let mut emulator = Emulator::new(model);
+    emulator.set_breakpoints(true);
+    emulator.load_cartridge(cartridge);
+    emulator
+}
+
+pub fn is_breakpoint(event: &CpuEvent) -> bool {
+    match *event {
+        CpuEvent::Breakpoint { .. } => true,
+        _ => false
+    }
+}
+
+// Run frames until check returns an outcome, giving up after a number of frames. check is called
+// after each frame, or at a breakpoint, with the events since the last call. Illegal opcodes fail.
+pub fn run_frames<F>(emulator: &mut Emulator, frames: u32, mut check: F) -> Outcome
+    where F: FnMut(&Emulator, &[CpuEvent]) -> Option<Outcome>
+{
+    for _ in 0..frames {
+        if !emulator.frame() {
+            return Outcome::Fail("Emulator stopped".to_string());
+        }
+        let events = emulator.take_events();
+        for event in &events {
+            if let CpuEvent::IllegalOpcode { .. } = *event {
+                return Outcome::Fail(format!("{}", event));
+            }
+        }
+        if let Some(outcome) = check(emulator, &events) {
+            return outcome;
+        }
+    }
+    Outcome::Timeout
+}
+
+// A table of results, printed once every test has run.
+pub struct Results {
+    table: String,
+    show_passes: bool,
+    pub passes: usize,
+    pub failures: usize,
+    pub skipped: usize
+}
+
+impl Results {
+    pub fn new() -> Results {
+        Results {
+            table: String::new(),
+            show_passes: true,
+            passes: 0,
+            failures: 0,
+            skipped: 0
+        }
+    }
+
+    // Only list the failures, for runs with a lot of tests.
+    pub fn failures_only() -> Results {
+        Results { show_passes: false, ..Results::new() }
+    }
+
+    pub fn add(&mut self, name: &str, outcome: &Outcome) {
+        self.add_with_note(name, outcome, "");
+    }
+
+    pub fn add_with_note(&mut self, name: &str, outcome: &Outcome, note: &str) {
+        let result = match *outcome {
+            Outcome::Pass => { self.passes += 1; "pass".to_string() },
+            Outcome::Fail(ref reason) => { self.failures += 1; format!("FAIL  {}", reason) },
+            Outcome::Timeout => { self.failures += 1; "FAIL  Timed out".to_string() },
+            Outcome::Missing => { self.skipped += 1; "skip".to_string() }
+        };
+        if let Outcome::Pass = *outcome {
+            if !self.show_passes {
+                return;
+            }
+        }
+        let result = if note.is_empty() { result } else { format!("{}  ({})", result, note) };
+        self.table.push_str(&format!("{:<50} {}\n", name, result));
+    }
+
+    pub fn print(&self) {
+        println!("\n{}\n{} passed, {} failed, {} skipped", self.table, self.passes, self.failures, self.skipped);
+    }
+}
@@ -0,0 +1,261 @@
+/*
+    Screenshot comparison tests for the PPU. Each ROM is run headlessly for a number of frames, or
+    until it executes LD B,B, and the screen is compared against a reference PNG. The ROMs and
+    references aren't distributed with the emulator, so put them in roms/tests/ using the layout
+    below. Missing ROMs are skipped.
+
+    - dmg-acid2 and cgb-acid2: https://github.com/mattcurrie/dmg-acid2
+    - Regression screenshots: any screenshots/<name>.gb (or .gbc) with a matching <name>.png.
+
+    References can use different shades to ours, so DMG screenshots are compared through a palette
+    mapping: every reference colour must consistently correspond to one of our colours. On a
+    mismatch, <name>-actual.png and <name>-diff.png are written to target/screenshot-diffs/, with
+    the differing pixels in red.
+*/
+
+extern crate gbc_rs;
+extern crate png;
+
+mod common;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use gbc_rs::cartridge::Cartridge;
+use gbc_rs::model::Model;
+use gbc_rs::gpu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use common::{ROM_DIR, Outcome, Results, assembled, is_breakpoint, run_frames};
+
+const DIFF_DIR: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/screenshot-diffs");
+
+#[derive(Clone, Copy)]
+enum Compare {
+    Exact,
+    Palette
+}
+
+struct Screenshot {
+    rom: PathBuf,
+    reference: PathBuf,
+    compare: Compare,
+    frames: u32     // Maximum frames to run before taking the screenshot
+}
+
+// Run until the ROM hits a breakpoint or the frame limit, and return the screen.
+fn capture(cartridge: Cartridge, frames: u32) -> Result<Vec<u8>, String> {
+    // CGB cartridges run on a CGB.
+    let model = if cartridge.is_cgb_enabled() { Model::Cgb } else { Model::Dmg };
+    let mut emulator = common::harness(cartridge, model);
+    let outcome = run_frames(&mut emulator, frames, |_, events| {
+        if events.iter().any(is_breakpoint) { Some(Outcome::Pass) } else { None }
+    });
+    match outcome {
+        Outcome::Fail(e) => Err(e),
+        Outcome::Pass => {
+            // The breakpoint is hit partway through a frame, and lines are drawn straight into
+            // the framebuffer. Run for another frame's worth of cycles so that every line has been
+            // drawn since the breakpoint.
+            emulator.frame();
+            Ok(emulator.framebuffer())
+        },
+        _ => Ok(emulator.framebuffer())
+    }
+}
+
+// Load a PNG as RGB, 3 bytes per pixel.
+fn load_png(path: &Path) -> Result<(usize, usize, Vec<u8>), String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let data = &data[..info.buffer_size()];
+    let rgb = match info.color_type {
+        png::ColorType::Rgb => data.to_vec(),
+        png::ColorType::Rgba => data.chunks(4).flat_map(|p| vec![p[0], p[1], p[2]]).collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|&v| vec![v, v, v]).collect(),
+        png::ColorType::GrayscaleAlpha => data.chunks(2).flat_map(|p| vec![p[0], p[0], p[0]]).collect(),
+        png::ColorType::Indexed => return Err(format!("Unexpanded palette in {}", path.display()))
+    };
+    Ok((info.width as usize, info.height as usize, rgb))
+}
+
+fn save_png(path: &Path, rgb: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(rgb))
+        .map_err(|e| format!("Unable to write {}: {}", path.display(), e))
+}
+
+fn pixel(rgb: &[u8], i: usize) -> (u8, u8, u8) {
+    (rgb[i * 3], rgb[i * 3 + 1], rgb[i * 3 + 2])
+}
+
+// Compare two screens, returning which pixels differ.
+fn compare(actual: &[u8], expected: &[u8], mode: Compare) -> Vec<bool> {
+    let mut forward = HashMap::new();
+    let mut backward = HashMap::new();
+    (0..SCREEN_WIDTH * SCREEN_HEIGHT).map(|i| {
+        let ours = pixel(actual, i);
+        let theirs = pixel(expected, i);
+        match mode {
+            Compare::Exact => ours != theirs,
+            // Each colour maps to whichever colour it was first seen with, in both directions.
+            Compare::Palette => {
+                *forward.entry(theirs).or_insert(ours) != ours || *backward.entry(ours).or_insert(theirs) != theirs
+            }
+        }
+    }).collect()
+}
+
+// Highlight differing pixels in red over a dimmed copy of our screen.
+fn diff_image(actual: &[u8], mismatches: &[bool]) -> Vec<u8> {
+    mismatches.iter().enumerate().flat_map(|(i, &mismatch)| {
+        if mismatch {
+            vec![255, 0, 0]
+        } else {
+            let (r, g, b) = pixel(actual, i);
+            vec![r / 3, g / 3, b / 3]
+        }
+    }).collect()
+}
+
+fn save_diff(name: &str, actual: &[u8], mismatches: &[bool]) -> Result<PathBuf, String> {
+    fs::create_dir_all(DIFF_DIR).map_err(|e| format!("Unable to create {}: {}", DIFF_DIR, e))?;
+    let dir = Path::new(DIFF_DIR);
+    save_png(&dir.join(format!("{}-actual.png", name)), actual)?;
+    let diff = dir.join(format!("{}-diff.png", name));
+    save_png(&diff, &diff_image(actual, mismatches))?;
+    Ok(diff)
+}
+
+fn check(name: &str, actual: &[u8], reference: &Path, mode: Compare) -> Outcome {
+    let (width, height, expected) = match load_png(reference) {
+        Ok(image) => image,
+        Err(e) => return Outcome::Fail(e)
+    };
+    if (width, height) != (SCREEN_WIDTH, SCREEN_HEIGHT) {
+        return Outcome::Fail(format!("Reference is {}x{}", width, height));
+    }
+    let mismatches = compare(actual, &expected, mode);
+    let count = mismatches.iter().filter(|&&m| m).count();
+    if count == 0 {
+        return Outcome::Pass;
+    }
+    match save_diff(name, actual, &mismatches) {
+        Ok(diff) => Outcome::Fail(format!("{} pixels differ, see {}", count, diff.display())),
+        Err(e) => Outcome::Fail(format!("{} pixels differ. {}", count, e))
+    }
+}
+
+fn run(screenshot: &Screenshot) -> Outcome {
+    if !screenshot.rom.exists() || !screenshot.reference.exists() {
+        return Outcome::Missing;
+    }
+    let name = screenshot.rom.file_stem().unwrap().to_string_lossy().into_owned();
+    let actual = match Cartridge::load(&screenshot.rom.to_string_lossy()).and_then(|c| capture(c, screenshot.frames)) {
+        Ok(actual) => actual,
+        Err(e) => return Outcome::Fail(e)
+    };
+    check(&name, &actual, &screenshot.reference, screenshot.compare)
+}
+
+fn screenshots() -> Vec<Screenshot> {
+    let dir = Path::new(ROM_DIR);
+    let mut screenshots = vec![
+        Screenshot {
+            rom: dir.join("acid2/dmg-acid2.gb"),
+            reference: dir.join("acid2/dmg-acid2.png"),
+            compare: Compare::Palette,
+            frames: 60
+        },
+        Screenshot {
+            rom: dir.join("acid2/cgb-acid2.gbc"),
+            reference: dir.join("acid2/cgb-acid2.png"),
+            compare: Compare::Exact,
+            frames: 60
+        }
+    ];
+
+    // Regression screenshots, taken after a fixed number of frames.
+    if let Ok(entries) = fs::read_dir(dir.join("screenshots")) {
+        let mut roms: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map_or(false, |ext| ext == "gb" || ext == "gbc"))
+            .collect();
+        roms.sort();
+        for rom in roms {
+            let reference = rom.with_extension("png");
+            screenshots.push(Screenshot { rom: rom, reference: reference, compare: Compare::Palette, frames: 300 });
+        }
+    }
+    screenshots
+}
+
+// Check the harness itself against a ROM which draws a single tile and hits a breakpoint.
+#[test]
+fn harness() {
+    let cartridge = assembled("
+    ; The boot ROM leaves the LCD on, so wait for vblank to turn it off before using VRAM.
+VBlank:
+    ldh a, [$FF44]
+    cp 144
+    jr c, VBlank
+    xor a
+    ldh [$FF40], a
+    ld hl, $8010
+    ld a, $FF
+    ld b, 16
+Tile:
+    ld [hl+], a
+    dec b
+    jr nz, Tile
+    ld a, 1
+    ld [$9801], a
+    ld a, $E4
+    ldh [$FF47], a
+    ld a, $91
+    ldh [$FF40], a
+    ld b, b
+Loop:
+    jr Loop");
+    let actual = capture(cartridge, 10).unwrap();
+
+    // The second tile uses colour 3 throughout, and the rest of the background colour 0.
+    let mut expected = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let mut mismatches = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
+    for y in 0..8 {
+        for x in 8..16 {
+            let i = y * SCREEN_WIDTH + x;
+            expected[i * 3..i * 3 + 3].copy_from_slice(&[0x00, 0x00, 0x00]);
+            mismatches[i] = true;
+        }
+    }
+    assert_eq!(compare(&actual, &expected, Compare::Exact), vec![false; SCREEN_WIDTH * SCREEN_HEIGHT]);
+
+    // Different shades still match through the palette, but not exactly.
+    let shaded: Vec<u8> = expected.iter().map(|&v| if v == 0 { 0x10 } else { 0xE0 }).collect();
+    assert_eq!(compare(&actual, &shaded, Compare::Palette), vec![false; SCREEN_WIDTH * SCREEN_HEIGHT]);
+    assert!(compare(&actual, &shaded, Compare::Exact).iter().all(|&m| m));
+
+    // Two of our colours can't both map to the same reference colour.
+    let flat = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    assert_eq!(compare(&actual, &flat, Compare::Palette), mismatches);
+}
+
+#[test]
+fn screenshot_tests() {
+    let screenshots = screenshots();
+    let mut results = Results::new();
+    for screenshot in &screenshots {
+        let path = screenshot.rom.strip_prefix(ROM_DIR).unwrap_or(&screenshot.rom).display().to_string();
+        results.add(&path, &run(screenshot));
+    }
+    results.print();
+    assert!(results.failures == 0, "{} screenshots failed", results.failures);
+}
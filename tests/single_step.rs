@@ -0,0 +1,245 @@
+/*
+    Runs the single step JSON tests for the SM83 (https://github.com/SingleStepTests/sm83). Each
+    file covers one opcode and holds a list of tests, each with an initial CPU and RAM state, the
+    expected final state, and the bus activity for every cycle:
+
+    { "name": "00 0000", "initial": { "pc": 256, "sp": 0, "a": 0, ..., "ime": 0, "ram": [[256, 0]] },
+      "final": { ... }, "cycles": [[256, 0, "r-m"]] }
+
+    The files aren't distributed with the emulator, so put them in roms/tests/sm83/. Each test
+    runs a single instruction against flat RAM, and the registers, RAM and bus activity of each
+    cycle are compared against the expected results.
+*/
+
+extern crate gbc_rs;
+extern crate serde_json;
+
+mod common;
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use gbc_rs::cpu::interpreter::Cpu;
+use gbc_rs::cpu::bus::{Bus, FlatRam, RecordingBus, BusAccess};
+use common::{ROM_DIR, Outcome, Results};
+
+const REGISTERS: [&'static str; 10] = ["a", "f", "b", "c", "d", "e", "h", "l", "sp", "pc"];
+
+fn number(value: &Value, what: &str) -> Result<u16, String> {
+    value.as_u64().map(|n| n as u16).ok_or_else(|| format!("Expected a number for {}", what))
+}
+
+fn register<B: Bus>(cpu: &Cpu<B>, name: &str) -> u16 {
+    match name {
+        "a" => cpu.regs.a as u16,
+        "f" => cpu.regs.f as u16,
+        "b" => cpu.regs.b as u16,
+        "c" => cpu.regs.c as u16,
+        "d" => cpu.regs.d as u16,
+        "e" => cpu.regs.e as u16,
+        "h" => cpu.regs.h as u16,
+        "l" => cpu.regs.l as u16,
+        "sp" => cpu.regs.sp,
+        "pc" => cpu.regs.pc,
+        _ => panic!("Unknown register {}", name)
+    }
+}
+
+fn set_register<B: Bus>(cpu: &mut Cpu<B>, name: &str, value: u16) {
+    match name {
+        "a" => cpu.regs.a = value as u8,
+        "f" => cpu.regs.f = value as u8,
+        "b" => cpu.regs.b = value as u8,
+        "c" => cpu.regs.c = value as u8,
+        "d" => cpu.regs.d = value as u8,
+        "e" => cpu.regs.e = value as u8,
+        "h" => cpu.regs.h = value as u8,
+        "l" => cpu.regs.l = value as u8,
+        "sp" => cpu.regs.sp = value,
+        "pc" => cpu.regs.pc = value,
+        _ => panic!("Unknown register {}", name)
+    }
+}
+
+// The [address, value] pairs of a state's RAM.
+fn ram(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    let entries = state["ram"].as_array().ok_or("Expected a ram array")?;
+    entries.iter().map(|entry| {
+        Ok((number(&entry[0], "a ram address")?, number(&entry[1], "a ram value")? as u8))
+    }).collect()
+}
+
+// A cycle's bus activity, given as [address, value, "r-m"] for a read, [address, value, "-wm"] for a
+// write or null for an internal cycle.
+fn cycle(cycle: &Value) -> Result<Option<BusAccess>, String> {
+    if cycle.is_null() {
+        return Ok(None);
+    }
+    let addr = number(&cycle[0], "a cycle address")?;
+    let kind = cycle[2].as_str().ok_or("Expected a cycle kind")?;
+    // Some internal cycles still put an address on the bus, without a value.
+    let value = match cycle[1].as_u64() {
+        Some(value) => value as u8,
+        None => return Ok(None)
+    };
+    if kind.contains('r') {
+        Ok(Some(BusAccess::Read { addr: addr, value: value }))
+    } else if kind.contains('w') {
+        Ok(Some(BusAccess::Write { addr: addr, value: value }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn describe(access: &Option<BusAccess>) -> String {
+    match *access {
+        Some(BusAccess::Read { addr, value }) => format!("read {:02X} from {:04X}", value, addr),
+        Some(BusAccess::Write { addr, value }) => format!("write {:02X} to {:04X}", value, addr),
+        None => "internal".to_string()
+    }
+}
+
+// Run a single test, returning a description of every difference from the final state.
+fn run_test(test: &Value) -> Result<Vec<String>, String> {
+    let initial = &test["initial"];
+    let expected = &test["final"];
+
+    let mut memory = FlatRam::new();
+    for (addr, value) in ram(initial)? {
+        memory.ram[addr as usize] = value;
+    }
+    let mut cpu = Cpu::new(RecordingBus::new(memory));
+    for name in REGISTERS.iter() {
+        set_register(&mut cpu, name, number(&initial[*name], name)?);
+    }
+    if !initial["ime"].is_null() {
+        cpu.set_interrupts_enabled(number(&initial["ime"], "ime")? != 0);
+    }
+
+    cpu.tick();
+
+    let mut differences = Vec::new();
+    for name in REGISTERS.iter() {
+        let value = number(&expected[*name], name)?;
+        if register(&cpu, name) != value {
+            differences.push(format!("{}: {:02X} != {:02X}", name.to_uppercase(), register(&cpu, name), value));
+        }
+    }
+    if !expected["ime"].is_null() {
+        let ime = number(&expected["ime"], "ime")? != 0;
+        if cpu.interrupts_enabled() != ime {
+            differences.push(format!("IME: {} != {}", cpu.interrupts_enabled() as u8, ime as u8));
+        }
+    }
+    for (addr, value) in ram(expected)? {
+        let actual = cpu.bus().inner.ram[addr as usize];
+        if actual != value {
+            differences.push(format!("[{:04X}]: {:02X} != {:02X}", addr, actual, value));
+        }
+    }
+    let cycles = test["cycles"].as_array().ok_or("Expected a cycles array")?;
+    for (i, (actual, expected)) in cpu.bus().cycles.iter().zip(cycles.iter()).enumerate() {
+        let expected = cycle(expected)?;
+        if *actual != expected {
+            differences.push(format!("Cycle {}: {} != {}", i, describe(actual), describe(&expected)));
+            break;
+        }
+    }
+    if cpu.bus().cycles.len() != cycles.len() {
+        differences.push(format!("Cycles: {} != {}", cpu.bus().cycles.len(), cycles.len()));
+    }
+    for access in &cpu.bus().unclocked {
+        differences.push(format!("Outside a cycle: {}", describe(&Some(*access))));
+    }
+    Ok(differences)
+}
+
+// Run every test for an opcode, returning the number which failed and a description of the first.
+fn run_tests(tests: &Value) -> Result<(usize, Option<String>), String> {
+    let tests = tests.as_array().ok_or("Expected a list of tests")?;
+    let mut failures = 0;
+    let mut first = None;
+    for test in tests {
+        let differences = run_test(test)?;
+        if !differences.is_empty() {
+            failures += 1;
+            if first.is_none() {
+                first = Some(format!("{}: {}", test["name"].as_str().unwrap_or("?"), differences.join(", ")));
+            }
+        }
+    }
+    Ok((failures, first))
+}
+
+fn test_dir() -> PathBuf {
+    Path::new(ROM_DIR).join("sm83")
+}
+
+fn test_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match fs::read_dir(test_dir()) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map_or(false, |ext| ext == "json"))
+            .collect(),
+        Err(_) => Vec::new()
+    };
+    files.sort();
+    files
+}
+
+fn load(path: &Path) -> Result<Value, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+    serde_json::from_reader(file).map_err(|e| format!("Unable to parse {}: {}", path.display(), e))
+}
+
+// Check the runner itself against hand written tests.
+#[test]
+fn harness() {
+    let tests: Value = serde_json::from_str(r#"[
+        { "name": "3e 0000",
+          "initial": { "pc": 256, "sp": 65534, "a": 0, "f": 0, "b": 0, "c": 0, "d": 0, "e": 0, "h": 0, "l": 0, "ime": 0,
+                       "ram": [[256, 62], [257, 66]] },
+          "final": { "pc": 258, "sp": 65534, "a": 66, "f": 0, "b": 0, "c": 0, "d": 0, "e": 0, "h": 0, "l": 0, "ime": 0,
+                     "ram": [[256, 62], [257, 66]] },
+          "cycles": [[256, 62, "r-m"], [257, 66, "r-m"]] },
+        { "name": "e2 0000",
+          "initial": { "pc": 256, "sp": 65534, "a": 7, "f": 0, "b": 0, "c": 128, "d": 0, "e": 0, "h": 0, "l": 0, "ime": 0,
+                       "ram": [[256, 226], [65408, 0]] },
+          "final": { "pc": 257, "sp": 65534, "a": 7, "f": 0, "b": 0, "c": 128, "d": 0, "e": 0, "h": 0, "l": 0, "ime": 0,
+                     "ram": [[256, 226], [65408, 7]] },
+          "cycles": [[256, 226, "r-m"], [65408, 7, "-wm"]] }
+    ]"#).unwrap();
+    assert_eq!(run_tests(&tests).unwrap(), (0, None));
+
+    // Expect the wrong value in A and one cycle too many.
+    let mut wrong = tests[0].clone();
+    wrong["final"]["a"] = Value::from(67);
+    wrong["cycles"].as_array_mut().unwrap().push(Value::Null);
+    assert_eq!(run_test(&wrong).unwrap(), vec!["A: 42 != 43", "Cycles: 2 != 3"]);
+
+    // Expect the write to happen to a different address.
+    let mut wrong = tests[1].clone();
+    wrong["cycles"][1][0] = Value::from(65409);
+    assert_eq!(run_test(&wrong).unwrap(), vec!["Cycle 1: write 07 to FF80 != write 07 to FF81"]);
+}
+
+#[test]
+fn single_step_tests() {
+    let files = test_files();
+    if files.is_empty() {
+        println!("\nNo single step tests found in {}, skipping", test_dir().display());
+        return;
+    }
+    let mut results = Results::failures_only();
+    for path in &files {
+        let opcode = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let outcome = match load(path).and_then(|tests| run_tests(&tests)) {
+            Ok((0, _)) => Outcome::Pass,
+            Ok((failures, first)) => Outcome::Fail(format!("{} failed, first {}", failures, first.unwrap_or_default())),
+            Err(e) => Outcome::Fail(e)
+        };
+        results.add(&opcode, &outcome);
+    }
+    results.print();
+    assert!(results.failures == 0, "{} opcodes failed", results.failures);
+}
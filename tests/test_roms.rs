@@ -0,0 +1,206 @@
+/*
+    Runs CPU accuracy test ROMs headlessly. The ROMs aren't distributed with the emulator, so put
+    them in roms/tests/ using the directory layout below. Missing ROMs are skipped.
+
+    - Blargg's ROMs report their result over the serial port, and also write it to cartridge RAM
+      at 0xA000 along with the signature DE B0 61.
+    - Mooneye's ROMs execute LD B,B when done, with the Fibonacci numbers 3/5/8/13/21/34 in
+      B/C/D/E/H/L on success.
+*/
+
+extern crate gbc_rs;
+
+mod common;
+
+use std::path::Path;
+use gbc_rs::emulator::Emulator;
+use gbc_rs::cartridge::Cartridge;
+use gbc_rs::model::Model;
+use common::{ROM_DIR, Outcome, Results, assembled, is_breakpoint, run_frames};
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Blargg,
+    Mooneye
+}
+
+struct TestRom {
+    path: &'static str,
+    kind: Kind,
+    timeout: u32       // Frames
+}
+
+macro_rules! test_roms {
+    ($($kind: ident $timeout: expr => $path: expr,)+) => {
+        &[$(TestRom { path: $path, kind: Kind::$kind, timeout: $timeout },)+]
+    }
+}
+
+static TEST_ROMS: &'static [TestRom] = test_roms!(
+    Blargg 3600 => "blargg/cpu_instrs/individual/01-special.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/02-interrupts.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/03-op sp,hl.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/04-op r,imm.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/05-op rp.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/06-ld r,r.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/07-jr,jp,call,ret,rst.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/08-misc instrs.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/09-op r,r.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/10-bit ops.gb",
+    Blargg 3600 => "blargg/cpu_instrs/individual/11-op a,(hl).gb",
+    Blargg 600  => "blargg/instr_timing/instr_timing.gb",
+    Blargg 600  => "blargg/mem_timing/individual/01-read_timing.gb",
+    Blargg 600  => "blargg/mem_timing/individual/02-write_timing.gb",
+    Blargg 600  => "blargg/mem_timing/individual/03-modify_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/add_sp_e_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/call_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/call_cc_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/div_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/ei_sequence.gb",
+    Mooneye 600 => "mooneye/acceptance/ei_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/halt_ime0_ei.gb",
+    Mooneye 600 => "mooneye/acceptance/intr_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/jp_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/jp_cc_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/ld_hl_sp_e_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/pop_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/push_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/rapid_di_ei.gb",
+    Mooneye 600 => "mooneye/acceptance/ret_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/ret_cc_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/reti_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/rst_timing.gb",
+    Mooneye 600 => "mooneye/acceptance/bits/reg_f.gb",
+    Mooneye 600 => "mooneye/acceptance/instr/daa.gb",
+    Mooneye 600 => "mooneye/acceptance/timer/div_write.gb",
+    Mooneye 600 => "mooneye/acceptance/timer/tim00.gb",
+    Mooneye 600 => "mooneye/acceptance/timer/tim01.gb",
+    Mooneye 600 => "mooneye/acceptance/timer/tim10.gb",
+    Mooneye 600 => "mooneye/acceptance/timer/tim11.gb",
+    Mooneye 600 => "mooneye/acceptance/timer/tima_reload.gb",
+);
+
+// ROMs which are known to fail, so only report their results. Remove a ROM from here once it
+// passes, so that it can't regress.
+static EXPECTED_FAILURES: &'static [&'static str] = &[
+    // Interrupts aren't dispatched yet.
+    "blargg/cpu_instrs/individual/02-interrupts.gb",
+    "mooneye/acceptance/ei_sequence.gb",
+    "mooneye/acceptance/ei_timing.gb",
+    "mooneye/acceptance/halt_ime0_ei.gb",
+    "mooneye/acceptance/intr_timing.gb",
+    "mooneye/acceptance/rapid_di_ei.gb",
+];
+
+// Blargg's result, from the serial output or the signature in cartridge RAM.
+fn blargg_result(emulator: &Emulator) -> Option<Outcome> {
+    let serial = String::from_utf8_lossy(&emulator.serial_output()).into_owned();
+    if serial.contains("Passed") {
+        return Some(Outcome::Pass);
+    }
+    if serial.contains("Failed") {
+        return Some(Outcome::Fail(serial.trim().replace("\n", " ")));
+    }
+
+    let signature = [emulator.read_u8(0xA001), emulator.read_u8(0xA002), emulator.read_u8(0xA003)];
+    if signature != [0xDE, 0xB0, 0x61] {
+        return None;
+    }
+    match emulator.read_u8(0xA000) {
+        0x80 => None,   // Still running
+        0x00 => Some(Outcome::Pass),
+        code => {
+            let text: String = (0xA004..0xBFFF)
+                .map(|addr| emulator.read_u8(addr))
+                .take_while(|&b| b != 0)
+                .map(|b| b as char)
+                .collect();
+            Some(Outcome::Fail(format!("Result code {}: {}", code, text.trim().replace("\n", " "))))
+        }
+    }
+}
+
+// Mooneye's result, once the LD B,B breakpoint has been hit.
+fn mooneye_result(emulator: &Emulator) -> Outcome {
+    let regs = &emulator.cpu().regs;
+    let values = [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l];
+    if values == [3, 5, 8, 13, 21, 34] {
+        Outcome::Pass
+    } else {
+        Outcome::Fail(format!("Registers {:?}", values))
+    }
+}
+
+fn run(rom: &TestRom) -> Outcome {
+    let path = Path::new(ROM_DIR).join(rom.path);
+    if !path.exists() {
+        return Outcome::Missing;
+    }
+    match Cartridge::load(&path.to_string_lossy()) {
+        Ok(c) => run_cartridge(c, rom.kind, rom.timeout),
+        Err(e) => Outcome::Fail(e)
+    }
+}
+
+fn run_cartridge(cartridge: Cartridge, kind: Kind, timeout: u32) -> Outcome {
+    let mut emulator = common::harness(cartridge, Model::Dmg);
+    run_frames(&mut emulator, timeout, |emulator, events| match kind {
+        Kind::Blargg => blargg_result(emulator),
+        Kind::Mooneye => if events.iter().any(is_breakpoint) { Some(mooneye_result(emulator)) } else { None }
+    })
+}
+
+// Check the harness itself against tiny ROMs which report their results the same way.
+#[test]
+fn harness() {
+    let mooneye = |l| assembled(&format!("
+    ld b, 3
+    ld c, 5
+    ld d, 8
+    ld e, 13
+    ld h, 21
+    ld l, {}
+    ld b, b
+Loop:
+    jr Loop", l));
+    match run_cartridge(mooneye(34), Kind::Mooneye, 1) { Outcome::Pass => {}, _ => panic!("Expected a pass") }
+    match run_cartridge(mooneye(0x42), Kind::Mooneye, 1) { Outcome::Fail(_) => {}, _ => panic!("Expected a failure") }
+
+    let blargg = assembled("
+    ld hl, Message
+Send:
+    ld a, [hl+]
+    and a
+    jr z, Loop
+    ldh [$FF01], a
+    ld a, $81
+    ldh [$FF02], a
+    jr Send
+Loop:
+    jr Loop
+Message:
+    db \"Passed\", 0");
+    match run_cartridge(blargg, Kind::Blargg, 1) { Outcome::Pass => {}, _ => panic!("Expected a pass") }
+
+    let timeout = assembled("Loop:\n    jr Loop");
+    match run_cartridge(timeout, Kind::Blargg, 2) { Outcome::Timeout => {}, _ => panic!("Expected a timeout") }
+}
+
+#[test]
+fn test_roms() {
+    let mut results = Results::new();
+    let mut unexpected = Vec::new();
+    for rom in TEST_ROMS {
+        let outcome = run(rom);
+        if EXPECTED_FAILURES.contains(&rom.path) {
+            results.add_with_note(rom.path, &outcome, "expected to fail");
+            continue;
+        }
+        if let Outcome::Fail(_) | Outcome::Timeout = outcome {
+            unexpected.push(rom.path);
+        }
+        results.add(rom.path, &outcome);
+    }
+    results.print();
+    assert!(unexpected.is_empty(), "Test ROMs failed unexpectedly: {}", unexpected.join(", "));
+}